@@ -0,0 +1,364 @@
+use std::{net::IpAddr, time::Duration};
+
+use clap::{Parser, Subcommand};
+use grapple_frc_msgs::grapple::{lasercan::{LaserCanRangingMode, LaserCanRoi, LaserCanRoiU4, LaserCanTimingBudget}, mitocandria::{MitocandriaChannelStatus, MitocandriaStatusFrame}};
+use grapplefrcdriver::{bridge_api::{enumerate_devices, set_device_id, DeviceApi, DiscoveredDevice}, bridge_config::{BridgeConfig, DEFAULT_TCP_PORT, DEFAULT_WEBSOCKET_PORT}, bridge_protocol::CanFilter, can_bridge::start_can_bridge_with_config, logging::{self, LogLevel}, ws_can_bridge::start_ws_can_bridge_with_config};
+use serde::Serialize;
+
+/// Talk to Grapple devices over SocketCAN, e.g. from a coprocessor with a CAN adapter.
+#[derive(Parser)]
+#[command(name = "grpl", version)]
+struct Args {
+  /// The SocketCAN interface the devices are on, e.g. can0, or vcan0 for testing.
+  #[arg(short, long, default_value = "can0")]
+  interface: String,
+
+  /// Print results as JSON, for scripting.
+  #[arg(long, global = true)]
+  json: bool,
+
+  /// Log what the driver is doing to stderr.
+  #[arg(short, long, global = true)]
+  verbose: bool,
+
+  #[command(subcommand)]
+  command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+  /// List the Grapple devices on the bus.
+  List {
+    /// How long to wait for devices to reply.
+    #[arg(long, default_value_t = 250)]
+    timeout_ms: u64,
+  },
+  /// Change a device's CAN ID.
+  SetId {
+    /// The device's current ID.
+    id: u8,
+    new_id: u8,
+    /// The device's serial number, needed if more than one device has the current ID.
+    #[arg(long)]
+    serial: Option<u32>,
+  },
+  /// Read and configure a LaserCAN.
+  Lasercan {
+    /// The device's CAN ID.
+    id: u8,
+    #[command(subcommand)]
+    command: LaserCanCommand,
+  },
+  /// Read and control a MitoCANdria.
+  Mitocandria {
+    /// The device's CAN ID.
+    id: u8,
+    #[command(subcommand)]
+    command: MitoCANdriaCommand,
+  },
+  /// Run the TCP and WebSocket CAN bridges on this interface, until interrupted.
+  Bridge {
+    #[arg(long, default_value_t = DEFAULT_TCP_PORT)]
+    tcp_port: u16,
+    #[arg(long, default_value_t = DEFAULT_WEBSOCKET_PORT)]
+    websocket_port: u16,
+    /// Don't run the TCP bridge (used by GrappleHook).
+    #[arg(long)]
+    no_tcp: bool,
+    /// Don't run the WebSocket bridge (and its REST API and dashboard).
+    #[arg(long)]
+    no_websocket: bool,
+    /// The address to listen on, to restrict the bridges to a single network interface.
+    #[arg(long)]
+    bind: Option<IpAddr>,
+    /// Require clients to authenticate with this token.
+    #[arg(long)]
+    token: Option<String>,
+    /// Refuse all frames sent by clients, so they can only listen to the bus.
+    #[arg(long)]
+    read_only: bool,
+    /// Only let clients send frames matching these filters, as comma-separated id/mask pairs.
+    #[arg(long)]
+    allow: Option<String>,
+  },
+}
+
+#[derive(Subcommand)]
+enum LaserCanCommand {
+  /// Print the latest measurement, continuously until interrupted.
+  Read {
+    /// How often to print the measurement.
+    #[arg(long, default_value_t = 100)]
+    interval_ms: u64,
+    /// Stop after this many measurements.
+    #[arg(long)]
+    count: Option<usize>,
+  },
+  /// Change the region of interest, timing budget and/or ranging mode.
+  Set {
+    /// The region of interest, as x,y,w,h.
+    #[arg(long, value_parser = parse_roi)]
+    roi: Option<LaserCanRoi>,
+    /// The timing budget in milliseconds: 20, 33, 50 or 100.
+    #[arg(long, value_parser = parse_timing_budget)]
+    budget: Option<LaserCanTimingBudget>,
+    /// The ranging mode: short or long.
+    #[arg(long, value_parser = parse_range)]
+    range: Option<LaserCanRangingMode>,
+  },
+}
+
+#[derive(Subcommand)]
+enum MitoCANdriaCommand {
+  /// Print the status of each channel.
+  Status,
+  /// Turn a channel on.
+  Enable { channel: u8 },
+  /// Turn a channel off.
+  Disable { channel: u8 },
+  /// Turn a channel on if it's off, or off if it's on.
+  Toggle { channel: u8 },
+  /// Set the voltage of an adjustable channel.
+  SetVoltage { channel: u8, voltage: f64 },
+}
+
+fn parse_roi(s: &str) -> Result<LaserCanRoi, String> {
+  let parts = s.split(',').map(|p| p.trim().parse::<u8>()).collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?;
+  match parts[..] {
+    [x, y, w, h] => Ok(LaserCanRoi { x: LaserCanRoiU4(x), y: LaserCanRoiU4(y), w: LaserCanRoiU4(w), h: LaserCanRoiU4(h) }),
+    _ => Err("Expected x,y,w,h".to_owned()),
+  }
+}
+
+fn parse_timing_budget(s: &str) -> Result<LaserCanTimingBudget, String> {
+  match s.trim_end_matches("ms") {
+    "20" => Ok(LaserCanTimingBudget::TB20ms),
+    "33" => Ok(LaserCanTimingBudget::TB33ms),
+    "50" => Ok(LaserCanTimingBudget::TB50ms),
+    "100" => Ok(LaserCanTimingBudget::TB100ms),
+    _ => Err("Expected 20, 33, 50 or 100".to_owned()),
+  }
+}
+
+fn parse_range(s: &str) -> Result<LaserCanRangingMode, String> {
+  match s.to_ascii_lowercase().as_str() {
+    "short" => Ok(LaserCanRangingMode::Short),
+    "long" => Ok(LaserCanRangingMode::Long),
+    _ => Err("Expected short or long".to_owned()),
+  }
+}
+
+#[cfg(target_os = "linux")]
+fn open_bus(interface: &str) -> anyhow::Result<()> {
+  use std::sync::Arc;
+  use grapplefrcdriver::{can::set_bus, socketcan::SocketCanBus};
+
+  set_bus(Some(Arc::new(SocketCanBus::open(interface)?)));
+  Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_bus(_interface: &str) -> anyhow::Result<()> {
+  anyhow::bail!("SocketCAN is only available on Linux")
+}
+
+fn print_json<T: Serialize>(value: &T) -> anyhow::Result<()> {
+  println!("{}", serde_json::to_string(value)?);
+  Ok(())
+}
+
+fn print_devices(devices: &[DiscoveredDevice]) {
+  if devices.is_empty() {
+    println!("No devices found");
+    return;
+  }
+  println!("{:<4} {:<12} {:<12} {:<10} NAME", "ID", "MODEL", "SERIAL", "VERSION");
+  for device in devices {
+    println!(
+      "{:<4} {:<12} {:<12} {:<10} {}{}",
+      device.device_id,
+      format!("{:?}", device.model),
+      device.serial,
+      device.version,
+      device.name,
+      if device.is_dfu { " (bootloader)" } else { "" }
+    );
+  }
+}
+
+fn print_mitocandria_status(frame: &MitocandriaStatusFrame) {
+  for (channel, status) in frame.channels.iter().enumerate() {
+    match status {
+      MitocandriaChannelStatus::NonSwitchable { current } => {
+        println!("{}: always on, {:.3} A", channel, *current as f64 / 1000.0)
+      },
+      MitocandriaChannelStatus::Switchable { enabled, current } => {
+        println!("{}: {}, {:.3} A", channel, if *enabled { "on" } else { "off" }, *current as f64 / 1000.0)
+      },
+      MitocandriaChannelStatus::Adjustable { enabled, voltage, voltage_setpoint, current } => println!(
+        "{}: {}, {:.3} V (set to {:.3} V), {:.3} A",
+        channel,
+        if *enabled { "on" } else { "off" },
+        *voltage as f64 / 1000.0,
+        *voltage_setpoint as f64 / 1000.0,
+        *current as f64 / 1000.0
+      ),
+    }
+  }
+}
+
+fn change_id(id: u8, new_id: u8, serial: Option<u32>) -> anyhow::Result<()> {
+  let devices: Vec<_> = enumerate_devices(Duration::from_millis(250))?.into_iter()
+    .filter(|d| d.device_id == id && serial.is_none_or(|s| s == d.serial))
+    .collect();
+
+  let device = match &devices[..] {
+    [] => anyhow::bail!("No device with ID {}", id),
+    [device] => device,
+    _ => anyhow::bail!(
+      "More than one device has ID {}, pick one with --serial: {}",
+      id,
+      devices.iter().map(|d| d.serial.to_string()).collect::<Vec<_>>().join(", ")
+    ),
+  };
+
+  set_device_id(device.serial, new_id)?;
+
+  // Check the device has taken the new ID
+  std::thread::sleep(Duration::from_millis(100));
+  let confirmed = enumerate_devices(Duration::from_millis(250))?.into_iter().any(|d| d.serial == device.serial && d.device_id == new_id);
+  if !confirmed {
+    anyhow::bail!("{} (serial {}) didn't confirm its new ID. Is its firmware up to date?", device.name, device.serial);
+  }
+  println!("{} (serial {}) is now ID {}", device.name, device.serial, new_id);
+  Ok(())
+}
+
+fn lasercan(api: &DeviceApi, id: u8, command: LaserCanCommand, json: bool) -> anyhow::Result<()> {
+  match command {
+    LaserCanCommand::Read { interval_ms, count } => {
+      let mut n = 0;
+      while count.is_none_or(|count| n < count) {
+        // Wait between samples, but not after the last one
+        if n > 0 {
+          std::thread::sleep(Duration::from_millis(interval_ms));
+        }
+        match api.lasercan_measurement(id) {
+          Ok(measurement) if json => print_json(&measurement)?,
+          Ok(measurement) => println!(
+            "{} mm (status {}, ambient {}, {:?}, {:?})",
+            measurement.distance_mm, measurement.status, measurement.ambient, measurement.mode, measurement.budget
+          ),
+          Err(e) => eprintln!("{}", e),
+        }
+        n += 1;
+      }
+    },
+    LaserCanCommand::Set { roi, budget, range } => {
+      if roi.is_none() && budget.is_none() && range.is_none() {
+        anyhow::bail!("Nothing to set, give at least one of --roi, --budget or --range");
+      }
+      if let Some(roi) = roi {
+        api.set_lasercan_roi(id, roi)?;
+      }
+      if let Some(budget) = budget {
+        api.set_lasercan_timing_budget(id, budget)?;
+      }
+      if let Some(range) = range {
+        api.set_lasercan_range(id, range)?;
+      }
+    },
+  }
+  Ok(())
+}
+
+fn mitocandria(api: &DeviceApi, id: u8, command: MitoCANdriaCommand, json: bool) -> anyhow::Result<()> {
+  match command {
+    MitoCANdriaCommand::Status => {
+      let status = api.mitocandria_status(id)?;
+      match json {
+        true => print_json(&status)?,
+        false => print_mitocandria_status(&status),
+      }
+    },
+    MitoCANdriaCommand::Enable { channel } => api.set_mitocandria_enabled(id, channel, true)?,
+    MitoCANdriaCommand::Disable { channel } => api.set_mitocandria_enabled(id, channel, false)?,
+    MitoCANdriaCommand::Toggle { channel } => {
+      let enabled = match api.mitocandria_status(id)?.channels.get(channel as usize) {
+        Some(MitocandriaChannelStatus::Switchable { enabled, .. } | MitocandriaChannelStatus::Adjustable { enabled, .. }) => *enabled,
+        Some(MitocandriaChannelStatus::NonSwitchable { .. }) => anyhow::bail!("Channel {} can't be switched", channel),
+        None => anyhow::bail!("Invalid channel {}", channel),
+      };
+      api.set_mitocandria_enabled(id, channel, !enabled)?;
+      println!("Channel {} is now {}", channel, if enabled { "off" } else { "on" });
+    },
+    MitoCANdriaCommand::SetVoltage { channel, voltage } => api.set_mitocandria_voltage(id, channel, voltage)?,
+  }
+  Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn bridge(tcp_port: u16, websocket_port: u16, no_tcp: bool, no_websocket: bool, bind: Option<IpAddr>, token: Option<String>, read_only: bool, allow: Option<String>) -> anyhow::Result<()> {
+  let allow = allow.map(|a| CanFilter::parse_list(&a)).transpose()?.unwrap_or_default();
+  let configure = |mut config: BridgeConfig| {
+    if let Some(bind) = bind {
+      config.bind_address = bind;
+    }
+    config.auth_token = token.clone();
+    config.read_only = read_only;
+    config.transmit_allow_list = allow.clone();
+    config
+  };
+
+  let mut handles = vec![];
+  if !no_tcp {
+    handles.push(("TCP", start_can_bridge_with_config(configure(BridgeConfig { port: tcp_port, ..BridgeConfig::tcp() }))));
+  }
+  if !no_websocket {
+    handles.push(("WebSocket", start_ws_can_bridge_with_config(configure(BridgeConfig { port: websocket_port, ..BridgeConfig::websocket() }))));
+  }
+  if handles.is_empty() {
+    anyhow::bail!("Both bridges are disabled, there's nothing to run");
+  }
+
+  // Run until interrupted, or until a bridge stops (e.g. its port is in use)
+  loop {
+    std::thread::sleep(Duration::from_millis(250));
+    if let Some((name, handle)) = handles.iter().find(|(_, h)| !h.is_running()) {
+      anyhow::bail!("The {} bridge stopped: {}", name, handle.last_error().unwrap_or_default());
+    }
+  }
+}
+
+fn main() -> anyhow::Result<()> {
+  let args = Args::parse();
+
+  // Keep stdout for results
+  logging::init();
+  logging::set_sink(Some(Box::new(|level, target, message| eprintln!("[{:?}] {}: {}", level, target, message))));
+  logging::set_level(match (args.verbose, &args.command) {
+    (true, _) => LogLevel::Debug,
+    (false, Command::Bridge { .. }) => LogLevel::Info,
+    (false, _) => LogLevel::Warn,
+  });
+
+  open_bus(&args.interface)?;
+
+  match args.command {
+    Command::List { timeout_ms } => {
+      let devices = enumerate_devices(Duration::from_millis(timeout_ms))?;
+      match args.json {
+        true => print_json(&devices)?,
+        false => print_devices(&devices),
+      }
+    },
+    Command::SetId { id, new_id, serial } => change_id(id, new_id, serial)?,
+    Command::Lasercan { id, command } => lasercan(&DeviceApi::new(&BridgeConfig::websocket()), id, command, args.json)?,
+    Command::Mitocandria { id, command } => mitocandria(&DeviceApi::new(&BridgeConfig::websocket()), id, command, args.json)?,
+    Command::Bridge { tcp_port, websocket_port, no_tcp, no_websocket, bind, token, read_only, allow } => {
+      bridge(tcp_port, websocket_port, no_tcp, no_websocket, bind, token, read_only, allow)?
+    },
+  }
+
+  Ok(())
+}
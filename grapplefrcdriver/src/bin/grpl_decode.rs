@@ -0,0 +1,233 @@
+use std::{io::Write, path::PathBuf};
+
+use clap::Parser;
+use grapple_frc_msgs::MessageId;
+use grapplefrcdriver::{bridge_json::{JsonCodec, JsonMessage}, can_recorder::{read_recording, RecordingEntry}};
+
+/// Decode Grapple CAN traffic from candump logs (`candump -L`) or recordings made by the CAN recorder.
+#[derive(Parser)]
+#[command(name = "grpl-decode", version)]
+struct Args {
+  /// The files to decode, in order. Fragmented messages are reassembled across files.
+  #[arg(required = true)]
+  files: Vec<PathBuf>,
+
+  /// Only show messages for this device type, e.g. 6 for LaserCAN or 8 for MitoCANdria.
+  #[arg(long, value_parser = parse_u8)]
+  device_type: Option<u8>,
+
+  /// Only show messages for this device ID.
+  #[arg(long, value_parser = parse_u8)]
+  device_id: Option<u8>,
+
+  /// Don't show frames that aren't from Grapple devices (or couldn't be decoded).
+  #[arg(long)]
+  no_raw: bool,
+
+  /// Print one JSON object per line, in the same format as the WebSocket bridge's JSON mode.
+  #[arg(long)]
+  json: bool,
+}
+
+// Accepts decimal or 0x-prefixed hex
+fn parse_u8(s: &str) -> Result<u8, String> {
+  match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+    Some(hex) => u8::from_str_radix(hex, 16),
+    None => s.parse(),
+  }.map_err(|e| e.to_string())
+}
+
+fn format_timestamp(timestamp_us: u64) -> String {
+  format!("({}.{:06})", timestamp_us / 1_000_000, timestamp_us % 1_000_000)
+}
+
+impl Args {
+  fn matches(&self, message: &JsonMessage) -> bool {
+    let (device_type, device_id) = match message {
+      JsonMessage::Device { id, .. } => (id.device_type, id.device_id),
+      JsonMessage::Raw { id, .. } => {
+        if self.no_raw {
+          return false;
+        }
+        let id: MessageId = (*id).into();
+        (id.device_type, id.device_id)
+      },
+      JsonMessage::CommandError(_) => return false,
+    };
+    self.device_type.is_none_or(|t| t == device_type) && self.device_id.is_none_or(|i| i == device_id)
+  }
+
+  fn format(&self, message: &JsonMessage) -> Option<String> {
+    if self.json {
+      return serde_json::to_string(message).ok();
+    }
+
+    match message {
+      JsonMessage::Device { id, timestamp_us, message, .. } => Some(format!(
+        "{} type={} id={} api={}.{}{}{} {:?}",
+        format_timestamp(timestamp_us.unwrap_or(0)),
+        id.device_type,
+        id.device_id,
+        id.api_class,
+        id.api_index,
+        if id.ack_flag { " ack" } else { "" },
+        if id.fragment_flag { " fragmented" } else { "" },
+        message
+      )),
+      JsonMessage::Raw { id, timestamp_us, data, .. } => Some(format!(
+        "{} raw {:08X}#{}",
+        format_timestamp(timestamp_us.unwrap_or(0)),
+        id,
+        data.iter().map(|b| format!("{:02X}", b)).collect::<String>()
+      )),
+      JsonMessage::CommandError(_) => None,
+    }
+  }
+
+  fn format_overflow(&self, timestamp_us: u64, overflows: u32) -> String {
+    if self.json {
+      serde_json::json!({ "type": "Overflow", "data": { "timestamp_us": timestamp_us, "overflows": overflows } }).to_string()
+    } else {
+      format!("{} frames lost, the recorder didn't keep up (overflow {})", format_timestamp(timestamp_us), overflows)
+    }
+  }
+
+  // Decode every file in turn, writing a line for each message that passes the filters.
+  fn decode(&self, out: &mut impl Write) -> anyhow::Result<()> {
+    let mut codec = JsonCodec::new();
+    // The reassembler works in milliseconds since the first frame, as Unix milliseconds don't fit
+    let mut start_us = None;
+
+    for path in &self.files {
+      let entries = read_recording(path).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+
+      for entry in entries {
+        let line = match entry {
+          RecordingEntry::Frame(frame) => {
+            let start_us = *start_us.get_or_insert(frame.timestamp_us);
+            let timestamp = (frame.timestamp_us.saturating_sub(start_us) / 1000) as u32;
+            codec.decode_frame(frame.id, timestamp, Some(frame.timestamp_us), &frame.data)
+              .filter(|message| self.matches(message))
+              .and_then(|message| self.format(&message))
+          },
+          RecordingEntry::Overflow { timestamp_us, overflows } => Some(self.format_overflow(timestamp_us, overflows)),
+        };
+        if let Some(line) = line {
+          writeln!(out, "{}", line)?;
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+fn main() -> anyhow::Result<()> {
+  Args::parse().decode(&mut std::io::stdout().lock())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::{Path, PathBuf};
+
+  use clap::Parser;
+  use grapple_frc_msgs::grapple::{lasercan::LaserCanMessage, mitocandria::{MitocandriaChannelStatus, MitocandriaMessage, MitocandriaStatusFrame}, GrappleDeviceMessage, Request};
+  use grapplefrcdriver::bridge_json::JsonCodec;
+
+  use super::Args;
+
+  // A REV frame, which isn't from a Grapple device
+  const RAW_ID: u32 = 0x0205_1234;
+
+  // The frames a device would send for `message`
+  fn encode(device_id: u8, message: GrappleDeviceMessage) -> Vec<(u32, Vec<u8>)> {
+    let command = serde_json::json!({ "type": "SendDevice", "data": { "device_id": device_id, "message": message } });
+    JsonCodec::new().encode_command(command.to_string().as_bytes()).unwrap()
+  }
+
+  fn write_candump(path: &Path, frames: &[(u32, Vec<u8>)]) {
+    let lines: String = frames.iter().enumerate().map(|(i, (id, data))| {
+      let data: String = data.iter().map(|b| format!("{:02X}", b)).collect();
+      format!("(1718000000.{:06}) can0 {:08X}#{}\n", i * 1000, id, data)
+    }).collect();
+    std::fs::write(path, lines).unwrap();
+  }
+
+  // A LaserCAN request and a raw frame in the first file, and a MitoCANdria status frame split
+  // across both.
+  fn recording(name: &str) -> (PathBuf, PathBuf) {
+    let directory = std::env::temp_dir().join(format!("grapplefrc-decode-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+
+    let lasercan = encode(1, GrappleDeviceMessage::DistanceSensor(LaserCanMessage::SetLedThreshold(Request::Request(100))));
+    let status = encode(2, GrappleDeviceMessage::PowerDistributionModule(MitocandriaMessage::StatusFrame(MitocandriaStatusFrame {
+      channels: [MitocandriaChannelStatus::NonSwitchable { current: 1000 }; 5],
+    })));
+    assert!(status.len() > 1);
+    let (first_half, second_half) = status.split_at(status.len() / 2);
+
+    let first = directory.join("first.log");
+    let second = directory.join("second.log");
+    write_candump(&first, &[lasercan, vec![(RAW_ID, vec![1, 2, 3])], first_half.to_vec()].concat());
+    write_candump(&second, second_half);
+    (first, second)
+  }
+
+  fn decode(flags: &[&str], files: &[&Path]) -> Vec<String> {
+    let args = Args::parse_from(["grpl-decode"].into_iter().map(String::from)
+      .chain(flags.iter().map(|f| f.to_string()))
+      .chain(files.iter().map(|f| f.display().to_string())));
+    let mut out = vec![];
+    args.decode(&mut out).unwrap();
+    String::from_utf8(out).unwrap().lines().map(String::from).collect()
+  }
+
+  #[test]
+  fn decodes_everything_by_default() {
+    let (first, second) = recording("all");
+    let lines = decode(&[], &[&first, &second]);
+    assert_eq!(lines.len(), 3, "{:#?}", lines);
+    assert!(lines[0].contains("type=6 id=1") && lines[0].contains("SetLedThreshold"));
+    assert!(lines[1].ends_with("raw 02051234#010203"));
+    assert!(lines[2].contains("type=8 id=2") && lines[2].contains("StatusFrame"));
+  }
+
+  #[test]
+  fn reassembles_across_files() {
+    let (first, second) = recording("reassembly");
+    assert!(decode(&[], &[&first]).iter().all(|l| !l.contains("StatusFrame")));
+    assert!(decode(&[], &[&second]).iter().all(|l| !l.contains("StatusFrame")));
+    assert!(decode(&[], &[&first, &second]).iter().any(|l| l.contains("StatusFrame")));
+  }
+
+  #[test]
+  fn device_type_filter() {
+    let (first, second) = recording("device-type");
+    let lines = decode(&["--device-type", "0x6"], &[&first, &second]);
+    assert_eq!(lines.len(), 1, "{:#?}", lines);
+    assert!(lines[0].contains("SetLedThreshold"));
+  }
+
+  #[test]
+  fn device_id_filter() {
+    let (first, second) = recording("device-id");
+    let lines = decode(&["--device-id", "2"], &[&first, &second]);
+    assert_eq!(lines.len(), 1, "{:#?}", lines);
+    assert!(lines[0].contains("StatusFrame"));
+  }
+
+  #[test]
+  fn no_raw() {
+    let (first, second) = recording("no-raw");
+    let lines = decode(&["--no-raw"], &[&first, &second]);
+    assert_eq!(lines.len(), 2, "{:#?}", lines);
+    assert!(lines.iter().all(|l| !l.contains("raw")));
+  }
+
+  #[test]
+  fn json_output() {
+    let (first, second) = recording("json");
+    let lines = decode(&["--json", "--no-raw"], &[&first, &second]);
+    let types: Vec<serde_json::Value> = lines.iter().map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap()["type"].clone()).collect();
+    assert_eq!(types, vec!["Device", "Device"]);
+  }
+}
@@ -0,0 +1,373 @@
+use std::{collections::{BTreeMap, HashMap}, sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+use grapple_frc_msgs::{binmarshal::{BitView, Demarshal}, grapple::{device_info::{GrappleDeviceInfo, GrappleModelId}, errors::GrappleError, fragments::FragmentReassembler, lasercan::{LaserCanMeasurement, LaserCanRangingMode, LaserCanRoi, LaserCanTimingBudget}, mitocandria::MitocandriaStatusFrame, GrappleBroadcastMessage, GrappleDeviceMessage, MaybeFragment, DEVICE_TYPE_DISTANCE_SENSOR, DEVICE_TYPE_POWER_DISTRIBUTION_MODULE, MANUFACTURER_GRAPPLE}, MessageId, DEVICE_ID_BROADCAST, DEVICE_TYPE_BROADCAST};
+use serde::{de::DeserializeOwned, Serialize};
+use warp::{filters::BoxedFilter, http::StatusCode, reply::Response, Filter, Reply};
+
+use crate::{bridge_config::BridgeConfig, bridge_protocol::CanFilter, bridge_session::tokens_match, can::{bus, GrappleCanDriver}, lasercan::LaserCAN, mitocandria::MitoCANdria, HAL_CANStreamMessage};
+
+/// How long to listen for devices to reply to an enumerate request.
+const ENUMERATE_TIMEOUT: Duration = Duration::from_millis(250);
+const ENUMERATE_SESSION_DEPTH: u32 = 64;
+/// How long to wait for a status frame from a device we haven't heard from yet.
+const STATUS_TIMEOUT: Duration = Duration::from_millis(250);
+const MAX_BODY_LENGTH: u64 = 1024;
+
+/// Returned in the body of any failed API request, alongside an appropriate HTTP status.
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiError {
+  #[serde(skip)]
+  status: StatusCode,
+  /// The kind of error, e.g. the GrappleError variant for errors returned by the device.
+  error: &'static str,
+  message: String,
+}
+
+impl ApiError {
+  fn new(status: StatusCode, error: &'static str, message: impl Into<String>) -> Self {
+    Self { status, error, message: message.into() }
+  }
+
+  fn offline(device: &str, id: u8) -> Self {
+    Self::new(StatusCode::SERVICE_UNAVAILABLE, "Offline", format!("No status received from {} #{}. Is it plugged in?", device, id))
+  }
+}
+
+impl From<GrappleError<'_>> for ApiError {
+  fn from(e: GrappleError<'_>) -> Self {
+    match e {
+      GrappleError::ParameterOutOfBounds(msg) => Self::new(StatusCode::BAD_REQUEST, "ParameterOutOfBounds", msg.into_owned()),
+      GrappleError::FailedAssertion(msg) => Self::new(StatusCode::CONFLICT, "FailedAssertion", msg.into_owned()),
+      GrappleError::TimedOut(msg) => Self::new(StatusCode::GATEWAY_TIMEOUT, "TimedOut", msg.into_owned()),
+      GrappleError::Generic(msg) => Self::new(StatusCode::INTERNAL_SERVER_ERROR, "Generic", msg.into_owned()),
+    }
+  }
+}
+
+impl From<anyhow::Error> for ApiError {
+  fn from(e: anyhow::Error) -> Self {
+    Self::new(StatusCode::INTERNAL_SERVER_ERROR, "Generic", e.to_string())
+  }
+}
+
+impl std::fmt::Display for ApiError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}: {}", self.error, self.message)
+  }
+}
+
+impl std::error::Error for ApiError { }
+
+impl Reply for ApiError {
+  fn into_response(self) -> Response {
+    warp::reply::with_status(warp::reply::json(&self), self.status).into_response()
+  }
+}
+
+type ApiResult<T> = Result<T, ApiError>;
+
+/// Check an `Authorization: Bearer <token>` header against the bridge's token, if it requires one.
+pub(crate) fn authorize_bearer(auth_token: Option<&str>, authorization: Option<&str>) -> ApiResult<()> {
+  match auth_token {
+    None => Ok(()),
+    Some(expected) => match authorization.and_then(|a| a.strip_prefix("Bearer ")) {
+      Some(token) if tokens_match(expected, token.trim()) => Ok(()),
+      _ => Err(ApiError::new(StatusCode::UNAUTHORIZED, "Unauthorized", "A valid bearer token is required")),
+    }
+  }
+}
+
+/// A Grapple device that replied to an enumerate request.
+#[derive(Debug, Clone, Serialize)]
+pub struct DiscoveredDevice {
+  pub device_id: u8,
+  pub model: GrappleModelId,
+  pub serial: u32,
+  pub name: String,
+  pub version: String,
+  pub is_dfu: bool,
+}
+
+/// Ask every Grapple device on the bus to identify itself, collecting the replies for `timeout`.
+pub fn enumerate_devices(timeout: Duration) -> anyhow::Result<Vec<DiscoveredDevice>> {
+  // Open the session before asking, so no replies are missed
+  let filter = CanFilter::device_type(DEVICE_TYPE_BROADCAST);
+  let mut stream = bus().open_stream(filter, ENUMERATE_SESSION_DEPTH)?;
+  let mut buffer = vec![HAL_CANStreamMessage { ..Default::default() }; ENUMERATE_SESSION_DEPTH as usize];
+
+  GrappleCanDriver::new(DEVICE_ID_BROADCAST, DEVICE_TYPE_BROADCAST)
+    .send(GrappleDeviceMessage::Broadcast(GrappleBroadcastMessage::DeviceInfo(GrappleDeviceInfo::EnumerateRequest)))?;
+
+  let (mut reassembler, _) = FragmentReassembler::new(1000, 8).split();
+  // Keyed by serial, as two devices can share an ID
+  let mut devices = BTreeMap::new();
+  let started = Instant::now();
+
+  while started.elapsed() < timeout {
+    std::thread::sleep(Duration::from_millis(10));
+
+    let (n_read, _) = stream.read(&mut buffer);
+    for msg in &buffer[0..n_read] {
+      let id: MessageId = msg.messageID.into();
+      if id.manufacturer != MANUFACTURER_GRAPPLE {
+        continue;
+      }
+
+      let len = (msg.dataSize as usize).min(8);
+      let mut data = [0u8; 8];
+      data[0..len].copy_from_slice(&msg.data[0..len]);
+      let mut storage = Vec::with_capacity(128);
+
+      let decoded = MaybeFragment::read(&mut BitView::new(&data), id.into())
+        .and_then(|m| reassembler.defragment(msg.timeStamp as i64, &id, m, &mut storage));
+
+      if let Ok(Some((id, GrappleDeviceMessage::Broadcast(GrappleBroadcastMessage::DeviceInfo(GrappleDeviceInfo::EnumerateResponse { model_id, serial, is_dfu, version, name, .. }))))) = decoded {
+        devices.insert(serial, DiscoveredDevice {
+          device_id: id.device_id,
+          model: model_id,
+          serial,
+          name: name.into_owned(),
+          version: version.into_owned(),
+          is_dfu,
+        });
+      }
+    }
+  }
+
+  Ok(devices.into_values().collect())
+}
+
+/// Change the CAN ID of the device with the given serial number (see enumerate_devices). The device
+/// keeps the new ID across reboots.
+pub fn set_device_id(serial: u32, new_id: u8) -> anyhow::Result<()> {
+  check_device_id(new_id)?;
+  GrappleCanDriver::new(DEVICE_ID_BROADCAST, DEVICE_TYPE_BROADCAST)
+    .send(GrappleDeviceMessage::Broadcast(GrappleBroadcastMessage::DeviceInfo(GrappleDeviceInfo::SetId { serial, new_id })))?;
+  Ok(())
+}
+
+// Call f until it returns something, or the timeout passes.
+fn wait_for<T>(timeout: Duration, mut f: impl FnMut() -> Option<T>) -> Option<T> {
+  let started = Instant::now();
+  loop {
+    if let Some(value) = f() {
+      return Some(value);
+    }
+    if started.elapsed() > timeout {
+      return None;
+    }
+    std::thread::sleep(Duration::from_millis(5));
+  }
+}
+
+// Drivers by device ID. Each has its own lock, so a slow request to one device doesn't hold up
+// requests to the others.
+type Devices<D> = Mutex<HashMap<u8, Arc<Mutex<D>>>>;
+
+// The driver for the device, creating it if this is the first request for it.
+fn device<D>(devices: &Devices<D>, id: u8, new: impl FnOnce(u8) -> D) -> Arc<Mutex<D>> {
+  let mut devices = devices.lock().unwrap_or_else(|e| e.into_inner());
+  devices.entry(id).or_insert_with(|| Arc::new(Mutex::new(new(id)))).clone()
+}
+
+/// High-level device operations for the REST API and the grpl CLI. Drivers are kept between
+/// requests, so status frames and fragments aren't lost.
+pub struct DeviceApi {
+  auth_token: Option<String>,
+  read_only: bool,
+  transmit_allow_list: Vec<CanFilter>,
+  lasercans: Devices<LaserCAN>,
+  mitocandrias: Devices<MitoCANdria>,
+}
+
+impl DeviceApi {
+  pub fn new(config: &BridgeConfig) -> Self {
+    Self {
+      auth_token: config.auth_token.clone(),
+      read_only: config.read_only,
+      transmit_allow_list: config.transmit_allow_list.clone(),
+      lasercans: Mutex::new(HashMap::new()),
+      mitocandrias: Mutex::new(HashMap::new()),
+    }
+  }
+
+  /// Check the `Authorization: Bearer <token>` header, if the bridge requires a token.
+  fn authorize(&self, authorization: Option<&str>) -> ApiResult<()> {
+    authorize_bearer(self.auth_token.as_deref(), authorization)
+  }
+
+  /// As authorize, and also check the bridge is allowed to send to the device.
+  fn authorize_write(&self, authorization: Option<&str>, device_type: u8, device_id: u8) -> ApiResult<()> {
+    self.authorize(authorization)?;
+    let id: u32 = MessageId { device_type, manufacturer: MANUFACTURER_GRAPPLE, api_class: 0, api_index: 0, device_id }.into();
+    if self.read_only || !CanFilter::any_match(&self.transmit_allow_list, id) {
+      return Err(ApiError::new(StatusCode::FORBIDDEN, "NotAllowed", "This bridge isn't allowed to send to that device"));
+    }
+    Ok(())
+  }
+
+  fn with_lasercan<T>(&self, id: u8, f: impl FnOnce(&mut LaserCAN) -> ApiResult<T>) -> ApiResult<T> {
+    check_device_id(id)?;
+    let lasercan = device(&self.lasercans, id, LaserCAN::new);
+    let mut lasercan = lasercan.lock().unwrap_or_else(|e| e.into_inner());
+    f(&mut lasercan)
+  }
+
+  fn with_mitocandria<T>(&self, id: u8, f: impl FnOnce(&mut MitoCANdria) -> ApiResult<T>) -> ApiResult<T> {
+    check_device_id(id)?;
+    let mitocandria = device(&self.mitocandrias, id, MitoCANdria::new);
+    let mut mitocandria = mitocandria.lock().unwrap_or_else(|e| e.into_inner());
+    f(&mut mitocandria)
+  }
+
+  pub fn lasercan_measurement(&self, id: u8) -> ApiResult<LaserCanMeasurement> {
+    self.with_lasercan(id, |lc| wait_for(STATUS_TIMEOUT, || lc.get_measurement()).ok_or_else(|| ApiError::offline("LaserCAN", id)))
+  }
+
+  pub fn set_lasercan_roi(&self, id: u8, roi: LaserCanRoi) -> ApiResult<()> {
+    self.with_lasercan(id, |lc| Ok(lc.set_roi(roi)?))
+  }
+
+  pub fn set_lasercan_timing_budget(&self, id: u8, budget: LaserCanTimingBudget) -> ApiResult<()> {
+    self.with_lasercan(id, |lc| Ok(lc.set_timing_budget(budget)?))
+  }
+
+  pub fn set_lasercan_range(&self, id: u8, mode: LaserCanRangingMode) -> ApiResult<()> {
+    self.with_lasercan(id, |lc| Ok(lc.set_range(mode)?))
+  }
+
+  pub fn mitocandria_status(&self, id: u8) -> ApiResult<MitocandriaStatusFrame> {
+    self.with_mitocandria(id, |mc| wait_for(STATUS_TIMEOUT, || mc.get_status()).ok_or_else(|| ApiError::offline("MitoCANdria", id)))
+  }
+
+  pub fn set_mitocandria_enabled(&self, id: u8, channel: u8, enabled: bool) -> ApiResult<()> {
+    self.with_mitocandria(id, |mc| {
+      // set_enabled needs a status frame to know what kind of channel it is
+      wait_for(STATUS_TIMEOUT, || mc.get_status()).ok_or_else(|| ApiError::offline("MitoCANdria", id))?;
+      Ok(mc.set_enabled(channel, enabled)?)
+    })
+  }
+
+  pub fn set_mitocandria_voltage(&self, id: u8, channel: u8, voltage: f64) -> ApiResult<()> {
+    self.with_mitocandria(id, |mc| {
+      wait_for(STATUS_TIMEOUT, || mc.get_status()).ok_or_else(|| ApiError::offline("MitoCANdria", id))?;
+      Ok(mc.set_voltage(channel, voltage)?)
+    })
+  }
+}
+
+fn check_device_id(id: u8) -> ApiResult<()> {
+  match id < DEVICE_ID_BROADCAST {
+    true => Ok(()),
+    false => Err(ApiError::new(StatusCode::BAD_REQUEST, "ParameterOutOfBounds", format!("Invalid device ID {}, must be 0-{}", id, DEVICE_ID_BROADCAST - 1))),
+  }
+}
+
+// Run a blocking request off the async runtime, replying with the result as JSON.
+async fn run<T, F>(api: Arc<DeviceApi>, access: ApiResult<()>, f: F) -> Response
+where
+  T: Serialize + Send + 'static,
+  F: FnOnce(&DeviceApi) -> ApiResult<T> + Send + 'static,
+{
+  if let Err(e) = access {
+    return e.into_response();
+  }
+
+  match tokio::task::spawn_blocking(move || f(&api)).await {
+    Ok(Ok(value)) => warp::reply::json(&value).into_response(),
+    Ok(Err(e)) => e.into_response(),
+    Err(_) => ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "Generic", "Request panicked").into_response(),
+  }
+}
+
+// Parse the body ourselves rather than with warp::body::json, so a bad body gets an ApiError.
+fn json_body<T: DeserializeOwned + Send>() -> impl Filter<Extract = (ApiResult<T>,), Error = warp::Rejection> + Clone {
+  warp::body::content_length_limit(MAX_BODY_LENGTH)
+    .and(warp::body::bytes())
+    .map(|body: warp::hyper::body::Bytes| {
+      serde_json::from_slice(&body).map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, "InvalidBody", e.to_string()))
+    })
+}
+
+/// The REST API routes, served under /api by the WebSocket bridge:
+///
+/// - `GET /api/devices` - enumerate Grapple devices on the bus. This sends a broadcast, so isn't
+///   available on read-only bridges.
+/// - `GET /api/lasercan/<id>` - the latest LaserCAN measurement
+/// - `PUT /api/lasercan/<id>/roi`, `/timing_budget`, `/range` - configure a LaserCAN
+/// - `GET /api/mitocandria/<id>` - the latest MitoCANdria status frame
+/// - `PUT /api/mitocandria/<id>/channels/<channel>/enabled`, `/voltage` - control a MitoCANdria channel
+///
+/// Bodies and replies use the same JSON as the bridge's JSON mode. Failed requests reply with an
+/// ApiError.
+pub fn routes(config: &BridgeConfig) -> BoxedFilter<(Response,)> {
+  let api = Arc::new(DeviceApi::new(config));
+  let api = warp::any().map(move || api.clone());
+  let auth = warp::header::optional::<String>("authorization");
+
+  let devices = warp::path!("api" / "devices")
+    .and(warp::get()).and(api.clone()).and(auth)
+    .then(|api: Arc<DeviceApi>, auth: Option<String>| {
+      let access = api.authorize_write(auth.as_deref(), DEVICE_TYPE_BROADCAST, DEVICE_ID_BROADCAST);
+      run(api, access, |_| Ok(enumerate_devices(ENUMERATE_TIMEOUT)?))
+    });
+
+  let lasercan = warp::path!("api" / "lasercan" / u8)
+    .and(warp::get()).and(api.clone()).and(auth)
+    .then(|id, api: Arc<DeviceApi>, auth: Option<String>| {
+      let access = api.authorize(auth.as_deref());
+      run(api, access, move |api| api.lasercan_measurement(id))
+    });
+
+  let lasercan_roi = warp::path!("api" / "lasercan" / u8 / "roi")
+    .and(warp::put()).and(api.clone()).and(auth).and(json_body())
+    .then(|id, api: Arc<DeviceApi>, auth: Option<String>, roi: ApiResult<_>| {
+      let access = api.authorize_write(auth.as_deref(), DEVICE_TYPE_DISTANCE_SENSOR, id);
+      run(api, access, move |api| api.set_lasercan_roi(id, roi?))
+    });
+
+  let lasercan_timing_budget = warp::path!("api" / "lasercan" / u8 / "timing_budget")
+    .and(warp::put()).and(api.clone()).and(auth).and(json_body())
+    .then(|id, api: Arc<DeviceApi>, auth: Option<String>, budget: ApiResult<_>| {
+      let access = api.authorize_write(auth.as_deref(), DEVICE_TYPE_DISTANCE_SENSOR, id);
+      run(api, access, move |api| api.set_lasercan_timing_budget(id, budget?))
+    });
+
+  let lasercan_range = warp::path!("api" / "lasercan" / u8 / "range")
+    .and(warp::put()).and(api.clone()).and(auth).and(json_body())
+    .then(|id, api: Arc<DeviceApi>, auth: Option<String>, mode: ApiResult<_>| {
+      let access = api.authorize_write(auth.as_deref(), DEVICE_TYPE_DISTANCE_SENSOR, id);
+      run(api, access, move |api| api.set_lasercan_range(id, mode?))
+    });
+
+  let mitocandria = warp::path!("api" / "mitocandria" / u8)
+    .and(warp::get()).and(api.clone()).and(auth)
+    .then(|id, api: Arc<DeviceApi>, auth: Option<String>| {
+      let access = api.authorize(auth.as_deref());
+      run(api, access, move |api| api.mitocandria_status(id))
+    });
+
+  let mitocandria_enabled = warp::path!("api" / "mitocandria" / u8 / "channels" / u8 / "enabled")
+    .and(warp::put()).and(api.clone()).and(auth).and(json_body())
+    .then(|id, channel, api: Arc<DeviceApi>, auth: Option<String>, enabled: ApiResult<_>| {
+      let access = api.authorize_write(auth.as_deref(), DEVICE_TYPE_POWER_DISTRIBUTION_MODULE, id);
+      run(api, access, move |api| api.set_mitocandria_enabled(id, channel, enabled?))
+    });
+
+  let mitocandria_voltage = warp::path!("api" / "mitocandria" / u8 / "channels" / u8 / "voltage")
+    .and(warp::put()).and(api).and(auth).and(json_body())
+    .then(|id, channel, api: Arc<DeviceApi>, auth: Option<String>, voltage: ApiResult<_>| {
+      let access = api.authorize_write(auth.as_deref(), DEVICE_TYPE_POWER_DISTRIBUTION_MODULE, id);
+      run(api, access, move |api| api.set_mitocandria_voltage(id, channel, voltage?))
+    });
+
+  devices
+    .or(lasercan).unify()
+    .or(lasercan_roi).unify()
+    .or(lasercan_timing_budget).unify()
+    .or(lasercan_range).unify()
+    .or(mitocandria).unify()
+    .or(mitocandria_enabled).unify()
+    .or(mitocandria_voltage).unify()
+    .boxed()
+}
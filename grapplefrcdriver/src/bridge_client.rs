@@ -0,0 +1,717 @@
+use std::{collections::VecDeque, net::{TcpStream, ToSocketAddrs}, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex, Weak}, thread::JoinHandle, time::{Duration, Instant}};
+
+use tungstenite::{http::Uri, Message, WebSocket};
+
+use crate::{bridge_protocol::{decode_message, encode_message, unpack_batch, BridgeControl, CanFilter, ClientHello, PROTOCOL_VERSION, TCP_BATCH_FLAG, TCP_CONTROL_FLAG}, can::{CanFrame, CanTransport}, can_bridge::{write_frame, FrameReader}};
+
+/// The default number of received frames each BridgeClient holds before dropping the oldest.
+pub const DEFAULT_QUEUE_DEPTH: usize = 1024;
+pub const DEFAULT_MAX_RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+const MIN_RECONNECT_INTERVAL: Duration = Duration::from_millis(100);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+/// How long to wait for the bridge to answer our Hello. Bridges from before the Hello handshake
+/// never answer it, and don't support authentication either.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Where to find a CAN bridge.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BridgeEndpoint {
+  /// The TCP bridge, as `host:port`, e.g. `10.47.88.2:8006`
+  Tcp(String),
+  /// The WebSocket bridge, as a URL, e.g. `ws://10.47.88.2:7171/`
+  WebSocket(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BridgeClientConfig {
+  pub endpoint: BridgeEndpoint,
+  /// The token to authenticate with, if the bridge requires one.
+  pub auth_token: Option<String>,
+  /// Only receive frames matching at least one of these filters. An empty list receives every frame.
+  pub filters: Vec<CanFilter>,
+  /// The number of received frames each BridgeClient holds before dropping the oldest.
+  pub queue_depth: usize,
+  /// The longest to wait between attempts to reconnect. The client starts by retrying quickly,
+  /// and backs off to this while the bridge is unreachable.
+  pub max_reconnect_interval: Duration,
+}
+
+impl BridgeClientConfig {
+  pub fn tcp(address: impl Into<String>) -> Self {
+    Self::with_endpoint(BridgeEndpoint::Tcp(address.into()))
+  }
+
+  pub fn websocket(url: impl Into<String>) -> Self {
+    Self::with_endpoint(BridgeEndpoint::WebSocket(url.into()))
+  }
+
+  fn with_endpoint(endpoint: BridgeEndpoint) -> Self {
+    Self {
+      endpoint,
+      auth_token: None,
+      filters: vec![],
+      queue_depth: DEFAULT_QUEUE_DEPTH,
+      max_reconnect_interval: DEFAULT_MAX_RECONNECT_INTERVAL,
+    }
+  }
+}
+
+// A single connection to the bridge, which is thrown away when it fails.
+enum Connection {
+  Tcp { stream: TcpStream, reader: FrameReader },
+  WebSocket(Box<WebSocket<TcpStream>>),
+}
+
+fn connect_tcp(address: &str) -> anyhow::Result<TcpStream> {
+  let addr = address.to_socket_addrs()?.next().ok_or_else(|| anyhow::anyhow!("Could not resolve {}", address))?;
+  let stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+  stream.set_nodelay(true)?;
+  Ok(stream)
+}
+
+fn is_would_block(e: &tungstenite::Error) -> bool {
+  matches!(e, tungstenite::Error::Io(e) if e.kind() == std::io::ErrorKind::WouldBlock)
+}
+
+impl Connection {
+  fn open(endpoint: &BridgeEndpoint) -> anyhow::Result<Self> {
+    match endpoint {
+      BridgeEndpoint::Tcp(address) => {
+        let stream = connect_tcp(address)?;
+        stream.set_nonblocking(true)?;
+        Ok(Self::Tcp { stream, reader: FrameReader::new() })
+      },
+      BridgeEndpoint::WebSocket(url) => {
+        let uri: Uri = url.parse()?;
+        let host = uri.host().ok_or_else(|| anyhow::anyhow!("No host in {}", url))?;
+        let stream = connect_tcp(&format!("{}:{}", host, uri.port_u16().unwrap_or(80)))?;
+
+        // Block during the handshake, but don't wait forever on a bridge that never answers
+        stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+        let (ws, _) = tungstenite::client(url.as_str(), stream).map_err(|e| anyhow::anyhow!("WebSocket handshake failed: {}", e))?;
+        ws.get_ref().set_read_timeout(None)?;
+        ws.get_ref().set_nonblocking(true)?;
+        Ok(Self::WebSocket(Box::new(ws)))
+      },
+    }
+  }
+
+  fn send_control(&mut self, control: &BridgeControl) -> anyhow::Result<()> {
+    match self {
+      Self::Tcp { stream, .. } => write_frame(stream, TCP_CONTROL_FLAG, control.to_json().as_bytes()),
+      Self::WebSocket(ws) => Self::send_ws(ws, Message::text(control.to_json())),
+    }
+  }
+
+  fn send_frame(&mut self, frame: Vec<u8>) -> anyhow::Result<()> {
+    match self {
+      Self::Tcp { stream, .. } => write_frame(stream, 0, &frame),
+      Self::WebSocket(ws) => Self::send_ws(ws, Message::binary(frame)),
+    }
+  }
+
+  // A WouldBlock just means the message has been buffered, and will go out on a later flush.
+  fn send_ws(ws: &mut WebSocket<TcpStream>, msg: Message) -> anyhow::Result<()> {
+    match ws.send(msg) {
+      Err(e) if !is_would_block(&e) => Err(e.into()),
+      _ => Ok(())
+    }
+  }
+
+  // Read everything that has arrived from the bridge, passing each encoded BridgedCANMessage and
+  // control message to the callbacks.
+  fn poll<F: FnMut(&[u8]), C: FnMut(BridgeControl)>(&mut self, on_frame: &mut F, on_control: &mut C) -> anyhow::Result<()> {
+    let mut handle_control = |data: &[u8]| match BridgeControl::from_json(data) {
+      Ok(control) => on_control(control),
+      Err(e) => log::warn!(error:% = e; "Invalid control message from the bridge"),
+    };
+
+    match self {
+      Self::Tcp { stream, reader } => {
+        let closed = reader.fill(stream)?;
+
+        while let Some((header, payload)) = reader.next_frame() {
+          if header & TCP_BATCH_FLAG != 0 {
+            unpack_batch(&payload).for_each(&mut *on_frame);
+          } else if header & TCP_CONTROL_FLAG != 0 {
+            handle_control(&payload);
+          } else {
+            on_frame(&payload);
+          }
+        }
+
+        let skipped = reader.take_skipped();
+        if skipped > 0 {
+          log::warn!(skipped; "Skipped invalid data from the bridge");
+        }
+
+        if closed {
+          anyhow::bail!("Connection closed by the bridge");
+        }
+      },
+      Self::WebSocket(ws) => {
+        loop {
+          match ws.read() {
+            Ok(Message::Binary(data)) => on_frame(&data),
+            Ok(Message::Text(text)) => handle_control(text.as_bytes()),
+            Ok(Message::Close(_)) => anyhow::bail!("Connection closed by the bridge"),
+            Ok(_) => (),
+            Err(e) if is_would_block(&e) => break,
+            Err(e) => return Err(e.into()),
+          }
+        }
+
+        match ws.flush() {
+          Err(e) if !is_would_block(&e) => return Err(e.into()),
+          _ => (),
+        }
+      },
+    }
+    Ok(())
+  }
+}
+
+type FrameQueue = Mutex<VecDeque<CanFrame>>;
+
+// The bridge didn't reply to our Hello over TCP, so it needs a connection without one.
+#[derive(Debug)]
+struct LegacyBridge;
+
+impl std::fmt::Display for LegacyBridge {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "The bridge doesn't support the handshake")
+  }
+}
+
+impl std::error::Error for LegacyBridge {}
+
+// State shared between every BridgeClient using the same connection, and the thread that owns it.
+struct Shared {
+  config: BridgeClientConfig,
+  stop: AtomicBool,
+  connected: AtomicBool,
+  // The bridge predates the handshake, so we talk to it without any control messages.
+  legacy: AtomicBool,
+  outbox: Mutex<VecDeque<Vec<u8>>>,
+  queues: Mutex<Vec<Weak<FrameQueue>>>,
+}
+
+impl Shared {
+  fn run(&self) {
+    let mut reconnect_interval = MIN_RECONNECT_INTERVAL;
+
+    while !self.stop.load(Ordering::Relaxed) {
+      let result = Connection::open(&self.config.endpoint).and_then(|mut connection| {
+        log::info!(endpoint:? = self.config.endpoint; "Connected to bridge");
+        self.run_connection(&mut connection)
+      });
+
+      // Frames queued while the connection was going down are stale, don't send them on the next one.
+      let was_connected = self.connected.swap(false, Ordering::SeqCst);
+      self.outbox.lock().unwrap().clear();
+
+      // Only retry quickly if the bridge actually let us in, so we don't hammer a bridge that keeps
+      // rejecting our token, or disconnects us for not authenticating in time.
+      if was_connected {
+        reconnect_interval = MIN_RECONNECT_INTERVAL;
+      }
+
+      match result {
+        Ok(()) => break,
+        // Not a failure, the bridge just needs a connection that never had a Hello on it.
+        Err(e) if e.is::<LegacyBridge>() => {
+          log::info!(endpoint:? = self.config.endpoint; "No reply to our Hello, reconnecting to an older bridge without it");
+          continue;
+        },
+        Err(e) => log::warn!(endpoint:? = self.config.endpoint, error:% = e, retry_in:? = reconnect_interval; "Disconnected from bridge"),
+      }
+
+      let retry_at = Instant::now() + reconnect_interval;
+      while !self.stop.load(Ordering::Relaxed) && Instant::now() < retry_at {
+        std::thread::sleep(Duration::from_millis(10));
+      }
+      reconnect_interval = (reconnect_interval * 2).min(self.config.max_reconnect_interval.max(MIN_RECONNECT_INTERVAL));
+    }
+  }
+
+  // Returns Ok once asked to stop, or an error if the connection fails.
+  fn run_connection(&self, connection: &mut Connection) -> anyhow::Result<()> {
+    let legacy = self.legacy.load(Ordering::SeqCst);
+    if legacy {
+      self.on_ready(connection)?;
+    } else {
+      connection.send_control(&BridgeControl::Hello(ClientHello {
+        protocol_version: PROTOCOL_VERSION,
+        client: Some(format!("libgrapplefrc {}", env!("CARGO_PKG_VERSION"))),
+      }))?;
+
+      if let Some(token) = &self.config.auth_token {
+        connection.send_control(&BridgeControl::Authenticate(token.clone()))?;
+      }
+    }
+    let hello_sent = Instant::now();
+    let mut welcomed = legacy;
+
+    while !self.stop.load(Ordering::Relaxed) {
+      let mut controls = vec![];
+      connection.poll(&mut |data| self.deliver(data), &mut |control| controls.push(control))?;
+
+      // We're only ready once the bridge has let us in, either by accepting our token or by saying
+      // it doesn't need one.
+      for control in controls {
+        match control {
+          BridgeControl::AuthenticationResult(true) => self.on_ready(connection)?,
+          BridgeControl::AuthenticationResult(false) => anyhow::bail!("The bridge rejected our token"),
+          BridgeControl::Welcome(welcome) => {
+            log::info!(server_version = welcome.server_version.as_str(), protocol_version = welcome.protocol_version; "Bridge says hello");
+            welcomed = true;
+            match (welcome.auth_required, &self.config.auth_token) {
+              (false, _) => self.on_ready(connection)?,
+              (true, None) => anyhow::bail!("The bridge requires a token"),
+              // Wait for the AuthenticationResult
+              (true, Some(_)) => (),
+            }
+          },
+          BridgeControl::SessionOverflow(n) => log::warn!(overflows = n; "The bridge's session overflowed, frames were lost"),
+          _ => (),
+        }
+      }
+
+      // Older bridges don't reply to the Hello. Over TCP they read its control header as the length of
+      // a huge frame and swallow everything after it, so the connection is useless and we start again
+      // without it. Older WebSocket bridges just ignore text messages, so we can carry on.
+      if !welcomed && self.config.auth_token.is_none() && hello_sent.elapsed() > HELLO_TIMEOUT {
+        welcomed = true;
+        self.legacy.store(true, Ordering::SeqCst);
+        match connection {
+          Connection::Tcp { .. } => return Err(LegacyBridge.into()),
+          Connection::WebSocket(_) => {
+            log::info!(endpoint:? = self.config.endpoint; "No reply to our Hello, assuming an older bridge");
+            self.on_ready(connection)?;
+          },
+        }
+      }
+
+      let outgoing: Vec<Vec<u8>> = self.outbox.lock().unwrap().drain(..).collect();
+      for frame in outgoing {
+        connection.send_frame(frame)?;
+      }
+
+      std::thread::sleep(POLL_INTERVAL);
+    }
+    Ok(())
+  }
+
+  // Called once the bridge is ready for us to use it, after authenticating if required.
+  fn on_ready(&self, connection: &mut Connection) -> anyhow::Result<()> {
+    if self.connected.load(Ordering::SeqCst) {
+      return Ok(());
+    }
+    if !self.config.filters.is_empty() && !self.legacy.load(Ordering::SeqCst) {
+      connection.send_control(&BridgeControl::SetFilters(self.config.filters.clone()))?;
+    }
+    self.connected.store(true, Ordering::SeqCst);
+    Ok(())
+  }
+
+  // Hand a received frame to every BridgeClient.
+  fn deliver(&self, data: &[u8]) {
+    let frame = match decode_message(data) {
+      Ok(frame) => frame,
+      Err(e) => {
+        log::warn!(error:% = e; "Invalid frame from the bridge");
+        return;
+      }
+    };
+
+    // Older bridges ignore SetFilters, so check them here too.
+    if !self.config.filters.is_empty() && !CanFilter::any_match(&self.config.filters, frame.id) {
+      return;
+    }
+
+    let mut queues = self.queues.lock().unwrap();
+    queues.retain(|q| q.strong_count() > 0);
+    for queue in queues.iter().filter_map(|q| q.upgrade()) {
+      let mut queue = queue.lock().unwrap();
+      if queue.len() >= self.config.queue_depth {
+        queue.pop_front();
+      }
+      queue.push_back(frame.clone());
+    }
+  }
+}
+
+// Stops the connection thread once the last BridgeClient using it is dropped.
+struct Worker {
+  shared: Arc<Shared>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for Worker {
+  fn drop(&mut self) {
+    self.shared.stop.store(true, Ordering::SeqCst);
+    if let Some(thread) = self.thread.take() {
+      thread.join().ok();
+    }
+  }
+}
+
+/// A client for the TCP and WebSocket CAN bridges, so code running on a laptop or coprocessor can
+/// use the robot's CAN bus as if it were local, e.g. with LaserCAN::with_transport. The connection
+/// is made in the background, and remade whenever it drops.
+///
+/// Cloning a BridgeClient shares the connection, but each clone receives its own copy of every
+/// frame, so one can be given to each device. Frames can only be sent while connected.
+pub struct BridgeClient {
+  shared: Arc<Shared>,
+  queue: Arc<FrameQueue>,
+  worker: Arc<Worker>,
+}
+
+impl BridgeClient {
+  pub fn connect(config: BridgeClientConfig) -> Self {
+    let shared = Arc::new(Shared {
+      config,
+      stop: AtomicBool::new(false),
+      connected: AtomicBool::new(false),
+      legacy: AtomicBool::new(false),
+      outbox: Mutex::new(VecDeque::new()),
+      queues: Mutex::new(vec![]),
+    });
+
+    let queue = Self::subscribe(&shared);
+    let thread_shared = shared.clone();
+    let thread = std::thread::spawn(move || thread_shared.run());
+
+    Self { shared: shared.clone(), queue, worker: Arc::new(Worker { shared, thread: Some(thread) }) }
+  }
+
+  fn subscribe(shared: &Shared) -> Arc<FrameQueue> {
+    let queue = Arc::new(Mutex::new(VecDeque::new()));
+    shared.queues.lock().unwrap().push(Arc::downgrade(&queue));
+    queue
+  }
+
+  /// Whether the client is connected to the bridge, and authenticated if the bridge requires it.
+  pub fn is_connected(&self) -> bool {
+    self.shared.connected.load(Ordering::SeqCst)
+  }
+
+  /// Block until the client is connected, returning false if it isn't connected within the timeout.
+  pub fn wait_for_connection(&self, timeout: Duration) -> bool {
+    let started = Instant::now();
+    while !self.is_connected() {
+      if started.elapsed() > timeout {
+        return false;
+      }
+      std::thread::sleep(Duration::from_millis(10));
+    }
+    true
+  }
+}
+
+impl Clone for BridgeClient {
+  fn clone(&self) -> Self {
+    Self { shared: self.shared.clone(), queue: Self::subscribe(&self.shared), worker: self.worker.clone() }
+  }
+}
+
+impl CanTransport for BridgeClient {
+  fn send(&mut self, id: u32, data: &[u8]) -> anyhow::Result<()> {
+    if !self.is_connected() {
+      anyhow::bail!("Not connected to the CAN bridge at {:?}", self.shared.config.endpoint);
+    }
+    if data.len() > 8 {
+      anyhow::bail!("CAN frames can't be longer than 8 bytes");
+    }
+    self.shared.outbox.lock().unwrap().push_back(encode_message(id, 0, data));
+    Ok(())
+  }
+
+  fn receive(&mut self, filter: CanFilter) -> Option<CanFrame> {
+    let mut queue = self.queue.lock().unwrap();
+    let position = queue.iter().position(|frame| filter.matches(frame.id))?;
+    queue.remove(position)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{collections::VecDeque, io::{Read, Write}, net::{IpAddr, Ipv4Addr, TcpListener, TcpStream}, sync::{Arc, Mutex, OnceLock, Weak}, time::{Duration, Instant}};
+
+  use crate::{bridge_config::BridgeConfig, bridge_handle::BridgeHandle, bridge_protocol::{decode_message, encode_message, CanFilter}, can::{set_bus, CanBus, CanFrame, CanStream, CanTransport}, can_bridge::start_can_bridge_with_config, ws_can_bridge::start_ws_can_bridge_with_config, HAL_CANStreamMessage};
+
+  use super::{BridgeClient, BridgeClientConfig};
+
+  const TIMEOUT: Duration = Duration::from_secs(5);
+
+  type StreamQueue = Mutex<VecDeque<HAL_CANStreamMessage>>;
+
+  // A CAN bus that only exists in memory. Frames injected by the test go to every open stream, and
+  // frames sent by the bridges are recorded.
+  #[derive(Default)]
+  struct FakeBus {
+    streams: Mutex<Vec<(CanFilter, Weak<StreamQueue>)>>,
+    sent: Mutex<Vec<(u32, Vec<u8>)>>,
+  }
+
+  impl FakeBus {
+    fn inject(&self, id: u32, data: &[u8]) {
+      let mut message = HAL_CANStreamMessage { messageID: id, timeStamp: 1234, dataSize: data.len() as u8, ..Default::default() };
+      message.data[0..data.len()].copy_from_slice(data);
+
+      let mut streams = self.streams.lock().unwrap();
+      streams.retain(|(_, queue)| queue.strong_count() > 0);
+      for (_, queue) in streams.iter().filter(|(filter, _)| filter.matches(id)) {
+        if let Some(queue) = queue.upgrade() {
+          queue.lock().unwrap().push_back(message);
+        }
+      }
+    }
+
+    fn was_sent(&self, id: u32, data: &[u8]) -> bool {
+      self.sent.lock().unwrap().iter().any(|(i, d)| *i == id && d == data)
+    }
+  }
+
+  struct FakeStream {
+    bus: Arc<FakeBus>,
+    queue: Arc<StreamQueue>,
+  }
+
+  impl CanStream for FakeStream {
+    fn read(&mut self, buffer: &mut [HAL_CANStreamMessage]) -> (usize, bool) {
+      let mut queue = self.queue.lock().unwrap();
+      let n = queue.len().min(buffer.len());
+      for (slot, message) in buffer.iter_mut().zip(queue.drain(0..n)) {
+        *slot = message;
+      }
+      (n, false)
+    }
+
+    fn send(&mut self, id: u32, data: &[u8]) -> anyhow::Result<()> {
+      self.bus.sent.lock().unwrap().push((id, data.to_vec()));
+      Ok(())
+    }
+  }
+
+  impl CanTransport for FakeStream {
+    fn send(&mut self, id: u32, data: &[u8]) -> anyhow::Result<()> {
+      CanStream::send(self, id, data)
+    }
+
+    fn receive(&mut self, filter: CanFilter) -> Option<CanFrame> {
+      let mut queue = self.queue.lock().unwrap();
+      let position = queue.iter().position(|m| filter.matches(m.messageID))?;
+      queue.remove(position).map(|m| CanFrame { id: m.messageID, timestamp: m.timeStamp, data: m.data[0..m.dataSize as usize].to_vec() })
+    }
+  }
+
+  struct SharedFakeBus(Arc<FakeBus>);
+
+  impl SharedFakeBus {
+    fn stream(&self, filter: CanFilter) -> FakeStream {
+      let queue = Arc::new(Mutex::new(VecDeque::new()));
+      self.0.streams.lock().unwrap().push((filter, Arc::downgrade(&queue)));
+      FakeStream { bus: self.0.clone(), queue }
+    }
+  }
+
+  impl CanBus for SharedFakeBus {
+    fn transport(&self) -> Box<dyn CanTransport> {
+      Box::new(self.stream(CanFilter { id: 0, mask: 0 }))
+    }
+
+    fn open_stream(&self, filter: CanFilter, _depth: u32) -> anyhow::Result<Box<dyn CanStream>> {
+      Ok(Box::new(self.stream(filter)))
+    }
+
+    fn time_us(&self) -> u64 {
+      0
+    }
+  }
+
+  // The bus is global, so every test shares one, and uses its own frame IDs.
+  fn fake_bus() -> Arc<FakeBus> {
+    static BUS: OnceLock<Arc<FakeBus>> = OnceLock::new();
+    BUS.get_or_init(|| {
+      let bus = Arc::new(FakeBus::default());
+      set_bus(Some(Arc::new(SharedFakeBus(bus.clone()))));
+      bus
+    }).clone()
+  }
+
+  fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+  }
+
+  fn wait_for<T>(mut f: impl FnMut() -> Option<T>) -> T {
+    let started = Instant::now();
+    loop {
+      if let Some(value) = f() {
+        return value;
+      }
+      assert!(started.elapsed() < TIMEOUT, "Timed out");
+      std::thread::sleep(Duration::from_millis(10));
+    }
+  }
+
+  #[derive(Clone, Copy)]
+  enum Endpoint {
+    Tcp,
+    WebSocket,
+  }
+
+  impl Endpoint {
+    fn start(self, port: u16, auth_token: Option<&str>) -> BridgeHandle {
+      let config = BridgeConfig {
+        bind_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+        port,
+        auth_token: auth_token.map(str::to_owned),
+        ..BridgeConfig::tcp()
+      };
+      match self {
+        Self::Tcp => start_can_bridge_with_config(config),
+        Self::WebSocket => start_ws_can_bridge_with_config(config),
+      }
+    }
+
+    fn client(self, port: u16, auth_token: Option<&str>) -> BridgeClient {
+      let config = match self {
+        Self::Tcp => BridgeClientConfig::tcp(format!("127.0.0.1:{}", port)),
+        Self::WebSocket => BridgeClientConfig::websocket(format!("ws://127.0.0.1:{}/", port)),
+      };
+      BridgeClient::connect(BridgeClientConfig { auth_token: auth_token.map(str::to_owned), max_reconnect_interval: Duration::from_millis(500), ..config })
+    }
+  }
+
+  fn assert_round_trip(bus: &FakeBus, client: &mut BridgeClient, id: u32) {
+    bus.inject(id, &[1, 2, 3]);
+    let frame = wait_for(|| client.receive(CanFilter { id, mask: 0x1FFF_FFFF }));
+    assert_eq!(frame.data, vec![1, 2, 3]);
+
+    client.send(id + 1, &[4, 5]).unwrap();
+    wait_for(|| bus.was_sent(id + 1, &[4, 5]).then_some(()));
+  }
+
+  fn round_trip(endpoint: Endpoint, id: u32) {
+    let bus = fake_bus();
+    let port = free_port();
+    let _bridge = endpoint.start(port, None);
+
+    let mut client = endpoint.client(port, None);
+    assert!(client.wait_for_connection(TIMEOUT));
+    assert_round_trip(&bus, &mut client, id);
+  }
+
+  fn reconnect(endpoint: Endpoint, id: u32) {
+    let bus = fake_bus();
+    let port = free_port();
+    let bridge = endpoint.start(port, None);
+
+    let mut client = endpoint.client(port, None);
+    assert!(client.wait_for_connection(TIMEOUT));
+
+    drop(bridge);
+    wait_for(|| (!client.is_connected()).then_some(()));
+
+    let _bridge = endpoint.start(port, None);
+    assert!(client.wait_for_connection(TIMEOUT));
+    assert_round_trip(&bus, &mut client, id);
+  }
+
+  fn authentication(endpoint: Endpoint, id: u32) {
+    let bus = fake_bus();
+    let port = free_port();
+    let _bridge = endpoint.start(port, Some("hunter2"));
+
+    let wrong = endpoint.client(port, Some("hunter3"));
+    let missing = endpoint.client(port, None);
+    assert!(!wrong.wait_for_connection(Duration::from_secs(2)));
+    assert!(!missing.is_connected());
+
+    let mut client = endpoint.client(port, Some("hunter2"));
+    assert!(client.wait_for_connection(TIMEOUT));
+    assert_round_trip(&bus, &mut client, id);
+  }
+
+  // A TCP bridge from before the handshake. Every message is a u16 length and a frame, with no flags,
+  // and each frame it receives is echoed back with the next ID.
+  fn start_legacy_bridge() -> (u16, Arc<Mutex<Vec<CanFrame>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let received = Arc::new(Mutex::new(vec![]));
+
+    let frames = received.clone();
+    std::thread::spawn(move || {
+      for stream in listener.incoming().flatten() {
+        let frames = frames.clone();
+        std::thread::spawn(move || legacy_connection(stream, &frames));
+      }
+    });
+    (port, received)
+  }
+
+  fn legacy_connection(mut stream: TcpStream, frames: &Mutex<Vec<CanFrame>>) -> anyhow::Result<()> {
+    loop {
+      let mut len = [0u8; 2];
+      stream.read_exact(&mut len)?;
+      let mut data = vec![0u8; u16::from_le_bytes(len) as usize];
+      stream.read_exact(&mut data)?;
+
+      let frame = decode_message(&data)?;
+      let reply = encode_message(frame.id + 1, 0, &frame.data);
+      frames.lock().unwrap().push(frame);
+      stream.write_all(&(reply.len() as u16).to_le_bytes())?;
+      stream.write_all(&reply)?;
+    }
+  }
+
+  #[test]
+  fn tcp_falls_back_to_legacy_bridge() {
+    let (port, received) = start_legacy_bridge();
+
+    let mut client = Endpoint::Tcp.client(port, None);
+    assert!(client.wait_for_connection(TIMEOUT));
+
+    client.send(0x700, &[1, 2, 3]).unwrap();
+    let frame = wait_for(|| client.receive(CanFilter { id: 0x701, mask: 0x1FFF_FFFF }));
+    assert_eq!(frame.data, vec![1, 2, 3]);
+
+    let received = received.lock().unwrap();
+    assert_eq!(received.len(), 1);
+    assert_eq!((received[0].id, received[0].data.clone()), (0x700, vec![1, 2, 3]));
+  }
+
+  #[test]
+  fn tcp_round_trip() {
+    round_trip(Endpoint::Tcp, 0x100);
+  }
+
+  #[test]
+  fn websocket_round_trip() {
+    round_trip(Endpoint::WebSocket, 0x200);
+  }
+
+  #[test]
+  fn tcp_reconnects_after_restart() {
+    reconnect(Endpoint::Tcp, 0x300);
+  }
+
+  #[test]
+  fn websocket_reconnects_after_restart() {
+    reconnect(Endpoint::WebSocket, 0x400);
+  }
+
+  #[test]
+  fn tcp_rejects_bad_token() {
+    authentication(Endpoint::Tcp, 0x500);
+  }
+
+  #[test]
+  fn websocket_rejects_bad_token() {
+    authentication(Endpoint::WebSocket, 0x600);
+  }
+}
@@ -0,0 +1,195 @@
+use std::{ffi::{c_char, CStr}, net::{IpAddr, Ipv4Addr, SocketAddr}, time::Duration};
+
+use crate::bridge_protocol::CanFilter;
+
+pub const DEFAULT_TCP_PORT: u16 = 8006;
+pub const DEFAULT_WEBSOCKET_PORT: u16 = 7171;
+/// The default maximum number of clients that may be connected to a bridge at once.
+pub const DEFAULT_MAX_CLIENTS: usize = 4;
+/// The default number of frames buffered by each client's HAL stream session.
+pub const DEFAULT_SESSION_DEPTH: u32 = 1024;
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(1);
+pub const DEFAULT_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(5);
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Configuration shared by the TCP and WebSocket CAN bridges.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BridgeConfig {
+  /// The address to listen on. Use this to restrict the bridge to a single interface,
+  /// e.g. the robot radio.
+  pub bind_address: IpAddr,
+  pub port: u16,
+  /// The maximum number of clients that may be connected at once.
+  pub max_clients: usize,
+  /// The number of frames each client's HAL stream session can hold before it overflows.
+  pub session_depth: u32,
+  /// How often each client's stream session is checked for new frames while the bus is busy. The
+  /// bridge backs off to bridge_session::MAX_IDLE_POLL_INTERVAL while the bus is idle.
+  pub poll_interval: Duration,
+  /// If set, clients must send this token in an Authenticate control message (or the `token` query
+  /// parameter on the WebSocket bridge) before they can use the bridge.
+  pub auth_token: Option<String>,
+  /// Refuse all frames sent by clients, so they can only listen to the bus.
+  pub read_only: bool,
+  /// If not empty, clients may only send frames matching at least one of these filters.
+  pub transmit_allow_list: Vec<CanFilter>,
+  /// How often the WebSocket bridge pings each client.
+  pub keepalive_interval: Duration,
+  /// How long the WebSocket bridge waits without hearing anything from a client (including replies
+  /// to its pings) before disconnecting it.
+  pub idle_timeout: Duration,
+}
+
+impl BridgeConfig {
+  /// The default configuration for the TCP bridge, as used by GrappleHook.
+  pub fn tcp() -> Self {
+    Self::with_port(DEFAULT_TCP_PORT)
+  }
+
+  /// The default configuration for the WebSocket bridge.
+  pub fn websocket() -> Self {
+    Self::with_port(DEFAULT_WEBSOCKET_PORT)
+  }
+
+  fn with_port(port: u16) -> Self {
+    Self {
+      bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+      port,
+      max_clients: DEFAULT_MAX_CLIENTS,
+      session_depth: DEFAULT_SESSION_DEPTH,
+      poll_interval: DEFAULT_POLL_INTERVAL,
+      auth_token: None,
+      read_only: false,
+      transmit_allow_list: vec![],
+      keepalive_interval: DEFAULT_KEEPALIVE_INTERVAL,
+      idle_timeout: DEFAULT_IDLE_TIMEOUT,
+    }
+  }
+
+  pub fn socket_addr(&self) -> SocketAddr {
+    SocketAddr::new(self.bind_address, self.port)
+  }
+}
+
+/// C representation of a BridgeConfig. Any field left as zero (or null, for the bind address and
+/// auth token) takes on the default for the bridge being started.
+#[repr(C)]
+pub struct CBridgeConfig {
+  pub bind_address: *const c_char,
+  pub port: u16,
+  pub max_clients: u32,
+  pub session_depth: u32,
+  pub poll_interval_ms: u32,
+  pub auth_token: *const c_char,
+  pub read_only: bool,
+  pub transmit_allow_list: *const CanFilter,
+  pub transmit_allow_list_len: usize,
+  pub keepalive_interval_ms: u32,
+  pub idle_timeout_ms: u32,
+}
+
+impl CBridgeConfig {
+  pub(crate) fn to_config(&self, mut defaults: BridgeConfig) -> anyhow::Result<BridgeConfig> {
+    if !self.bind_address.is_null() {
+      defaults.bind_address = unsafe { CStr::from_ptr(self.bind_address) }.to_str()?.parse()?;
+    }
+    if self.port != 0 { defaults.port = self.port; }
+    if self.max_clients != 0 { defaults.max_clients = self.max_clients as usize; }
+    if self.session_depth != 0 { defaults.session_depth = self.session_depth; }
+    if self.poll_interval_ms != 0 { defaults.poll_interval = Duration::from_millis(self.poll_interval_ms as u64); }
+    if !self.auth_token.is_null() {
+      defaults.auth_token = Some(unsafe { CStr::from_ptr(self.auth_token) }.to_str()?.to_owned());
+    }
+    defaults.read_only = self.read_only;
+    if !self.transmit_allow_list.is_null() {
+      defaults.transmit_allow_list = unsafe { std::slice::from_raw_parts(self.transmit_allow_list, self.transmit_allow_list_len) }.to_vec();
+    }
+    if self.keepalive_interval_ms != 0 { defaults.keepalive_interval = Duration::from_millis(self.keepalive_interval_ms as u64); }
+    if self.idle_timeout_ms != 0 { defaults.idle_timeout = Duration::from_millis(self.idle_timeout_ms as u64); }
+    Ok(defaults)
+  }
+}
+
+#[cfg(feature = "jni")]
+pub(crate) mod jni {
+  use std::time::Duration;
+
+  use jni::{objects::{JObject, JObjectArray, JString}, JNIEnv};
+
+  use crate::bridge_protocol::CanFilter;
+
+  use super::BridgeConfig;
+
+  pub(crate) fn get_string<'local>(env: &mut JNIEnv<'local>, obj: &JObject<'local>, field: &str) -> jni::errors::Result<Option<String>> {
+    let s: JString = env.get_field(obj, field, "Ljava/lang/String;")?.l()?.into();
+    if s.is_null() {
+      return Ok(None);
+    }
+    let s: String = env.get_string(&s)?.into();
+    Ok(Some(s))
+  }
+
+  pub(crate) fn get_int<'local>(env: &mut JNIEnv<'local>, obj: &JObject<'local>, field: &str) -> jni::errors::Result<i32> {
+    env.get_field(obj, field, "I")?.i()
+  }
+
+  pub(crate) fn get_filters<'local>(env: &mut JNIEnv<'local>, obj: &JObject<'local>, field: &str) -> jni::errors::Result<Vec<CanFilter>> {
+    let arr: JObjectArray = env.get_field(obj, field, "[Lau/grapplerobotics/CanBridge$Filter;")?.l()?.into();
+    if arr.is_null() {
+      return Ok(vec![]);
+    }
+
+    let mut filters = vec![];
+    for i in 0..env.get_array_length(&arr)? {
+      let filter = env.get_object_array_element(&arr, i)?;
+      filters.push(CanFilter { id: get_int(env, &filter, "id")? as u32, mask: get_int(env, &filter, "mask")? as u32 });
+    }
+    Ok(filters)
+  }
+
+  fn read_config<'local>(env: &mut JNIEnv<'local>, config: &JObject<'local>, mut defaults: BridgeConfig) -> anyhow::Result<BridgeConfig> {
+    if let Some(bind_address) = get_string(env, config, "bindAddress")? {
+      defaults.bind_address = bind_address.parse()?;
+    }
+    match get_int(env, config, "port")? {
+      0 => (),
+      port => defaults.port = port as u16,
+    }
+    match get_int(env, config, "maxClients")? {
+      0 => (),
+      max_clients => defaults.max_clients = max_clients as usize,
+    }
+    match get_int(env, config, "sessionDepth")? {
+      0 => (),
+      session_depth => defaults.session_depth = session_depth as u32,
+    }
+    match get_int(env, config, "pollIntervalMs")? {
+      0 => (),
+      poll_interval_ms => defaults.poll_interval = Duration::from_millis(poll_interval_ms as u64),
+    }
+    defaults.auth_token = get_string(env, config, "authToken")?;
+    defaults.read_only = env.get_field(config, "readOnly", "Z")?.z()?;
+    defaults.transmit_allow_list = get_filters(env, config, "transmitAllowList")?;
+    match get_int(env, config, "keepaliveIntervalMs")? {
+      0 => (),
+      keepalive_interval_ms => defaults.keepalive_interval = Duration::from_millis(keepalive_interval_ms as u64),
+    }
+    match get_int(env, config, "idleTimeoutMs")? {
+      0 => (),
+      idle_timeout_ms => defaults.idle_timeout = Duration::from_millis(idle_timeout_ms as u64),
+    }
+    Ok(defaults)
+  }
+
+  /// Build a BridgeConfig from a Java CanBridge.Config, throwing an IllegalArgumentException if it
+  /// is invalid.
+  pub(crate) fn config_from_jni<'local>(env: &mut JNIEnv<'local>, config: JObject<'local>, defaults: BridgeConfig) -> Option<BridgeConfig> {
+    match read_config(env, &config, defaults) {
+      Ok(config) => Some(config),
+      Err(e) => {
+        env.throw_new("java/lang/IllegalArgumentException", format!("Invalid CAN Bridge Configuration: {}", e)).ok();
+        None
+      }
+    }
+  }
+}
@@ -0,0 +1,217 @@
+use std::{panic::AssertUnwindSafe, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex}, thread::JoinHandle};
+
+use crate::bridge_stats::{BridgeStats, BridgeStatsSnapshot};
+
+#[derive(Default)]
+struct BridgeState {
+  stop: Arc<AtomicBool>,
+  running: AtomicBool,
+  last_error: Mutex<Option<String>>,
+}
+
+/// A handle to a CAN bridge running in the background. Dropping the handle will stop the bridge,
+/// unless it has been detached.
+pub struct BridgeHandle {
+  state: Arc<BridgeState>,
+  stats: Arc<BridgeStats>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl BridgeHandle {
+  /// Run a bridge on a background thread. The bridge is given a flag that will be set when it
+  /// should shut down, and is expected to close its HAL stream sessions before returning.
+  pub fn spawn<F: FnOnce(Arc<AtomicBool>, Arc<BridgeStats>) -> anyhow::Result<()> + Send + 'static>(stats: Arc<BridgeStats>, f: F) -> Self {
+    let state = Arc::new(BridgeState::default());
+    state.running.store(true, Ordering::SeqCst);
+
+    let thread_state = state.clone();
+    let thread_stats = stats.clone();
+    let thread = std::thread::spawn(move || {
+      // Catch panics so the bridge is still marked as stopped, and the panic is reported as the error.
+      let result = std::panic::catch_unwind(AssertUnwindSafe(|| f(thread_state.stop.clone(), thread_stats.clone())))
+        .unwrap_or_else(|_| Err(anyhow::anyhow!("Bridge thread panicked")));
+      if let Err(e) = result {
+        log::error!(error:% = e; "CAN bridge stopped");
+        *thread_state.last_error.lock().unwrap_or_else(|e| e.into_inner()) = Some(e.to_string());
+      }
+      thread_stats.unregister();
+      thread_state.running.store(false, Ordering::SeqCst);
+    });
+
+    Self { state, stats, thread: Some(thread) }
+  }
+
+  /// Ask the bridge to shut down. Use join to wait for it to finish.
+  pub fn stop(&self) {
+    self.state.stop.store(true, Ordering::SeqCst);
+  }
+
+  /// Wait for the bridge to finish.
+  pub fn join(&mut self) {
+    if let Some(thread) = self.thread.take() {
+      thread.join().ok();
+    }
+  }
+
+  pub fn is_running(&self) -> bool {
+    self.state.running.load(Ordering::SeqCst)
+  }
+
+  /// The error that caused the bridge to exit, if any.
+  pub fn last_error(&self) -> Option<String> {
+    self.state.last_error.lock().unwrap_or_else(|e| e.into_inner()).clone()
+  }
+
+  /// The bridge's traffic counters. These remain available after the bridge has stopped.
+  pub fn stats(&self) -> BridgeStatsSnapshot {
+    self.stats.snapshot()
+  }
+
+  /// Let the bridge run for the rest of the program, without holding on to the handle.
+  pub fn detach(mut self) {
+    self.thread.take();
+  }
+}
+
+impl Drop for BridgeHandle {
+  fn drop(&mut self) {
+    if self.thread.is_some() {
+      self.stop();
+      self.join();
+    }
+  }
+}
+
+#[cfg(feature = "c")]
+mod c {
+  use std::ffi::CString;
+
+  use crate::{bridge_stats::BridgeStatsSnapshot, COptional, CGrappleError};
+
+  use super::BridgeHandle;
+
+  #[no_mangle]
+  pub extern "C" fn bridge_handle_free(handle: *mut BridgeHandle) {
+    if handle.is_null() { return; }
+    unsafe { drop(Box::from_raw(handle)) }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn bridge_handle_stop(handle: *mut BridgeHandle) {
+    unsafe { (*handle).stop() }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn bridge_handle_join(handle: *mut BridgeHandle) {
+    unsafe { (*handle).join() }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn bridge_handle_is_running(handle: *mut BridgeHandle) -> bool {
+    unsafe { (*handle).is_running() }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn bridge_handle_stats(handle: *mut BridgeHandle) -> BridgeStatsSnapshot {
+    unsafe { (*handle).stats() }
+  }
+
+  // Need to wrap this so MSVC doesn't complain about using C++ generics in extern "C"
+  #[repr(C)]
+  pub struct MaybeBridgeError(COptional<CGrappleError>);
+
+  #[no_mangle]
+  pub extern "C" fn bridge_handle_last_error(handle: *mut BridgeHandle) -> MaybeBridgeError {
+    let err = unsafe { (*handle).last_error() };
+    MaybeBridgeError(err.map(|e| CGrappleError {
+      message: CString::new(e).unwrap_or_default().into_raw(),
+      code: 0xFF
+    }).into())
+  }
+}
+
+#[cfg(feature = "jni")]
+mod jni {
+  use jni::{objects::{JClass, JObject, JValueGen}, sys::{jboolean, jlong, jobject, jstring}, JNIEnv};
+
+  use super::BridgeHandle;
+
+  fn get_handle<'local>(env: &mut JNIEnv<'local>, inst: JObject<'local>) -> *mut BridgeHandle {
+    let handle = env.get_field(inst, "handle", "Lau/grapplerobotics/CanBridge$Handle;").unwrap().l().unwrap();
+    env.get_field(handle, "handle", "J").unwrap().j().unwrap() as *mut BridgeHandle
+  }
+
+  #[no_mangle]
+  pub extern "system" fn Java_au_grapplerobotics_CanBridge_free<'local>(
+    mut _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+  ) {
+    unsafe { drop(Box::from_raw(handle as *mut BridgeHandle)); }
+  }
+
+  #[no_mangle]
+  pub extern "system" fn Java_au_grapplerobotics_CanBridge_stop<'local>(
+    mut env: JNIEnv<'local>,
+    inst: JObject<'local>,
+  ) {
+    let handle = get_handle(&mut env, inst);
+    unsafe { (*handle).stop() }
+  }
+
+  #[no_mangle]
+  pub extern "system" fn Java_au_grapplerobotics_CanBridge_join<'local>(
+    mut env: JNIEnv<'local>,
+    inst: JObject<'local>,
+  ) {
+    let handle = get_handle(&mut env, inst);
+    unsafe { (*handle).join() }
+  }
+
+  #[no_mangle]
+  pub extern "system" fn Java_au_grapplerobotics_CanBridge_isRunning<'local>(
+    mut env: JNIEnv<'local>,
+    inst: JObject<'local>,
+  ) -> jboolean {
+    let handle = get_handle(&mut env, inst);
+    unsafe { (*handle).is_running() as jboolean }
+  }
+
+  #[no_mangle]
+  pub extern "system" fn Java_au_grapplerobotics_CanBridge_getLastError<'local>(
+    mut env: JNIEnv<'local>,
+    inst: JObject<'local>,
+  ) -> jstring {
+    let handle = get_handle(&mut env, inst);
+    match unsafe { (*handle).last_error() } {
+      Some(e) => env.new_string(e).unwrap().into_raw(),
+      None => JObject::null().into_raw(),
+    }
+  }
+
+  #[no_mangle]
+  pub extern "system" fn Java_au_grapplerobotics_CanBridge_getStats<'local>(
+    mut env: JNIEnv<'local>,
+    inst: JObject<'local>,
+  ) -> jobject {
+    let handle = get_handle(&mut env, inst);
+    let stats = unsafe { (*handle).stats() };
+
+    let cls = env.find_class("au/grapplerobotics/CanBridge$Stats").unwrap();
+    env.new_object(cls, "(JJJJJJJJJIJJD)V", &[
+      JValueGen::Long(stats.frames_in as i64),
+      JValueGen::Long(stats.frames_out as i64),
+      JValueGen::Long(stats.bytes_in as i64),
+      JValueGen::Long(stats.bytes_out as i64),
+      JValueGen::Long(stats.session_overflows as i64),
+      JValueGen::Long(stats.decode_errors as i64),
+      JValueGen::Long(stats.send_failures as i64),
+      JValueGen::Long(stats.refused_frames as i64),
+      JValueGen::Long(stats.auth_failures as i64),
+      JValueGen::Int(stats.connected_clients as i32),
+      JValueGen::Long(stats.total_clients as i64),
+      JValueGen::Long(stats.rejected_clients as i64),
+      JValueGen::Double(stats.uptime),
+    ]).unwrap().into_raw()
+  }
+}
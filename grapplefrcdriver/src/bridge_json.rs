@@ -0,0 +1,232 @@
+use bounded_static::IntoBoundedStatic;
+use grapple_frc_msgs::{binmarshal::{BitView, Demarshal}, grapple::{fragments::{FragmentReassembler, FragmentReassemblerRx, FragmentReassemblerTx}, GrappleDeviceMessage, GrappleMessageId, MaybeFragment, MANUFACTURER_GRAPPLE}, MessageId, Validate};
+use serde::{Deserialize, Serialize};
+
+use crate::HAL_CANStreamMessage;
+
+/// The WebSocket sub-protocol a client can ask for to use JSON mode, instead of the `format=json`
+/// query parameter.
+pub const JSON_SUBPROTOCOL: &str = "grapple-json";
+
+/// Sent to clients of the WebSocket bridge in JSON mode, as text messages alongside the usual
+/// BridgeControl messages.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", content = "data")]
+pub enum JsonMessage<'a> {
+  /// A message from a Grapple device, reassembled from its fragments if it was fragmented.
+  Device {
+    id: GrappleMessageId,
+    timestamp: u32,
+    /// The full timestamp, if the client has asked for extended timestamps.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp_us: Option<u64>,
+    message: GrappleDeviceMessage<'a>,
+  },
+  /// Any other frame on the bus, including Grapple frames that couldn't be decoded.
+  Raw {
+    id: u32,
+    timestamp: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp_us: Option<u64>,
+    data: &'a [u8],
+  },
+  /// A JsonCommand from the client could not be sent.
+  CommandError(String),
+}
+
+/// Sent by clients of the WebSocket bridge in JSON mode, as text messages alongside the usual
+/// BridgeControl messages.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum JsonCommand<'a> {
+  /// Send a message to a Grapple device, fragmenting it if it doesn't fit in a single frame.
+  SendDevice {
+    device_id: u8,
+    #[serde(borrow)]
+    message: GrappleDeviceMessage<'a>,
+  },
+  /// Send a single frame as-is.
+  SendRaw { id: u32, data: Vec<u8> },
+}
+
+impl JsonCommand<'_> {
+  /// Whether a text message from the client is a JsonCommand, rather than a BridgeControl message.
+  pub fn is_command(data: &[u8]) -> bool {
+    #[derive(Deserialize)]
+    struct Tagged {
+      #[serde(rename = "type")]
+      ty: String,
+    }
+
+    matches!(serde_json::from_slice::<Tagged>(data), Ok(t) if t.ty == "SendDevice" || t.ty == "SendRaw")
+  }
+}
+
+/// Converts frames to and from JSON for a single client. Each client needs its own, as fragments
+/// are reassembled per client.
+pub struct JsonCodec {
+  reassembler_rx: FragmentReassemblerRx,
+  reassembler_tx: FragmentReassemblerTx,
+}
+
+impl Default for JsonCodec {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl JsonCodec {
+  pub fn new() -> Self {
+    let (rx, tx) = FragmentReassembler::new(1000, 8).split();
+    Self { reassembler_rx: rx, reassembler_tx: tx }
+  }
+
+  /// Convert a frame read from the HAL to a JsonMessage. Returns None for fragments of a message
+  /// that hasn't been completely received yet.
+  pub fn decode(&mut self, msg: &HAL_CANStreamMessage, timestamp_us: Option<u64>) -> Option<String> {
+    let len = (msg.dataSize as usize).min(8);
+    let message = self.decode_frame(msg.messageID, msg.timeStamp, timestamp_us, &msg.data[0..len])?;
+    serde_json::to_string(&message).ok()
+  }
+
+  /// Decode a single frame, as a Device message if it's from a Grapple device or a Raw message
+  /// otherwise. Returns None for fragments of a message that hasn't been completely received yet.
+  pub fn decode_frame<'a>(&mut self, id: u32, timestamp: u32, timestamp_us: Option<u64>, data: &'a [u8]) -> Option<JsonMessage<'a>> {
+    let message_id: MessageId = id.into();
+    let len = data.len().min(8);
+
+    if message_id.manufacturer == MANUFACTURER_GRAPPLE {
+      // Decode from a zero-padded frame, the same as GrappleCanDriver
+      let mut padded = [0u8; 8];
+      padded[0..len].copy_from_slice(&data[0..len]);
+      let mut storage = Vec::with_capacity(128);
+
+      let decoded = MaybeFragment::read(&mut BitView::new(&padded), message_id.into())
+        .and_then(|m| self.reassembler_rx.defragment(timestamp as i64, &message_id, m, &mut storage));
+
+      match decoded {
+        Ok(Some((id, message))) => return Some(JsonMessage::Device { id, timestamp, timestamp_us, message: message.into_static() }),
+        Ok(None) => return None,
+        // Fall back to a raw frame, so it can still be seen
+        Err(_) => (),
+      }
+    }
+
+    Some(JsonMessage::Raw { id, timestamp, timestamp_us, data: &data[0..len] })
+  }
+
+  /// Decode a JsonCommand, validating it and encoding it as the (id, data) frames that need to be sent.
+  pub fn encode_command(&mut self, data: &[u8]) -> anyhow::Result<Vec<(u32, Vec<u8>)>> {
+    match serde_json::from_slice::<JsonCommand>(data)? {
+      JsonCommand::SendDevice { device_id, message } => {
+        message.validate().map_err(|e| anyhow::anyhow!("Invalid message: {}", e))?;
+
+        let mut frames = vec![];
+        self.reassembler_tx.maybe_fragment(device_id, message, &mut |id, buf| {
+          frames.push((id.into(), buf.to_vec()));
+        }).map_err(|e| anyhow::anyhow!("Could not encode message: {:?}", e))?;
+        Ok(frames)
+      },
+      JsonCommand::SendRaw { id, data } => {
+        if data.len() > 8 {
+          anyhow::bail!("CAN frames can't be longer than 8 bytes");
+        }
+        Ok(vec![(id, data)])
+      },
+    }
+  }
+
+  /// A JsonMessage telling the client its command failed.
+  pub fn command_error(error: &anyhow::Error) -> String {
+    serde_json::to_string(&JsonMessage::CommandError(error.to_string())).unwrap()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use bounded_static::IntoBoundedStatic;
+  use grapple_frc_msgs::grapple::{lasercan::LaserCanMessage, mitocandria::{MitocandriaChannelStatus, MitocandriaMessage, MitocandriaStatusFrame}, GrappleDeviceMessage, Request};
+  use serde_json::json;
+
+  use crate::bridge_protocol::BridgeControl;
+
+  use super::{JsonCodec, JsonCommand, JsonMessage};
+
+  const DEVICE_ID: u8 = 7;
+
+  fn led_threshold(distance_mm: u16) -> GrappleDeviceMessage<'static> {
+    GrappleDeviceMessage::DistanceSensor(LaserCanMessage::SetLedThreshold(Request::Request(distance_mm)))
+  }
+
+  // Big enough to be split over several frames
+  fn status_frame() -> GrappleDeviceMessage<'static> {
+    GrappleDeviceMessage::PowerDistributionModule(MitocandriaMessage::StatusFrame(MitocandriaStatusFrame {
+      channels: [
+        MitocandriaChannelStatus::NonSwitchable { current: 1000 },
+        MitocandriaChannelStatus::Switchable { enabled: true, current: 2000 },
+        MitocandriaChannelStatus::Switchable { enabled: false, current: 0 },
+        MitocandriaChannelStatus::Switchable { enabled: true, current: 3000 },
+        MitocandriaChannelStatus::Adjustable { enabled: true, voltage: 12000, voltage_setpoint: 12000, current: 4000 },
+      ],
+    }))
+  }
+
+  fn send_device(message: &GrappleDeviceMessage) -> Vec<u8> {
+    serde_json::to_vec(&json!({ "type": "SendDevice", "data": { "device_id": DEVICE_ID, "message": message } })).unwrap()
+  }
+
+  // Encode a message the way a client would send it, then decode the frames the way a device's
+  // frames would be.
+  fn round_trip(message: &GrappleDeviceMessage) -> (usize, Vec<GrappleDeviceMessage<'static>>) {
+    let frames = JsonCodec::new().encode_command(&send_device(message)).unwrap();
+    let mut codec = JsonCodec::new();
+    let decoded = frames.iter().filter_map(|(id, data)| match codec.decode_frame(*id, 0, None, data) {
+      Some(JsonMessage::Device { message, .. }) => Some(message.into_static()),
+      None => None,
+      other => panic!("Expected a device message, got {:?}", other),
+    }).collect();
+    (frames.len(), decoded)
+  }
+
+  #[test]
+  fn single_frame_message() {
+    assert_eq!(round_trip(&led_threshold(100)), (1, vec![led_threshold(100)]));
+  }
+
+  #[test]
+  fn fragmented_message() {
+    let (frames, decoded) = round_trip(&status_frame());
+    assert!(frames > 1);
+    // Only the last fragment completes the message
+    assert_eq!(decoded, vec![status_frame()]);
+  }
+
+  #[test]
+  fn non_grapple_frame_is_raw() {
+    // Manufacturer 5 (REV)
+    let message = JsonCodec::new().decode_frame(0x0205_1234, 10, Some(10_000), &[1, 2, 3]);
+    assert_eq!(message, Some(JsonMessage::Raw { id: 0x0205_1234, timestamp: 10, timestamp_us: Some(10_000), data: &[1, 2, 3] }));
+  }
+
+  #[test]
+  fn invalid_device_message_is_rejected() {
+    let error = JsonCodec::new().encode_command(&send_device(&led_threshold(10))).unwrap_err();
+    assert!(error.to_string().starts_with("Invalid message"), "{}", error);
+  }
+
+  #[test]
+  fn raw_frames() {
+    let mut codec = JsonCodec::new();
+    let command = |data: Vec<u8>| serde_json::to_vec(&json!({ "type": "SendRaw", "data": { "id": 0x123, "data": data } })).unwrap();
+    assert_eq!(codec.encode_command(&command(vec![1; 8])).unwrap(), vec![(0x123, vec![1; 8])]);
+    assert!(codec.encode_command(&command(vec![1; 9])).is_err());
+  }
+
+  #[test]
+  fn commands_and_control_messages() {
+    assert!(JsonCommand::is_command(&send_device(&led_threshold(100))));
+    assert!(JsonCommand::is_command(br#"{"type":"SendRaw","data":{"id":1,"data":[]}}"#));
+    assert!(!JsonCommand::is_command(&serde_json::to_vec(&BridgeControl::SetFilters(vec![])).unwrap()));
+    assert!(!JsonCommand::is_command(b"not json"));
+  }
+}
@@ -0,0 +1,308 @@
+use std::borrow::Cow;
+
+use grapple_frc_msgs::{binmarshal::{BitView, BitWriter, Demarshal, LengthTaggedPayload, Marshal, VecBitWriter}, bridge::BridgedCANMessage, MessageId};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "pyo3")]
+use pyo3::prelude::*;
+
+use crate::{can::CanFrame, HAL_CANStreamMessage};
+
+/// TCP bridge frames are prefixed by a little-endian u16 header. The lower 14 bits are the length
+/// of the frame. If the top bit is set, the frame is a JSON encoded BridgeControl message instead
+/// of a BridgedCANMessage.
+pub const TCP_CONTROL_FLAG: u16 = 0x8000;
+/// If set in a TCP frame header, the frame is a batch (see pack_batches).
+pub const TCP_BATCH_FLAG: u16 = 0x4000;
+pub const TCP_LENGTH_MASK: u16 = 0x3FFF;
+/// The largest payload that fits in a single TCP frame.
+pub const MAX_FRAME_LENGTH: usize = TCP_LENGTH_MASK as usize;
+
+const CAN_ID_MASK: u32 = 0x1FFFFFFF;
+
+/// The version of the bridge protocol spoken by this crate. Bump this when a change would confuse
+/// clients written for an older version. Features that clients opt in to don't need a bump, just
+/// a new BridgeFeature.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The version of grapple-frc-msgs the bridge is built against, which determines the messages it
+/// understands. Keep this in step with Cargo.toml.
+pub const GRAPPLE_FRC_MSGS_VERSION: &str = "2025.0.11";
+
+/// Matches a CAN frame if `(frame_id & mask) == (id & mask)`. A mask of 0 matches every frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "pyo3", pyclass(get_all, set_all, eq))]
+#[repr(C)]
+pub struct CanFilter {
+  pub id: u32,
+  pub mask: u32,
+}
+
+impl CanFilter {
+  pub const ALL: CanFilter = CanFilter { id: 0, mask: 0 };
+
+  /// Match all frames from the given manufacturer, e.g. grapple_frc_msgs::grapple::MANUFACTURER_GRAPPLE
+  pub fn manufacturer(manufacturer: u8) -> Self {
+    Self::from_fields(MessageId { device_type: 0, manufacturer, api_class: 0, api_index: 0, device_id: 0 }, MessageId { device_type: 0, manufacturer: 0xFF, api_class: 0, api_index: 0, device_id: 0 })
+  }
+
+  /// Match all frames for the given device type, e.g. grapple_frc_msgs::grapple::DEVICE_TYPE_DISTANCE_SENSOR
+  pub fn device_type(device_type: u8) -> Self {
+    Self::from_fields(MessageId { device_type, manufacturer: 0, api_class: 0, api_index: 0, device_id: 0 }, MessageId { device_type: 0x1F, manufacturer: 0, api_class: 0, api_index: 0, device_id: 0 })
+  }
+
+  fn from_fields(id: MessageId, mask: MessageId) -> Self {
+    let mask: u32 = mask.into();
+    Self { id: Into::<u32>::into(id) & mask, mask }
+  }
+
+  pub fn matches(&self, id: u32) -> bool {
+    (id & self.mask & CAN_ID_MASK) == (self.id & self.mask & CAN_ID_MASK)
+  }
+
+  /// Returns true if the frame matches any of the filters, or if there are no filters.
+  pub fn any_match(filters: &[CanFilter], id: u32) -> bool {
+    filters.is_empty() || filters.iter().any(|f| f.matches(id))
+  }
+
+  /// The narrowest single filter that matches every frame matched by any of `filters`. The HAL
+  /// only takes one filter per stream session, so this is used to narrow the session before the
+  /// individual filters are checked.
+  pub fn covering(filters: &[CanFilter]) -> CanFilter {
+    let Some(first) = filters.first() else { return CanFilter::ALL };
+    let mask = filters.iter().fold(first.mask & CAN_ID_MASK, |mask, f| mask & f.mask & !(f.id ^ first.id));
+    CanFilter { id: first.id & mask, mask }
+  }
+
+  /// Parse a comma separated list of `id:mask` filters, where each of id and mask is either
+  /// decimal or 0x-prefixed hex, e.g. `0x60000:0xFF0000`.
+  pub fn parse_list(s: &str) -> anyhow::Result<Vec<CanFilter>> {
+    s.split(',').filter(|f| !f.trim().is_empty()).map(|f| {
+      let (id, mask) = f.split_once(':').ok_or_else(|| anyhow::anyhow!("Invalid filter '{}', expected id:mask", f))?;
+      Ok(CanFilter { id: parse_u32(id)?, mask: parse_u32(mask)? })
+    }).collect()
+  }
+}
+
+#[cfg(feature = "pyo3")]
+#[pymethods]
+impl CanFilter {
+  #[new]
+  fn py_new(id: u32, mask: u32) -> Self {
+    Self { id, mask }
+  }
+
+  #[staticmethod]
+  #[pyo3(name = "manufacturer")]
+  fn manufacturer_py(manufacturer: u8) -> Self {
+    Self::manufacturer(manufacturer)
+  }
+
+  #[staticmethod]
+  #[pyo3(name = "device_type")]
+  fn device_type_py(device_type: u8) -> Self {
+    Self::device_type(device_type)
+  }
+
+  fn __repr__(&self) -> String {
+    format!("CanFilter {{ id: 0x{:08X}, mask: 0x{:08X} }}", self.id, self.mask)
+  }
+}
+
+fn parse_u32(s: &str) -> anyhow::Result<u32> {
+  let s = s.trim();
+  Ok(match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+    Some(hex) => u32::from_str_radix(hex, 16)?,
+    None => s.parse()?,
+  })
+}
+
+/// Control messages exchanged between a client and the bridge. These are JSON encoded, and sent as
+/// a text message on the WebSocket bridge, or as a flagged frame (see TCP_CONTROL_FLAG) on the TCP bridge.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum BridgeControl {
+  /// Only forward frames matching at least one of the filters. An empty list forwards every frame.
+  SetFilters(Vec<CanFilter>),
+  /// Pack all frames read from the bus at once into a single batch (see pack_batches), instead
+  /// of sending one message per frame. The bridge echoes this message back once it takes effect,
+  /// so WebSocket clients know which binary messages are batches.
+  SetBatching(bool),
+  /// Sent by the bridge when the client's stream session has overflowed because frames arrived
+  /// faster than they could be sent, so some frames before the next one received were lost.
+  /// Contains the number of times the client's session has overflowed.
+  SessionOverflow(u64),
+  /// Sent by a client to authenticate with a bridge that requires a token. Until the client has
+  /// authenticated, it receives no frames and any frames it sends are refused.
+  Authenticate(String),
+  /// Sent by the bridge in reply to Authenticate. If false, the bridge will disconnect the client.
+  AuthenticationResult(bool),
+  /// Sent by a client to ask for a ClockSync straight away. Contains the client's own time, in
+  /// any units, which is returned in the reply so the client can measure the round trip.
+  RequestClockSync(u64),
+  /// Sent by the bridge once a client that has said Hello can use it, every
+  /// bridge_session::CLOCK_SYNC_INTERVAL after that, and in reply to RequestClockSync.
+  ClockSync(ClockSync),
+  /// Prefix every frame sent to the client with a 64-bit timestamp (see encode_extended_stream_message),
+  /// instead of relying on the BridgedCANMessage's wrapping 32-bit millisecond timestamp. The bridge
+  /// echoes this message back once it takes effect.
+  SetExtendedTimestamps(bool),
+  /// Sent by a client to say which protocol version it speaks. The bridge replies with Welcome.
+  /// Clients that never send Hello (e.g. older versions of GrappleHook) only ever receive frames
+  /// and replies to their own control messages, never unsolicited ones like SessionOverflow.
+  Hello(ClientHello),
+  /// Sent by the bridge in reply to Hello.
+  Welcome(ServerHello),
+}
+
+/// Optional parts of the bridge protocol, advertised by the bridge in its Welcome.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BridgeFeature {
+  /// SetFilters
+  Filters,
+  /// SetBatching
+  Batching,
+  /// SetExtendedTimestamps
+  ExtendedTimestamps,
+  /// ClockSync and RequestClockSync
+  ClockSync,
+  /// SessionOverflow
+  SessionOverflow,
+  /// Authenticate
+  Authentication,
+  /// JSON mode on the WebSocket bridge (see bridge_json)
+  JsonMode,
+  /// CAN FD frames, longer than 8 bytes. Not yet supported by any bridge.
+  CanFd,
+  /// A feature added after this version of the crate.
+  #[serde(other)]
+  Unknown,
+}
+
+impl BridgeFeature {
+  /// The features supported by the bridges in this crate.
+  pub const SUPPORTED: &'static [BridgeFeature] = &[
+    BridgeFeature::Filters,
+    BridgeFeature::Batching,
+    BridgeFeature::ExtendedTimestamps,
+    BridgeFeature::ClockSync,
+    BridgeFeature::SessionOverflow,
+    BridgeFeature::Authentication,
+    BridgeFeature::JsonMode,
+  ];
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientHello {
+  pub protocol_version: u32,
+  /// The name and version of the client, e.g. "GrappleHook 2025.1.0", for the bridge's logs.
+  #[serde(default)]
+  pub client: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerHello {
+  /// The protocol version the bridge will speak to this client, which is the lower of the
+  /// client's and the bridge's.
+  pub protocol_version: u32,
+  /// The version of libgrapplefrc running the bridge.
+  pub server_version: String,
+  pub grapple_frc_msgs_version: String,
+  pub features: Vec<BridgeFeature>,
+  /// Whether the client must send Authenticate before it can use the bridge.
+  pub auth_required: bool,
+}
+
+/// The bridge's clocks at the time it was sent, so clients can convert frame timestamps to their own clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClockSync {
+  /// The time from the RequestClockSync this is a reply to, if it is one.
+  pub client_time: Option<u64>,
+  /// The roboRIO's FPGA time in microseconds. This is the clock used by extended timestamps, and
+  /// by the roboRIO's logs.
+  pub fpga_time_us: u64,
+  /// Microseconds since the unix epoch, according to the roboRIO. This is only as accurate as the
+  /// roboRIO's clock, which is set by the Driver Station.
+  pub unix_time_us: u64,
+}
+
+impl BridgeControl {
+  pub fn from_json(data: &[u8]) -> anyhow::Result<Self> {
+    Ok(serde_json::from_slice(data)?)
+  }
+
+  pub fn to_json(&self) -> String {
+    serde_json::to_string(self).unwrap()
+  }
+}
+
+/// Encode a frame read from the HAL as a BridgedCANMessage.
+pub fn encode_stream_message(msg: &HAL_CANStreamMessage) -> Vec<u8> {
+  encode_message(msg.messageID, msg.timeStamp, &msg.data[0..msg.dataSize as usize])
+}
+
+/// Encode a frame read from the HAL as a big-endian u64 timestamp in microseconds on the FPGA
+/// clock, followed by the BridgedCANMessage. `fpga_time_us` is the FPGA time when the frame was read.
+pub fn encode_extended_stream_message(msg: &HAL_CANStreamMessage, fpga_time_us: u64) -> Vec<u8> {
+  let mut buf = extend_timestamp(msg.timeStamp, fpga_time_us).to_be_bytes().to_vec();
+  buf.extend(encode_stream_message(msg));
+  buf
+}
+
+/// The HAL timestamps frames in milliseconds on the FPGA clock, truncated to 32 bits so they wrap
+/// every ~49 days. Given the current FPGA time, recover the full timestamp in microseconds.
+pub fn extend_timestamp(timestamp_ms: u32, fpga_time_us: u64) -> u64 {
+  let now_ms = fpga_time_us / 1000;
+  let age_ms = (now_ms as u32).wrapping_sub(timestamp_ms) as u64;
+  now_ms.saturating_sub(age_ms) * 1000
+}
+
+/// Encode a frame as a BridgedCANMessage.
+pub fn encode_message(id: u32, timestamp: u32, data: &[u8]) -> Vec<u8> {
+  let message_id: MessageId = id.into();
+  let bridged_msg = BridgedCANMessage { id: message_id, timestamp, data: Cow::Borrowed(Into::<&LengthTaggedPayload<_>>::into(data)).into() };
+
+  let mut write_buf = VecBitWriter::new();
+  bridged_msg.write(&mut write_buf, ()).ok();
+  write_buf.slice().to_vec()
+}
+
+/// Decode a BridgedCANMessage.
+pub fn decode_message(data: &[u8]) -> anyhow::Result<CanFrame> {
+  let bridged_msg = BridgedCANMessage::read(&mut BitView::new(data), ()).map_err(|e| anyhow::anyhow!("Invalid Message! {:?}", e))?;
+  let payload = bridged_msg.data.as_ref();
+  Ok(CanFrame { id: bridged_msg.id.into(), timestamp: bridged_msg.timestamp, data: payload.as_ref().to_vec() })
+}
+
+/// Split a batch (see pack_batches) back into its encoded BridgedCANMessages.
+pub fn unpack_batch(mut batch: &[u8]) -> impl Iterator<Item = &[u8]> {
+  std::iter::from_fn(move || {
+    if batch.len() < 2 {
+      return None;
+    }
+    let len = (u16::from_le_bytes([ batch[0], batch[1] ]) as usize).min(batch.len() - 2);
+    let (frame, rest) = batch[2..].split_at(len);
+    batch = rest;
+    Some(frame)
+  })
+}
+
+/// Pack encoded BridgedCANMessages into batches, each of which is a series of frames prefixed by
+/// their little-endian u16 length, no longer than MAX_FRAME_LENGTH.
+pub fn pack_batches<I: IntoIterator<Item = Vec<u8>>>(frames: I) -> Vec<Vec<u8>> {
+  let mut batches = vec![];
+  let mut batch: Vec<u8> = vec![];
+
+  for frame in frames {
+    if !batch.is_empty() && batch.len() + frame.len() + 2 > MAX_FRAME_LENGTH {
+      batches.push(std::mem::take(&mut batch));
+    }
+    batch.extend_from_slice(&u16::to_le_bytes(frame.len() as u16));
+    batch.extend_from_slice(&frame);
+  }
+
+  if !batch.is_empty() {
+    batches.push(batch);
+  }
+  batches
+}
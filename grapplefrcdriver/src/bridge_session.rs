@@ -0,0 +1,337 @@
+use std::{sync::{atomic::Ordering, Arc}, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+
+use grapple_frc_msgs::{binmarshal::{BitView, Demarshal}, bridge::BridgedCANMessage};
+
+use crate::{bridge_config::BridgeConfig, bridge_json::JsonCodec, bridge_protocol::{encode_extended_stream_message, encode_stream_message, extend_timestamp, BridgeControl, BridgeFeature, CanFilter, ClientHello, ClockSync, ServerHello, GRAPPLE_FRC_MSGS_VERSION, PROTOCOL_VERSION}, bridge_stats::BridgeStats, can::{bus, CanStream}, HAL_CANStreamMessage};
+
+/// How long a client has to authenticate before it is disconnected, if the bridge requires it.
+pub const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often the bridge sends each client a ClockSync, so it can follow drift between the clocks.
+pub const CLOCK_SYNC_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The longest a session will go between polls when the bus is idle.
+pub const MAX_IDLE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// Polls quickly while frames are arriving, and backs off while the bus is idle. If a read fills
+// more than half the session, the session is polled again straight away so it doesn't overflow.
+struct AdaptivePoll {
+  min: Duration,
+  max: Duration,
+  current: Duration,
+}
+
+impl AdaptivePoll {
+  fn new(min: Duration) -> Self {
+    Self { min, max: MAX_IDLE_POLL_INTERVAL.max(min), current: min }
+  }
+
+  fn update(&mut self, n_read: usize, capacity: usize, overflowed: bool) {
+    self.current = if overflowed || n_read * 2 >= capacity {
+      Duration::ZERO
+    } else if n_read > 0 {
+      self.min
+    } else {
+      (self.current * 2).clamp(self.min, self.max)
+    };
+  }
+}
+
+/// A stream session on the bus for a single bridge client, filtered down to the frames the client
+/// has asked for, along with the other options the client has negotiated.
+pub struct BridgeSession {
+  client: String,
+  stream: Box<dyn CanStream>,
+  filters: Vec<CanFilter>,
+  buffer: Vec<HAL_CANStreamMessage>,
+  batching: bool,
+  stats: Arc<BridgeStats>,
+  poll: AdaptivePoll,
+  overflows: u64,
+  overflow_pending: bool,
+  auth_token: Option<String>,
+  authenticated: bool,
+  auth_failed: bool,
+  opened: Instant,
+  read_only: bool,
+  transmit_allow_list: Vec<CanFilter>,
+  refused_logged: bool,
+  extended_timestamps: bool,
+  last_clock_sync: Option<Instant>,
+  hello: Option<ClientHello>,
+}
+
+fn fpga_time_us() -> u64 {
+  bus().time_us()
+}
+
+/// The bridge's current clocks.
+pub fn clock_sync(client_time: Option<u64>) -> ClockSync {
+  ClockSync {
+    client_time,
+    fpga_time_us: fpga_time_us(),
+    unix_time_us: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_micros() as u64).unwrap_or(0),
+  }
+}
+
+// Compare tokens without bailing out early, so the token can't be guessed from response times.
+pub(crate) fn tokens_match(a: &str, b: &str) -> bool {
+  a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+impl BridgeSession {
+  /// Open a stream session that can hold `config.session_depth` frames between reads. `client`
+  /// identifies the client in log messages, e.g. its address.
+  pub fn open(config: &BridgeConfig, client: String, filters: Vec<CanFilter>, stats: Arc<BridgeStats>) -> anyhow::Result<Self> {
+    let covering = CanFilter::covering(&filters);
+    Ok(Self {
+      client,
+      stream: bus().open_stream(covering, config.session_depth)?,
+      filters,
+      buffer: vec![HAL_CANStreamMessage { ..Default::default() }; config.session_depth as usize],
+      batching: false,
+      stats,
+      poll: AdaptivePoll::new(config.poll_interval),
+      overflows: 0,
+      overflow_pending: false,
+      auth_token: config.auth_token.clone(),
+      authenticated: config.auth_token.is_none(),
+      auth_failed: false,
+      opened: Instant::now(),
+      read_only: config.read_only,
+      transmit_allow_list: config.transmit_allow_list.clone(),
+      refused_logged: false,
+      extended_timestamps: false,
+      last_clock_sync: None,
+      hello: None,
+    })
+  }
+
+  /// Check the client's token against the one required by the bridge.
+  pub fn authenticate(&mut self, token: &str) -> bool {
+    self.authenticated = match &self.auth_token {
+      Some(expected) => tokens_match(expected, token),
+      None => true,
+    };
+    if !self.authenticated {
+      log::warn!(client = self.client.as_str(); "Client sent an invalid token");
+      self.auth_failed = true;
+      self.stats.auth_failures.fetch_add(1, Ordering::Relaxed);
+    }
+    self.authenticated
+  }
+
+  /// Returns an error if the client should be disconnected, because it failed to authenticate or
+  /// didn't authenticate in time.
+  pub fn check_access(&self) -> anyhow::Result<()> {
+    if self.auth_failed {
+      anyhow::bail!("Authentication failed");
+    }
+    if !self.authenticated && self.opened.elapsed() > AUTH_TIMEOUT {
+      self.stats.auth_failures.fetch_add(1, Ordering::Relaxed);
+      anyhow::bail!("Authentication timed out");
+    }
+    Ok(())
+  }
+
+  /// How long to wait before reading from the session again, based on how busy it has been.
+  pub fn next_poll_delay(&self) -> Duration {
+    self.poll.current
+  }
+
+  /// If the session has overflowed since this was last called, returns a SessionOverflow message
+  /// to let the client know it has missed frames. Only clients that have said hello are told.
+  pub fn take_overflow_notification(&mut self) -> Option<BridgeControl> {
+    let pending = std::mem::take(&mut self.overflow_pending);
+    (pending && self.hello.is_some()).then_some(BridgeControl::SessionOverflow(self.overflows))
+  }
+
+  /// Returns a ClockSync message if it's time to send one. The first is sent as soon as the client
+  /// has said hello and authenticated.
+  pub fn take_clock_sync(&mut self) -> Option<BridgeControl> {
+    if self.hello.is_none() || !self.authenticated || self.last_clock_sync.is_some_and(|t| t.elapsed() < CLOCK_SYNC_INTERVAL) {
+      return None;
+    }
+    self.last_clock_sync = Some(Instant::now());
+    Some(BridgeControl::ClockSync(clock_sync(None)))
+  }
+
+  /// The client's Hello, if it has sent one.
+  pub fn hello(&self) -> Option<&ClientHello> {
+    self.hello.as_ref()
+  }
+
+  fn welcome(&mut self, hello: ClientHello) -> BridgeControl {
+    let welcome = ServerHello {
+      protocol_version: hello.protocol_version.min(PROTOCOL_VERSION),
+      server_version: env!("CARGO_PKG_VERSION").to_owned(),
+      grapple_frc_msgs_version: GRAPPLE_FRC_MSGS_VERSION.to_owned(),
+      features: BridgeFeature::SUPPORTED.to_vec(),
+      auth_required: self.auth_token.is_some(),
+    };
+    log::info!(client = self.client.as_str(), name = hello.client.as_deref().unwrap_or("(unnamed)"), protocol_version = hello.protocol_version; "Client says hello");
+    self.hello = Some(hello);
+    BridgeControl::Welcome(welcome)
+  }
+
+  pub fn filters(&self) -> &[CanFilter] {
+    &self.filters
+  }
+
+  /// How many times the session has overflowed, losing frames that weren't read in time.
+  pub fn overflows(&self) -> u64 {
+    self.overflows
+  }
+
+  /// Identifies the client in log messages.
+  pub fn client(&self) -> &str {
+    &self.client
+  }
+
+  /// Whether the client has asked for frames to be sent in batches.
+  pub fn batching(&self) -> bool {
+    self.batching
+  }
+
+  /// Decode a JSON control message from the client and apply it, returning a message to send back
+  /// to it, if any.
+  pub fn handle_control_json(&mut self, data: &[u8]) -> anyhow::Result<Option<BridgeControl>> {
+    let control = BridgeControl::from_json(data).inspect_err(|_| {
+      self.stats.decode_errors.fetch_add(1, Ordering::Relaxed);
+    })?;
+    self.handle_control(control)
+  }
+
+  /// Apply a control message from the client, returning a message to send back to it, if any.
+  pub fn handle_control(&mut self, control: BridgeControl) -> anyhow::Result<Option<BridgeControl>> {
+    match control {
+      BridgeControl::Authenticate(token) => Ok(Some(BridgeControl::AuthenticationResult(self.authenticate(&token)))),
+      BridgeControl::Hello(hello) => Ok(Some(self.welcome(hello))),
+      _ if !self.authenticated => anyhow::bail!("Not authenticated"),
+      BridgeControl::SetFilters(filters) => {
+        self.set_filters(filters)?;
+        Ok(None)
+      },
+      BridgeControl::SetBatching(batching) => {
+        self.batching = batching;
+        Ok(Some(BridgeControl::SetBatching(batching)))
+      },
+      BridgeControl::RequestClockSync(client_time) => Ok(Some(BridgeControl::ClockSync(clock_sync(Some(client_time))))),
+      BridgeControl::SetExtendedTimestamps(extended) => {
+        self.extended_timestamps = extended;
+        Ok(Some(BridgeControl::SetExtendedTimestamps(extended)))
+      },
+      // Only ever sent by the bridge
+      BridgeControl::SessionOverflow(_) | BridgeControl::AuthenticationResult(_) | BridgeControl::ClockSync(_) | BridgeControl::Welcome(_) => Ok(None),
+    }
+  }
+
+  /// Change the filters, reopening the stream session so the bus can discard frames we don't want
+  /// before they reach us. Frames buffered in the old session are dropped.
+  pub fn set_filters(&mut self, filters: Vec<CanFilter>) -> anyhow::Result<()> {
+    let covering = CanFilter::covering(&filters);
+    self.stream = bus().open_stream(covering, self.buffer.len() as u32)?;
+    self.filters = filters;
+    Ok(())
+  }
+
+  /// Drain the stream session, returning the frames that match the filters.
+  pub fn read(&mut self) -> impl Iterator<Item = &HAL_CANStreamMessage> {
+    let (n_read, overflowed) = self.stream.read(&mut self.buffer);
+    if overflowed {
+      self.overflows += 1;
+      self.overflow_pending = true;
+      self.stats.session_overflows.fetch_add(1, Ordering::Relaxed);
+    }
+    self.poll.update(n_read, self.buffer.len(), overflowed);
+
+    let filters = &self.filters;
+    self.buffer[0..n_read].iter().filter(move |msg| CanFilter::any_match(filters, msg.messageID))
+  }
+
+  /// Drain the stream session, returning the frames that match the filters encoded as BridgedCANMessages,
+  /// with extended timestamps if the client has asked for them. Nothing is returned until the client
+  /// has authenticated.
+  pub fn read_encoded(&mut self) -> Vec<Vec<u8>> {
+    let authenticated = self.authenticated;
+    let extended_timestamps = self.extended_timestamps;
+    let now_us = if extended_timestamps { fpga_time_us() } else { 0 };
+
+    let frames: Vec<Vec<u8>> = self.read().filter(|_| authenticated).map(|msg| match extended_timestamps {
+      true => encode_extended_stream_message(msg, now_us),
+      false => encode_stream_message(msg),
+    }).collect();
+    self.stats.frames_out.fetch_add(frames.len() as u64, Ordering::Relaxed);
+    self.stats.bytes_out.fetch_add(frames.iter().map(|f| f.len() as u64).sum(), Ordering::Relaxed);
+    frames
+  }
+
+  /// Drain the stream session, returning the frames that match the filters as JsonMessages. Nothing
+  /// is returned until the client has authenticated.
+  pub fn read_json(&mut self, codec: &mut JsonCodec) -> Vec<String> {
+    let authenticated = self.authenticated;
+    let extended_timestamps = self.extended_timestamps;
+    let now_us = if extended_timestamps { fpga_time_us() } else { 0 };
+
+    let messages: Vec<String> = self.read().filter(|_| authenticated).filter_map(|msg| {
+      codec.decode(msg, extended_timestamps.then(|| extend_timestamp(msg.timeStamp, now_us)))
+    }).collect();
+    self.stats.frames_out.fetch_add(messages.len() as u64, Ordering::Relaxed);
+    self.stats.bytes_out.fetch_add(messages.iter().map(|m| m.len() as u64).sum(), Ordering::Relaxed);
+    messages
+  }
+
+  /// Decode a BridgedCANMessage from the client and send it on the bus. Frames the client isn't
+  /// allowed to send are dropped, and an error is returned if the client hasn't authenticated.
+  pub fn send_encoded(&mut self, data: &[u8]) -> anyhow::Result<()> {
+    if !self.authenticated {
+      anyhow::bail!("Not authenticated");
+    }
+
+    let bridged_msg = BridgedCANMessage::read(&mut BitView::new(data), ()).map_err(|e| {
+      self.stats.decode_errors.fetch_add(1, Ordering::Relaxed);
+      anyhow::anyhow!("Invalid Message! {:?}", e)
+    })?;
+
+    let r = bridged_msg.data.as_ref();
+    self.send_frame(bridged_msg.id.into(), r.as_ref())?;
+    self.stats.bytes_in.fetch_add(data.len() as u64, Ordering::Relaxed);
+    Ok(())
+  }
+
+  /// Decode a JsonCommand from the client, and send the frames it encodes to on the bus. Frames the
+  /// client isn't allowed to send are dropped, and an error is returned if the client hasn't
+  /// authenticated or the command is invalid.
+  pub fn send_json(&mut self, codec: &mut JsonCodec, data: &[u8]) -> anyhow::Result<()> {
+    if !self.authenticated {
+      anyhow::bail!("Not authenticated");
+    }
+
+    let frames = codec.encode_command(data).inspect_err(|_| {
+      self.stats.decode_errors.fetch_add(1, Ordering::Relaxed);
+    })?;
+    for (id, frame) in frames {
+      self.send_frame(id, &frame)?;
+    }
+    self.stats.bytes_in.fetch_add(data.len() as u64, Ordering::Relaxed);
+    Ok(())
+  }
+
+  // Send a frame from the client on the bus, unless the client isn't allowed to send it.
+  fn send_frame(&mut self, id: u32, data: &[u8]) -> anyhow::Result<()> {
+    if self.read_only || !CanFilter::any_match(&self.transmit_allow_list, id) {
+      self.stats.refused_frames.fetch_add(1, Ordering::Relaxed);
+      if !std::mem::replace(&mut self.refused_logged, true) {
+        log::warn!(client = self.client.as_str(), id = format!("0x{:08X}", id).as_str(); "Refusing frame from client, further refused frames will not be logged");
+      }
+      return Ok(());
+    }
+
+    self.stream.send(id, data).inspect_err(|_| {
+      self.stats.send_failures.fetch_add(1, Ordering::Relaxed);
+    })?;
+
+    self.stats.frames_in.fetch_add(1, Ordering::Relaxed);
+    Ok(())
+  }
+}
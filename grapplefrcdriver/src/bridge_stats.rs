@@ -0,0 +1,137 @@
+use std::{sync::{atomic::{AtomicU64, AtomicUsize, Ordering}, Arc, Mutex, Weak}, time::Instant};
+
+use serde::Serialize;
+
+#[cfg(feature = "pyo3")]
+use pyo3::prelude::*;
+
+// Every bridge that's currently running, so they can all be reported on by the stats endpoint.
+static REGISTRY: Mutex<Vec<Weak<BridgeStats>>> = Mutex::new(Vec::new());
+
+/// Live traffic counters for a single bridge, shared between the bridge and all of its clients.
+pub struct BridgeStats {
+  kind: &'static str,
+  port: u16,
+  started: Instant,
+  pub(crate) frames_in: AtomicU64,
+  pub(crate) frames_out: AtomicU64,
+  pub(crate) bytes_in: AtomicU64,
+  pub(crate) bytes_out: AtomicU64,
+  pub(crate) session_overflows: AtomicU64,
+  pub(crate) decode_errors: AtomicU64,
+  pub(crate) send_failures: AtomicU64,
+  pub(crate) refused_frames: AtomicU64,
+  pub(crate) auth_failures: AtomicU64,
+  pub(crate) connected_clients: AtomicUsize,
+  pub(crate) total_clients: AtomicU64,
+  pub(crate) rejected_clients: AtomicU64,
+}
+
+/// A point-in-time copy of a bridge's traffic counters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[cfg_attr(feature = "pyo3", pyclass(name = "BridgeStats", get_all, eq))]
+#[repr(C)]
+pub struct BridgeStatsSnapshot {
+  /// CAN frames received from clients and sent on the bus
+  pub frames_in: u64,
+  /// CAN frames read from the bus and sent to clients. Frames sent to multiple clients are counted once per client.
+  pub frames_out: u64,
+  /// Encoded frame bytes received from clients
+  pub bytes_in: u64,
+  /// Encoded frame bytes sent to clients
+  pub bytes_out: u64,
+  /// Times a client's stream session overflowed, losing frames that were not read in time
+  pub session_overflows: u64,
+  /// Messages from clients that could not be decoded
+  pub decode_errors: u64,
+  /// Frames from clients that the HAL failed to send on the bus
+  pub send_failures: u64,
+  /// Frames from clients that were refused because the bridge is read-only or the frame wasn't on the allow-list
+  pub refused_frames: u64,
+  /// Clients that gave the wrong token or didn't authenticate in time
+  pub auth_failures: u64,
+  pub connected_clients: u32,
+  /// Clients that have connected since the bridge started, including those currently connected
+  pub total_clients: u64,
+  /// Clients turned away because the bridge was full
+  pub rejected_clients: u64,
+  /// Seconds since the bridge started
+  pub uptime: f64,
+}
+
+#[cfg(feature = "pyo3")]
+#[pymethods]
+impl BridgeStatsSnapshot {
+  fn __repr__(&self) -> String {
+    format!("{:?}", self)
+  }
+}
+
+/// The stats of a running bridge, as reported by the stats endpoint.
+#[derive(Debug, Clone, Serialize)]
+pub struct BridgeStatsReport {
+  pub kind: &'static str,
+  pub port: u16,
+  #[serde(flatten)]
+  pub stats: BridgeStatsSnapshot,
+}
+
+impl BridgeStats {
+  /// Create the stats for a new bridge, registering them so they're included in `BridgeStats::all`.
+  pub fn register(kind: &'static str, port: u16) -> Arc<Self> {
+    let stats = Arc::new(Self {
+      kind,
+      port,
+      started: Instant::now(),
+      frames_in: AtomicU64::new(0),
+      frames_out: AtomicU64::new(0),
+      bytes_in: AtomicU64::new(0),
+      bytes_out: AtomicU64::new(0),
+      session_overflows: AtomicU64::new(0),
+      decode_errors: AtomicU64::new(0),
+      send_failures: AtomicU64::new(0),
+      refused_frames: AtomicU64::new(0),
+      auth_failures: AtomicU64::new(0),
+      connected_clients: AtomicUsize::new(0),
+      total_clients: AtomicU64::new(0),
+      rejected_clients: AtomicU64::new(0),
+    });
+
+    let mut registry = REGISTRY.lock().unwrap();
+    registry.retain(|s| s.strong_count() > 0);
+    registry.push(Arc::downgrade(&stats));
+    stats
+  }
+
+  /// Remove the stats from `BridgeStats::all`, once the bridge has stopped.
+  pub fn unregister(self: &Arc<Self>) {
+    REGISTRY.lock().unwrap().retain(|s| s.strong_count() > 0 && !std::ptr::eq(s.as_ptr(), Arc::as_ptr(self)));
+  }
+
+  /// The stats of every bridge that is currently running.
+  pub fn all() -> Vec<BridgeStatsReport> {
+    REGISTRY.lock().unwrap().iter().filter_map(|s| s.upgrade()).map(|s| s.report()).collect()
+  }
+
+  pub fn snapshot(&self) -> BridgeStatsSnapshot {
+    BridgeStatsSnapshot {
+      frames_in: self.frames_in.load(Ordering::Relaxed),
+      frames_out: self.frames_out.load(Ordering::Relaxed),
+      bytes_in: self.bytes_in.load(Ordering::Relaxed),
+      bytes_out: self.bytes_out.load(Ordering::Relaxed),
+      session_overflows: self.session_overflows.load(Ordering::Relaxed),
+      decode_errors: self.decode_errors.load(Ordering::Relaxed),
+      send_failures: self.send_failures.load(Ordering::Relaxed),
+      refused_frames: self.refused_frames.load(Ordering::Relaxed),
+      auth_failures: self.auth_failures.load(Ordering::Relaxed),
+      connected_clients: self.connected_clients.load(Ordering::Relaxed) as u32,
+      total_clients: self.total_clients.load(Ordering::Relaxed),
+      rejected_clients: self.rejected_clients.load(Ordering::Relaxed),
+      uptime: self.started.elapsed().as_secs_f64(),
+    }
+  }
+
+  pub fn report(&self) -> BridgeStatsReport {
+    BridgeStatsReport { kind: self.kind, port: self.port, stats: self.snapshot() }
+  }
+}
@@ -1,29 +1,205 @@
-use std::{time::{Instant, Duration}, borrow::Cow};
+use std::{time::{Instant, Duration}, borrow::Cow, sync::{Arc, RwLock}};
 
 use bounded_static::{IntoBoundedStatic, ToBoundedStatic};
 use grapple_frc_msgs::{grapple::{fragments::{FragmentReassembler, FragmentReassemblerRx, FragmentReassemblerTx}, GrappleMessageId, GrappleDeviceMessage, MaybeFragment, errors::{GrappleResult, GrappleError}}, MessageId, binmarshal::{BitView, Demarshal, MarshalUpdate}, Validate};
 
-use crate::{hal_safe_call, HAL_CAN_ReceiveMessage, HAL_CAN_SendMessage, HAL_CAN_SEND_PERIOD_NO_REPEAT};
+use crate::{bridge_protocol::CanFilter, calling::WpiHalResult, hal_safe_call, HAL_CANStreamMessage, HAL_CAN_CloseStreamSession, HAL_CAN_OpenStreamSession, HAL_CAN_ReadStreamSession, HAL_CAN_ReceiveMessage, HAL_CAN_SendMessage, HAL_CAN_SEND_PERIOD_NO_REPEAT, HAL_ERR_CANSessionMux_SessionOverrun, HAL_GetFPGATime};
+
+/// A single CAN frame, as sent or received through a CanTransport.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CanFrame {
+  pub id: u32,
+  /// Milliseconds, as timestamped by the roboRIO that received the frame.
+  pub timestamp: u32,
+  pub data: Vec<u8>,
+}
+
+/// Somewhere CAN frames can be sent to and received from. This is the roboRIO's own bus through
+/// the HAL by default, but can also be a remote bus (see bridge_client::BridgeClient).
+pub trait CanTransport: Send + Sync {
+  fn send(&mut self, id: u32, data: &[u8]) -> anyhow::Result<()>;
+
+  /// Take the next received frame that matches the filter, if there is one.
+  fn receive(&mut self, filter: CanFilter) -> Option<CanFrame>;
+}
+
+/// The roboRIO's CAN bus, accessed through the HAL.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HalCanTransport;
+
+impl CanTransport for HalCanTransport {
+  fn send(&mut self, id: u32, data: &[u8]) -> anyhow::Result<()> {
+    hal_safe_call!(HAL_CAN_SendMessage(id, data.as_ptr(), data.len() as u8, HAL_CAN_SEND_PERIOD_NO_REPEAT as i32))?;
+    Ok(())
+  }
+
+  fn receive(&mut self, filter: CanFilter) -> Option<CanFrame> {
+    let mut id = filter.id;
+    let mut data = [0u8; 8];
+    let mut len = 0u8;
+    let mut timestamp = 0u32;
+
+    hal_safe_call!(HAL_CAN_ReceiveMessage(&mut id as *mut u32, filter.mask, data.as_mut_ptr(), &mut len as *mut u8, &mut timestamp as *mut u32)).ok()?;
+    Some(CanFrame { id, timestamp, data: data[0..(len as usize).min(8)].to_vec() })
+  }
+}
+
+/// Every frame on a bus that matches a filter, buffered between reads. Used by the bridges and the
+/// recorder, which need to see all the traffic rather than a single device's.
+pub trait CanStream: Send {
+  /// Read as many buffered frames as fit in `buffer`. Returns how many were read, and whether
+  /// frames were lost because the stream filled up before it was read.
+  fn read(&mut self, buffer: &mut [HAL_CANStreamMessage]) -> (usize, bool);
+
+  fn send(&mut self, id: u32, data: &[u8]) -> anyhow::Result<()>;
+}
+
+/// A HAL stream session on the roboRIO's CAN bus.
+pub struct CanDropGuard {
+  session_handle: u32
+}
+
+impl CanDropGuard {
+  /// Open a HAL stream session, which will be closed when the guard is dropped.
+  pub fn open_stream_session(message_id: u32, message_id_mask: u32, max_messages: u32) -> WpiHalResult<Self> {
+    let mut session_handle = 0u32;
+    hal_safe_call!(HAL_CAN_OpenStreamSession(&mut session_handle as *mut u32, message_id, message_id_mask, max_messages))?;
+    Ok(Self { session_handle })
+  }
+
+  pub fn session_handle(&self) -> u32 {
+    self.session_handle
+  }
+}
+
+impl Drop for CanDropGuard {
+  fn drop(&mut self) {
+    unsafe { HAL_CAN_CloseStreamSession(self.session_handle) };
+  }
+}
+
+impl CanStream for CanDropGuard {
+  fn read(&mut self, buffer: &mut [HAL_CANStreamMessage]) -> (usize, bool) {
+    let mut n_read = 0u32;
+    let mut status = 0i32;
+    unsafe { HAL_CAN_ReadStreamSession(self.session_handle(), buffer.as_mut_ptr(), buffer.len() as u32, &mut n_read as *mut u32, &mut status as *mut i32) };
+
+    // An overrun is only a warning - the frames that were read are still valid, but some frames
+    // were lost before them. Any other error (usually no messages) means nothing was read.
+    let overflowed = status == HAL_ERR_CANSessionMux_SessionOverrun as i32;
+    if status != 0 && !overflowed {
+      return (0, false);
+    }
+    (n_read as usize, overflowed)
+  }
+
+  fn send(&mut self, id: u32, data: &[u8]) -> anyhow::Result<()> {
+    HalCanTransport.send(id, data)
+  }
+}
+
+/// A whole CAN bus, which devices, bridges and recorders all use. This is the roboRIO's bus through
+/// the HAL by default, but can be changed with set_bus, e.g. to a SocketCAN interface on a
+/// coprocessor (see socketcan::SocketCanBus).
+pub trait CanBus: Send + Sync {
+  /// A transport for a single device on the bus.
+  fn transport(&self) -> Box<dyn CanTransport>;
+
+  /// Open a stream of the frames matching `filter`, holding up to `depth` frames between reads.
+  fn open_stream(&self, filter: CanFilter, depth: u32) -> anyhow::Result<Box<dyn CanStream>>;
+
+  /// The clock frames on the bus are timestamped with, in microseconds. On the roboRIO this is the
+  /// FPGA time.
+  fn time_us(&self) -> u64;
+}
+
+/// The roboRIO's CAN bus, accessed through the HAL.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HalCanBus;
+
+impl CanBus for HalCanBus {
+  fn transport(&self) -> Box<dyn CanTransport> {
+    Box::new(HalCanTransport)
+  }
+
+  fn open_stream(&self, filter: CanFilter, depth: u32) -> anyhow::Result<Box<dyn CanStream>> {
+    Ok(Box::new(CanDropGuard::open_stream_session(filter.id, filter.mask, depth)?))
+  }
+
+  fn time_us(&self) -> u64 {
+    hal_safe_call!(HAL_GetFPGATime()).unwrap_or(0)
+  }
+}
+
+static BUS: RwLock<Option<Arc<dyn CanBus>>> = RwLock::new(None);
+
+/// The bus in use, which is the roboRIO's CAN bus unless changed with set_bus.
+pub fn bus() -> Arc<dyn CanBus> {
+  match BUS.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+    Some(bus) => bus.clone(),
+    None => Arc::new(HalCanBus),
+  }
+}
+
+/// Change the bus used by devices, bridges and recorders created from now on. Ones that already
+/// exist keep using the old bus. Pass None to go back to the roboRIO's CAN bus.
+pub fn set_bus(bus: Option<Arc<dyn CanBus>>) {
+  *BUS.write().unwrap_or_else(|e| e.into_inner()) = bus;
+}
+
+/// Creates the transport for each device that isn't given one explicitly.
+pub type TransportFactory = Box<dyn Fn() -> Box<dyn CanTransport> + Send + Sync>;
+
+static DEFAULT_TRANSPORT: RwLock<Option<TransportFactory>> = RwLock::new(None);
+
+/// The transport used by devices created without one, which is a transport on the current bus
+/// (see set_bus) unless changed with set_default_transport.
+pub fn default_transport() -> Box<dyn CanTransport> {
+  match DEFAULT_TRANSPORT.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+    Some(factory) => factory(),
+    None => bus().transport(),
+  }
+}
+
+/// Change the transport used by devices created from now on without one, e.g. to replay a
+/// recording (see can_replay). Devices that already exist keep their transport. Pass None to go
+/// back to the current bus.
+pub fn set_default_transport(factory: Option<TransportFactory>) {
+  *DEFAULT_TRANSPORT.write().unwrap_or_else(|e| e.into_inner()) = factory;
+}
 
 pub struct GrappleCanDriver {
   can_id: u8,
   device_type: u8,
+  transport: Box<dyn CanTransport>,
   reassembler_rx: FragmentReassemblerRx,
   reassembler_tx: FragmentReassemblerTx
 }
 
 impl GrappleCanDriver {
   pub fn new(can_id: u8, device_type: u8) -> Self {
+    Self::with_transport(can_id, device_type, default_transport())
+  }
+
+  /// Talk to the device over the given transport instead of the roboRIO's CAN bus.
+  pub fn with_transport(can_id: u8, device_type: u8, transport: Box<dyn CanTransport>) -> Self {
     let (rx, tx) = FragmentReassembler::new(1000, 8).split();
     Self {
       can_id,
       device_type,
+      transport,
       reassembler_rx: rx,
       reassembler_tx: tx,
     }
   }
 
   pub fn spin<F: FnMut(GrappleMessageId, GrappleDeviceMessage) -> bool>(&mut self, consumer: &mut F) {
+    self.spin_timestamped(&mut |_timestamp, id, msg| consumer(id, msg))
+  }
+
+  /// As spin, but also passes the timestamp of the frame each message arrived in (the last
+  /// fragment's, for fragmented messages), in milliseconds on the bus's clock.
+  pub fn spin_timestamped<F: FnMut(u32, GrappleMessageId, GrappleDeviceMessage) -> bool>(&mut self, consumer: &mut F) {
     let id: MessageId = GrappleMessageId {
       device_type: self.device_type,
       fragment_flag: false,
@@ -42,36 +218,38 @@ impl GrappleCanDriver {
       device_id: 0xFF,
     }.into();
 
-    let mut data = [0u8; 8];
-    let mut len = 0u8;
-    let mut timestamp = 0u32;
+    let filter = CanFilter { id: id.into(), mask: mask.into() };
 
     loop {
-      let mut message_id: u32 = id.into();
-      let result = hal_safe_call!(HAL_CAN_ReceiveMessage(&mut message_id as *mut u32, mask.into(), data.as_mut_ptr(), &mut len as *mut u8, &mut timestamp as *mut u32));
+      let result = self.transport.receive(filter);
 
       match result {
-        Ok(_) => {
+        Some(frame) => {
+          let mut data = [0u8; 8];
+          let len = frame.data.len().min(8);
+          data[0..len].copy_from_slice(&frame.data[0..len]);
+
           let mut view = BitView::new(&mut data);
-          let this_message_id: MessageId = message_id.into();
+          let this_message_id: MessageId = frame.id.into();
           let msg = MaybeFragment::read(&mut view, this_message_id.into());
           match msg {
             Ok(msg) => {
               let mut storage = Vec::with_capacity(128);
-              match self.reassembler_rx.defragment(timestamp as i64, &this_message_id, msg, &mut storage) {
+              match self.reassembler_rx.defragment(frame.timestamp as i64, &this_message_id, msg, &mut storage) {
                 Ok(Some((mid, m))) => {
-                  let cont = consumer(mid, m);
+                  let cont = consumer(frame.timestamp, mid, m);
                   if !cont {
                     break;
                   }
                 },
-                _ => (),
+                Ok(None) => (),
+                Err(e) => log::debug!(device_id = self.can_id, error:? = e; "Could not reassemble message"),
               }
             },
-            _ => (),
+            Err(e) => log::debug!(device_id = self.can_id, error:? = e; "Could not decode message"),
           }
         },
-        Err(_) => break
+        None => break
       }
     }
   }
@@ -85,8 +263,11 @@ impl GrappleCanDriver {
     }).ok();
 
     for (id, buf) in msgs {
-      hal_safe_call!(HAL_CAN_SendMessage(id.into(), buf.as_ptr(), buf.len() as u8, HAL_CAN_SEND_PERIOD_NO_REPEAT as i32))
-        .map_err(|e| GrappleError::Generic(Cow::<str>::Owned(e.to_string()).into()))?;
+      self.transport.send(id.into(), &buf)
+        .map_err(|e| {
+          log::warn!(device_id = self.can_id, error:% = e; "Could not send message");
+          GrappleError::Generic(Cow::<str>::Owned(e.to_string()).into())
+        })?;
     }
     Ok(())
   }
@@ -129,8 +310,14 @@ impl GrappleCanDriver {
     
     match self.request_inner(msg.clone(), complement_id, timeout_ms) {
       Ok(x) => Ok(x.to_static()),
-      Err(_) if retry >= 1 => self.request(msg, timeout_ms, retry - 1),
-      Err(e) => Err(e)
+      Err(e) if retry >= 1 => {
+        log::debug!(device_id = self.can_id, error:? = e, retries_left = retry - 1; "Request failed, retrying");
+        self.request(msg, timeout_ms, retry - 1)
+      },
+      Err(e) => {
+        log::debug!(device_id = self.can_id, error:? = e; "Request failed");
+        Err(e)
+      }
     }
   }
 }
\ No newline at end of file
@@ -1,131 +1,328 @@
-use std::{net::{TcpListener, TcpStream}, io::{Read, Write, ErrorKind}, time::Duration, borrow::Cow};
+use std::{net::{TcpListener, TcpStream}, io::{Read, Write, ErrorKind}, time::Duration, sync::{Arc, atomic::{AtomicBool, Ordering}}, thread::JoinHandle};
 
-use grapple_frc_msgs::{bridge::BridgedCANMessage, binmarshal::{BitView, VecBitWriter, BitWriter, Demarshal, Marshal, LengthTaggedPayload}, MessageId};
+use crate::{bridge_config::{BridgeConfig, CBridgeConfig}, bridge_handle::BridgeHandle, bridge_protocol::{pack_batches, TCP_BATCH_FLAG, TCP_CONTROL_FLAG, TCP_LENGTH_MASK}, bridge_session::BridgeSession, bridge_stats::BridgeStats};
 
-use crate::{hal_safe_call, HAL_CAN_SendMessage, HAL_CAN_SEND_PERIOD_NO_REPEAT, HAL_CAN_OpenStreamSession, HAL_CANStreamMessage, HAL_CAN_ReadStreamSession, HAL_CAN_CloseStreamSession};
+// Write a frame prefixed with its header, blocking until it has been written.
+pub(crate) fn write_frame(stream: &mut TcpStream, flags: u16, payload: &[u8]) -> anyhow::Result<()> {
+  let mut buf = Vec::with_capacity(payload.len() + 2);
+  buf.extend_from_slice(&u16::to_le_bytes(payload.len() as u16 | flags));
+  buf.extend_from_slice(payload);
 
-fn handle_client(session_handle: u32, mut stream: TcpStream) -> anyhow::Result<()> {
-  let mut read_buf = Vec::with_capacity(1024);
+  let mut slice = &buf[..];
+  while !slice.is_empty() {
+    match stream.write(slice) {
+      Ok(0) => anyhow::bail!("Failed to write"),
+      Ok(n) => slice = &slice[n..],
+      Err(e) if e.kind() == ErrorKind::Interrupted || e.kind() == ErrorKind::WouldBlock => {},
+      Err(e) => anyhow::bail!("Write error: {}", e)
+    }
+  }
+  Ok(())
+}
+
+fn handle_control(session: &mut BridgeSession, stream: &mut TcpStream, data: &[u8]) -> anyhow::Result<()> {
+  if let Some(reply) = session.handle_control_json(data)? {
+    write_frame(stream, TCP_CONTROL_FLAG, reply.to_json().as_bytes())?;
+  }
+  Ok(())
+}
+
+// An encoded BridgedCANMessage is a u32 id, u32 timestamp and u8 length, followed by up to 8 bytes of data.
+const MIN_MESSAGE_LENGTH: usize = 9;
+const MAX_MESSAGE_LENGTH: usize = MIN_MESSAGE_LENGTH + 8;
+
+// Splits the bytes received from a TCP client into frames. If a header doesn't make sense (e.g. the
+// client sent garbage or we joined mid-frame), the reader skips forward a byte at a time until it
+// finds one that does, rather than waiting on a bogus length or dropping the client.
+pub(crate) struct FrameReader {
+  buf: Vec<u8>,
+  skipped: usize,
+}
+
+impl FrameReader {
+  pub(crate) fn new() -> Self {
+    Self { buf: Vec::with_capacity(1024), skipped: 0 }
+  }
+
+  // Read everything available from a non-blocking stream, returning true if it has been closed.
+  pub(crate) fn fill<R: Read>(&mut self, stream: &mut R) -> std::io::Result<bool> {
+    match stream.read_to_end(&mut self.buf) {
+      // read_to_end only returns Ok once the other end has closed the connection
+      Ok(_) => Ok(true),
+      Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::Interrupted => Ok(false),
+      Err(e) => Err(e)
+    }
+  }
+
+  // Check the header against as much of the payload as has arrived so far.
+  fn is_plausible(header: u16, payload: &[u8]) -> bool {
+    let len = (header & TCP_LENGTH_MASK) as usize;
+    if header & TCP_BATCH_FLAG != 0 {
+      len > 0
+    } else if header & TCP_CONTROL_FLAG != 0 {
+      // Control messages are JSON objects
+      len > 0 && payload.iter().take(len).find(|b| !b.is_ascii_whitespace()).is_none_or(|&b| b == b'{')
+    } else {
+      (MIN_MESSAGE_LENGTH..=MAX_MESSAGE_LENGTH).contains(&len)
+        && payload.get(MIN_MESSAGE_LENGTH - 1).is_none_or(|&n| n as usize + MIN_MESSAGE_LENGTH == len)
+    }
+  }
+
+  // Take the next complete frame, returning its header and payload.
+  pub(crate) fn next_frame(&mut self) -> Option<(u16, Vec<u8>)> {
+    while self.buf.len() >= 2 {
+      let header = u16::from_le_bytes([ self.buf[0], self.buf[1] ]);
+      let len = (header & TCP_LENGTH_MASK) as usize;
+
+      if !Self::is_plausible(header, &self.buf[2..]) {
+        self.buf.remove(0);
+        self.skipped += 1;
+        continue;
+      }
+
+      if self.buf.len() - 2 < len {
+        return None;
+      }
+      return Some((header, self.buf.drain(0..len + 2).skip(2).collect()));
+    }
+    None
+  }
+
+  // The number of bytes skipped while resynchronising since this was last called.
+  pub(crate) fn take_skipped(&mut self) -> usize {
+    std::mem::take(&mut self.skipped)
+  }
+}
+
+fn handle_client(session: &mut BridgeSession, mut stream: TcpStream, stop: &AtomicBool, stats: &BridgeStats) -> anyhow::Result<()> {
+  let mut reader = FrameReader::new();
 
   stream.set_nonblocking(true)?;
 
-  loop {
+  while !stop.load(Ordering::Relaxed) {
+    session.check_access()?;
+
     // Read from socket first
-    match stream.read_to_end(&mut read_buf) {
-      Ok(_) => (),
-      Err(e) if e.kind() == ErrorKind::WouldBlock => (),
-      Err(e) => anyhow::bail!(e)
-    };
+    let closed = reader.fill(&mut stream)?;
 
-    if read_buf.len() >= 2 {
-      let msg_len: usize = u16::from_le_bytes([ read_buf[0], read_buf[1] ]) as usize;
+    while let Some((header, payload)) = reader.next_frame() {
+      if header & TCP_BATCH_FLAG != 0 {
+        log::warn!(client = session.client(); "Ignoring batch from client, only the bridge may send batches");
+      } else if header & TCP_CONTROL_FLAG != 0 {
+        if let Err(e) = handle_control(session, &mut stream, &payload) {
+          log::warn!(client = session.client(), error:% = e; "Invalid control message");
+        }
+      } else if let Err(e) = session.send_encoded(&payload) {
+        log::warn!(client = session.client(), error:% = e; "Dropped frame from client");
+      }
+    }
 
-      if (read_buf.len() - 2) >= msg_len {
-        let mut next_buf = read_buf.split_off(msg_len + 2);
+    let skipped = reader.take_skipped();
+    if skipped > 0 {
+      stats.decode_errors.fetch_add(1, Ordering::Relaxed);
+      log::warn!(client = session.client(), skipped; "Skipped invalid data from client");
+    }
 
-        let bridged_msg = BridgedCANMessage::read(&mut BitView::new(&read_buf[2..]), ()).map_err(|e| anyhow::anyhow!("Invalid Message! {:?}", e))?;
-        let r = bridged_msg.data.as_ref();
-        let msg_data = r.as_ref();
-        hal_safe_call!(HAL_CAN_SendMessage(
-          bridged_msg.id.into(),
-          msg_data.as_ptr(),
-          msg_data.len() as u8,
-          HAL_CAN_SEND_PERIOD_NO_REPEAT as i32
-        ))?;
+    if closed {
+      return Ok(());
+    }
 
-        next_buf.reserve(1024);
-        read_buf = next_buf;
+    // See if there's anything to write. Block on writes to the socket.
+    let frames = session.read_encoded();
+    for notification in [session.take_overflow_notification(), session.take_clock_sync()].into_iter().flatten() {
+      write_frame(&mut stream, TCP_CONTROL_FLAG, notification.to_json().as_bytes())?;
+    }
+    if session.batching() {
+      for batch in pack_batches(frames) {
+        write_frame(&mut stream, TCP_BATCH_FLAG, &batch)?;
+      }
+    } else {
+      for frame in frames {
+        write_frame(&mut stream, 0, &frame)?;
       }
     }
 
-    // See if there's anything to write
-    let mut stream_messages: [HAL_CANStreamMessage; 1024] = [HAL_CANStreamMessage { ..Default::default() }; 1024];
-    let mut n_read = 0u32;
-    let result = hal_safe_call!(HAL_CAN_ReadStreamSession(session_handle, &mut stream_messages as *mut HAL_CANStreamMessage, 1024, &mut n_read as *mut u32));
+    std::thread::sleep(session.next_poll_delay());
+  }
+
+  Ok(())
+}
+
+fn run_client(stream: TcpStream, config: &BridgeConfig, stop: &AtomicBool, stats: Arc<BridgeStats>) -> anyhow::Result<()> {
+  // Each client gets its own stream session, so every client sees every frame. Clients can narrow
+  // this down by sending a SetFilters control message.
+  let client = stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_default();
+  let mut session = BridgeSession::open(config, client, vec![], stats.clone())?;
+  handle_client(&mut session, stream, stop, &stats)
+}
+
+// Frees up a client slot when the client's thread exits, even if it panics.
+pub(crate) struct ClientSlot(Arc<BridgeStats>);
 
-    match result {
+impl ClientSlot {
+  /// Take one of the `max_clients` client slots, if there are any free.
+  pub(crate) fn acquire(stats: &Arc<BridgeStats>, max_clients: usize) -> Option<Self> {
+    match stats.connected_clients.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| (n < max_clients).then_some(n + 1)) {
       Ok(_) => {
-        for msg in &stream_messages[0..n_read as usize] {
-          let message_id: MessageId = msg.messageID.into();
-          let bridged_msg = BridgedCANMessage { id: message_id, timestamp: msg.timeStamp, data: Cow::Borrowed(Into::<&LengthTaggedPayload<_>>::into(&msg.data[0..msg.dataSize as usize])).into() };
-  
-          let mut write_buf = VecBitWriter::new();
-          bridged_msg.write(&mut write_buf, ()).ok();
-          let mut slice = write_buf.slice();
-          
-          let l = u16::to_le_bytes(slice.len() as u16);
-          let mut slice1 = &l[..];
-  
-          // Block on writes to the socket
-  
-          while !slice1.is_empty() {
-            match stream.write(slice1) {
-              Ok(0) => anyhow::bail!("Failed to write"),
-              Ok(n) => slice1 = &slice1[n..],
-              Err(e) if e.kind() == ErrorKind::Interrupted || e.kind() == ErrorKind::WouldBlock => {},
-              Err(e) => anyhow::bail!("Write error: {}", e)
-            }
-          }
-  
-          while !slice.is_empty() {
-            match stream.write(slice) {
-              Ok(0) => anyhow::bail!("Failed to write"),
-              Ok(n) => slice = &slice[n..],
-              Err(e) if e.kind() == ErrorKind::Interrupted || e.kind() == ErrorKind::WouldBlock => {},
-              Err(e) => anyhow::bail!("Write error: {}", e)
-            }
-          }
-        }
+        stats.total_clients.fetch_add(1, Ordering::Relaxed);
+        Some(Self(stats.clone()))
       },
-      Err(_) => ()
+      Err(_) => {
+        stats.rejected_clients.fetch_add(1, Ordering::Relaxed);
+        None
+      }
     }
+  }
+}
 
-    std::thread::sleep(Duration::from_millis(1));
+impl Drop for ClientSlot {
+  fn drop(&mut self) {
+    self.0.connected_clients.fetch_sub(1, Ordering::SeqCst);
   }
 }
 
-fn start_can_bridge(forever: bool) -> anyhow::Result<()> {
-  let server = TcpListener::bind("0.0.0.0:8006")?;
-
-  for stream in server.incoming() {
-    // Only handle one client at a time, otherwise the process lives forever when GrappleHook is done.
-    let mut session_handle = 0u32;
-    hal_safe_call!(HAL_CAN_OpenStreamSession(&mut session_handle as *mut u32, 0u32, 0u32, 1024))?;
-    let result = handle_client(session_handle, stream?);
-    unsafe { HAL_CAN_CloseStreamSession(session_handle) };
+/// Run the TCP CAN bridge until `stop` is set, serving up to `config.max_clients` clients at once.
+/// If `forever` is false, the bridge will serve a single client and return once it disconnects.
+pub fn run_can_bridge_until(config: BridgeConfig, forever: bool, stop: Arc<AtomicBool>, stats: Arc<BridgeStats>) -> anyhow::Result<()> {
+  let server = TcpListener::bind(config.socket_addr())?;
+  // Non-blocking so we can periodically check if we've been asked to stop
+  server.set_nonblocking(true)?;
+
+  let mut clients: Vec<JoinHandle<()>> = vec![];
+
+  while !stop.load(Ordering::Relaxed) {
+    clients.retain(|c| !c.is_finished());
+
+    let (stream, addr) = match server.accept() {
+      Ok(client) => client,
+      Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::Interrupted => {
+        std::thread::sleep(Duration::from_millis(50));
+        continue;
+      },
+      // Errors accepting a single client (e.g. it reset the connection, or we're out of file
+      // descriptors) shouldn't take the bridge down for everyone else.
+      Err(e) => {
+        log::error!(error:% = e; "Failed to accept client");
+        std::thread::sleep(Duration::from_millis(50));
+        continue;
+      }
+    };
+
     if !forever {
-      return result;
+      // Only handle the one client, otherwise the process lives forever when GrappleHook is done.
+      let _slot = ClientSlot::acquire(&stats, 1);
+      return run_client(stream, &config, &stop, stats);
     }
+
+    let Some(slot) = ClientSlot::acquire(&stats, config.max_clients) else {
+      log::warn!(client:% = addr, max_clients = config.max_clients; "Rejecting client, too many clients connected");
+      continue;
+    };
+    let stop = stop.clone();
+    let config = config.clone();
+    let stats = stats.clone();
+
+    clients.push(std::thread::spawn(move || {
+      let _slot = slot;
+      log::info!(client:% = addr; "Client connected");
+      match run_client(stream, &config, &stop, stats) {
+        Ok(()) => log::info!(client:% = addr; "Client disconnected"),
+        Err(e) => log::warn!(client:% = addr, error:% = e; "Client disconnected with error"),
+      }
+    }));
+  }
+
+  // Wait for clients to close their stream sessions
+  for client in clients {
+    client.join().ok();
   }
 
   Ok(())
 }
 
+fn start_can_bridge(forever: bool) -> anyhow::Result<()> {
+  let config = BridgeConfig::tcp();
+  let stats = BridgeStats::register("tcp", config.port);
+  run_can_bridge_until(config, forever, Arc::new(AtomicBool::new(false)), stats)
+}
+
+/// Start the TCP CAN bridge in the background, returning a handle that can be used to stop it.
+pub fn start_can_bridge_with_config(config: BridgeConfig) -> BridgeHandle {
+  let stats = BridgeStats::register("tcp", config.port);
+  BridgeHandle::spawn(stats, move |stop, stats| run_can_bridge_until(config, true, stop, stats))
+}
+
+pub fn start_can_bridge_background(port: u16) -> BridgeHandle {
+  start_can_bridge_with_config(BridgeConfig { port, ..BridgeConfig::tcp() })
+}
+
 #[no_mangle]
 pub extern "C" fn start_can_bridge_c(forever: bool) {
-  start_can_bridge(forever).unwrap()
+  crate::logging::init();
+  if let Err(e) = start_can_bridge(forever) {
+    log::error!(error:% = e; "CAN bridge stopped");
+  }
 }
 
 #[no_mangle]
 pub extern "C" fn start_can_bridge_c_background() {
-  std::thread::spawn(move || {
-    start_can_bridge(true).unwrap()
-  });
+  crate::logging::init();
+  start_can_bridge_background(8006).detach();
+}
+
+#[no_mangle]
+pub extern "C" fn can_bridge_start(port: u16) -> *mut BridgeHandle {
+  crate::logging::init();
+  Box::into_raw(Box::new(start_can_bridge_background(port)))
+}
+
+/// Start the TCP CAN bridge with the given configuration. Returns null if the configuration is invalid.
+#[no_mangle]
+pub extern "C" fn can_bridge_start_with_config(config: CBridgeConfig) -> *mut BridgeHandle {
+  crate::logging::init();
+  match config.to_config(BridgeConfig::tcp()) {
+    Ok(config) => Box::into_raw(Box::new(start_can_bridge_with_config(config))),
+    Err(e) => {
+      log::error!(error:% = e; "Invalid CAN bridge configuration");
+      std::ptr::null_mut()
+    }
+  }
 }
 
 #[cfg(feature = "jni")]
 mod jni {
-  use jni::{objects::JClass, JNIEnv};
+  use jni::{objects::{JClass, JObject}, sys::{jint, jlong}, JNIEnv};
+
+  use crate::bridge_config::{jni::config_from_jni, BridgeConfig};
 
-  use super::start_can_bridge;
+  use super::{start_can_bridge_background, start_can_bridge_with_config};
 
   #[no_mangle]
   pub extern "system" fn Java_au_grapplerobotics_CanBridge_runTCPNow<'local>(
     mut _env: JNIEnv<'local>,
     _class: JClass<'local>
   ) {
-    std::thread::spawn(move || {
-      start_can_bridge(true).unwrap()
-    });
+    start_can_bridge_background(8006).detach();
+  }
+
+  #[no_mangle]
+  pub extern "system" fn Java_au_grapplerobotics_CanBridge_startTCPInternal<'local>(
+    mut _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    port: jint
+  ) -> jlong {
+    Box::into_raw(Box::new(start_can_bridge_background(port as u16))) as jlong
   }
-}
\ No newline at end of file
+
+  #[no_mangle]
+  pub extern "system" fn Java_au_grapplerobotics_CanBridge_startTCPWithConfigInternal<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    config: JObject<'local>
+  ) -> jlong {
+    match config_from_jni(&mut env, config, BridgeConfig::tcp()) {
+      Some(config) => Box::into_raw(Box::new(start_can_bridge_with_config(config))) as jlong,
+      None => 0
+    }
+  }
+}
@@ -0,0 +1,742 @@
+use std::{ffi::{c_char, CStr}, fs::File, io::{BufRead, BufReader, BufWriter, Read, Write}, path::{Path, PathBuf}, sync::{atomic::{AtomicBool, Ordering}, Arc}, time::{Duration, Instant}};
+
+use crate::{bridge_config::{BridgeConfig, DEFAULT_POLL_INTERVAL, DEFAULT_SESSION_DEPTH}, bridge_handle::BridgeHandle, bridge_protocol::{extend_timestamp, CanFilter}, bridge_session::{clock_sync, BridgeSession}, bridge_stats::BridgeStats, HAL_CANStreamMessage};
+
+#[cfg(feature = "pyo3")]
+use pyo3::prelude::*;
+
+/// The first bytes of every binary recording.
+pub const RECORDING_MAGIC: &[u8; 8] = b"GRPLCAN\0";
+pub const RECORDING_VERSION: u8 = 1;
+/// The length byte of a record marking where frames were lost because the recorder didn't keep up.
+/// The record has no data, and its ID is the number of times the session has overflowed so far.
+pub const RECORD_OVERFLOW: u8 = 0xFF;
+
+pub const DEFAULT_RECORDING_PREFIX: &str = "grapplecan";
+pub const DEFAULT_CANDUMP_INTERFACE: &str = "can0";
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+pub const DEFAULT_MAX_FILES: usize = 16;
+pub const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Which files the recorder writes.
+///
+/// Binary recordings start with RECORDING_MAGIC, the RECORDING_VERSION byte, then the FPGA time
+/// and Unix time in microseconds when the file was opened (little-endian u64s), so timestamps can
+/// be lined up with other logs. Each frame follows as its FPGA timestamp in microseconds (u64), ID
+/// (u32), length (u8) and data, all little-endian.
+///
+/// Candump recordings are text in the format written by `candump -L` and read by `canplayer`, e.g.
+/// `(1718000000.123456) can0 0A060187#DEADBEEF`, with Unix timestamps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "pyo3", pyclass(eq, eq_int))]
+#[repr(C)]
+pub enum RecordingFormat {
+  Binary = 0,
+  Candump = 1,
+  Both = 2,
+}
+
+impl RecordingFormat {
+  fn binary(self) -> bool {
+    matches!(self, RecordingFormat::Binary | RecordingFormat::Both)
+  }
+
+  fn candump(self) -> bool {
+    matches!(self, RecordingFormat::Candump | RecordingFormat::Both)
+  }
+}
+
+/// Configuration for a CAN recorder.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecorderConfig {
+  /// The directory to write recordings to. It's created if it doesn't exist.
+  pub directory: PathBuf,
+  /// Recordings are named `<prefix>-<unix time>-<index>.grplcan` (or `.log` for candump), so
+  /// recordings from the same run sort together.
+  pub prefix: String,
+  pub format: RecordingFormat,
+  /// The interface name written in candump recordings.
+  pub candump_interface: String,
+  /// Only record frames matching at least one of these filters. Records everything if empty.
+  pub filters: Vec<CanFilter>,
+  /// Start a new file once the current one reaches this size, in bytes.
+  pub max_file_size: u64,
+  /// The most files with this prefix (and format) to keep in the directory, including those from
+  /// previous runs. The oldest are deleted first. 0 keeps every file.
+  pub max_files: usize,
+  /// The number of frames the HAL stream session can hold before it overflows.
+  pub session_depth: u32,
+  /// How often the stream session is checked for new frames while the bus is busy.
+  pub poll_interval: Duration,
+  /// How often buffered frames are written out, bounding how much is lost if the robot loses power.
+  pub flush_interval: Duration,
+}
+
+impl RecorderConfig {
+  /// Record all frames to the given directory in the binary format.
+  pub fn new(directory: impl Into<PathBuf>) -> Self {
+    Self {
+      directory: directory.into(),
+      prefix: DEFAULT_RECORDING_PREFIX.to_owned(),
+      format: RecordingFormat::Binary,
+      candump_interface: DEFAULT_CANDUMP_INTERFACE.to_owned(),
+      filters: vec![],
+      max_file_size: DEFAULT_MAX_FILE_SIZE,
+      max_files: DEFAULT_MAX_FILES,
+      session_depth: DEFAULT_SESSION_DEPTH,
+      poll_interval: DEFAULT_POLL_INTERVAL,
+      flush_interval: DEFAULT_FLUSH_INTERVAL,
+    }
+  }
+}
+
+// A file that is replaced with a new one once it reaches the size limit, deleting the oldest files
+// once there are too many.
+struct RotatingFile {
+  directory: PathBuf,
+  prefix: String,
+  extension: &'static str,
+  max_file_size: u64,
+  max_files: usize,
+  run: u64,
+  index: u32,
+  file: Option<BufWriter<File>>,
+  written: u64,
+}
+
+impl RotatingFile {
+  fn new(config: &RecorderConfig, extension: &'static str, run: u64) -> Self {
+    Self {
+      directory: config.directory.clone(),
+      prefix: config.prefix.clone(),
+      extension,
+      max_file_size: config.max_file_size,
+      max_files: config.max_files,
+      run,
+      index: 0,
+      file: None,
+      written: 0,
+    }
+  }
+
+  // Write a record, starting a new file (beginning with `header`) first if needed. Records are
+  // never split across files.
+  fn write(&mut self, record: &[u8], header: impl FnOnce() -> Vec<u8>) -> std::io::Result<u64> {
+    let mut written = 0;
+    if self.file.is_none() || self.written + record.len() as u64 > self.max_file_size {
+      let header = header();
+      self.rotate()?;
+      written += self.write_raw(&header)?;
+    }
+    written += self.write_raw(record)?;
+    Ok(written)
+  }
+
+  fn write_raw(&mut self, data: &[u8]) -> std::io::Result<u64> {
+    if let Some(file) = self.file.as_mut() {
+      file.write_all(data)?;
+      self.written += data.len() as u64;
+    }
+    Ok(data.len() as u64)
+  }
+
+  fn rotate(&mut self) -> std::io::Result<()> {
+    self.flush()?;
+    let path = self.directory.join(format!("{}-{:010}-{:04}.{}", self.prefix, self.run, self.index, self.extension));
+    log::debug!(path:? = path; "Starting new recording file");
+    self.file = Some(BufWriter::new(File::create(path)?));
+    self.written = 0;
+    self.index += 1;
+    self.prune();
+    Ok(())
+  }
+
+  // Delete the oldest recordings once there are more than max_files. The names sort in the order
+  // they were created, as the run and index are zero-padded.
+  fn prune(&self) {
+    if self.max_files == 0 {
+      return;
+    }
+
+    let Ok(entries) = std::fs::read_dir(&self.directory) else { return };
+    let start = format!("{}-", self.prefix);
+    let end = format!(".{}", self.extension);
+    let mut names: Vec<String> = entries
+      .filter_map(|e| e.ok())
+      .filter_map(|e| e.file_name().into_string().ok())
+      .filter(|name| name.starts_with(&start) && name.ends_with(&end))
+      .collect();
+    names.sort();
+
+    let excess = names.len().saturating_sub(self.max_files);
+    for name in names.into_iter().take(excess) {
+      let path = self.directory.join(name);
+      match std::fs::remove_file(&path) {
+        Ok(()) => log::debug!(path:? = path; "Deleted old recording file"),
+        Err(e) => log::warn!(path:? = path, error:% = e; "Could not delete old recording file"),
+      }
+    }
+  }
+
+  fn flush(&mut self) -> std::io::Result<()> {
+    match self.file.as_mut() {
+      Some(file) => file.flush(),
+      None => Ok(()),
+    }
+  }
+}
+
+// Writes frames to each of the configured formats.
+pub(crate) struct Recorder {
+  binary: Option<RotatingFile>,
+  candump: Option<RotatingFile>,
+  candump_interface: String,
+  // Unix time minus FPGA time, in microseconds
+  unix_offset_us: i64,
+}
+
+impl Recorder {
+  pub(crate) fn new(config: &RecorderConfig) -> Self {
+    let sync = clock_sync(None);
+    let run = sync.unix_time_us / 1_000_000;
+    Self {
+      binary: config.format.binary().then(|| RotatingFile::new(config, "grplcan", run)),
+      candump: config.format.candump().then(|| RotatingFile::new(config, "log", run)),
+      candump_interface: config.candump_interface.clone(),
+      unix_offset_us: sync.unix_time_us as i64 - sync.fpga_time_us as i64,
+    }
+  }
+
+  fn binary_header() -> Vec<u8> {
+    let sync = clock_sync(None);
+    let mut header = RECORDING_MAGIC.to_vec();
+    header.push(RECORDING_VERSION);
+    header.extend(sync.fpga_time_us.to_le_bytes());
+    header.extend(sync.unix_time_us.to_le_bytes());
+    header
+  }
+
+  pub(crate) fn write_binary(&mut self, timestamp_us: u64, id: u32, len: u8, data: &[u8]) -> std::io::Result<u64> {
+    let Some(file) = self.binary.as_mut() else { return Ok(0) };
+    let mut record = Vec::with_capacity(13 + data.len());
+    record.extend(timestamp_us.to_le_bytes());
+    record.extend(id.to_le_bytes());
+    record.push(len);
+    record.extend(data);
+    file.write(&record, Self::binary_header)
+  }
+
+  fn write_candump(&mut self, timestamp_us: u64, id: u32, data: &[u8]) -> std::io::Result<u64> {
+    let Some(file) = self.candump.as_mut() else { return Ok(0) };
+    let unix_us = (timestamp_us as i64 + self.unix_offset_us).max(0) as u64;
+    let data: String = data.iter().map(|b| format!("{:02X}", b)).collect();
+    let line = format!("({}.{:06}) {} {:08X}#{}\n", unix_us / 1_000_000, unix_us % 1_000_000, self.candump_interface, id, data);
+    file.write(line.as_bytes(), Vec::new)
+  }
+
+  fn write_frame(&mut self, msg: &HAL_CANStreamMessage, fpga_time_us: u64) -> std::io::Result<u64> {
+    let timestamp_us = extend_timestamp(msg.timeStamp, fpga_time_us);
+    let data = &msg.data[0..(msg.dataSize as usize).min(8)];
+    Ok(self.write_binary(timestamp_us, msg.messageID, data.len() as u8, data)? + self.write_candump(timestamp_us, msg.messageID, data)?)
+  }
+
+  // Candump has no way to mark lost frames, so they're only marked in binary recordings
+  fn write_overflow(&mut self, overflows: u64, fpga_time_us: u64) -> std::io::Result<u64> {
+    self.write_binary(fpga_time_us, overflows.min(u32::MAX as u64) as u32, RECORD_OVERFLOW, &[])
+  }
+
+  pub(crate) fn flush(&mut self) -> std::io::Result<()> {
+    if let Some(file) = self.binary.as_mut() { file.flush()?; }
+    if let Some(file) = self.candump.as_mut() { file.flush()?; }
+    Ok(())
+  }
+}
+
+/// A frame read back from a recording.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedFrame {
+  /// Unix time in microseconds.
+  pub timestamp_us: u64,
+  pub id: u32,
+  pub data: Vec<u8>,
+}
+
+/// An entry read back from a recording.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordingEntry {
+  Frame(RecordedFrame),
+  /// Frames were lost here because the recorder didn't keep up. `overflows` is the number of times
+  /// this has happened since recording started.
+  Overflow { timestamp_us: u64, overflows: u32 },
+}
+
+impl RecordingEntry {
+  pub fn timestamp_us(&self) -> u64 {
+    match self {
+      RecordingEntry::Frame(frame) => frame.timestamp_us,
+      RecordingEntry::Overflow { timestamp_us, .. } => *timestamp_us,
+    }
+  }
+}
+
+fn read_binary_recording<R: Read>(mut reader: R) -> anyhow::Result<Vec<RecordingEntry>> {
+  let mut header = [0u8; 25];
+  reader.read_exact(&mut header)?;
+  if &header[0..8] != RECORDING_MAGIC {
+    anyhow::bail!("Not a binary CAN recording");
+  }
+  if header[8] != RECORDING_VERSION {
+    anyhow::bail!("Unsupported recording version {}", header[8]);
+  }
+  let fpga_time_us = u64::from_le_bytes(header[9..17].try_into()?);
+  let unix_time_us = u64::from_le_bytes(header[17..25].try_into()?);
+  let to_unix = |timestamp_us: u64| (timestamp_us as i64 - fpga_time_us as i64 + unix_time_us as i64).max(0) as u64;
+
+  let mut entries = vec![];
+  let mut record = [0u8; 13];
+  loop {
+    // A recording cut short by a power loss can end partway through a record, so stop at the last
+    // complete one
+    match reader.read_exact(&mut record) {
+      Ok(()) => (),
+      Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+      Err(e) => return Err(e.into()),
+    }
+    let timestamp_us = to_unix(u64::from_le_bytes(record[0..8].try_into()?));
+    let id = u32::from_le_bytes(record[8..12].try_into()?);
+
+    if record[12] == RECORD_OVERFLOW {
+      entries.push(RecordingEntry::Overflow { timestamp_us, overflows: id });
+      continue;
+    }
+
+    let mut data = vec![0u8; (record[12] as usize).min(8)];
+    match reader.read_exact(&mut data) {
+      Ok(()) => entries.push(RecordingEntry::Frame(RecordedFrame { timestamp_us, id, data })),
+      Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+      Err(e) => return Err(e.into()),
+    }
+  }
+  Ok(entries)
+}
+
+// Parse a line written by `candump -L`, e.g. `(1718000000.123456) can0 0A060187#DEADBEEF`
+fn parse_candump_line(line: &str) -> Option<RecordedFrame> {
+  let mut parts = line.split_whitespace();
+  let (secs, micros) = parts.next()?.strip_prefix('(')?.strip_suffix(')')?.split_once('.')?;
+  let _interface = parts.next()?;
+  let (id, data) = parts.next()?.split_once('#')?;
+
+  let data = (0..data.len()).step_by(2)
+    .map(|i| data.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+    .collect::<Option<Vec<u8>>>()?;
+  if data.len() > 8 {
+    return None;
+  }
+
+  // candump pads the fraction to 6 digits, but be lenient with shorter ones
+  let micros: u64 = format!("{:0<6}", micros).get(0..6)?.parse().ok()?;
+  Some(RecordedFrame {
+    timestamp_us: secs.parse::<u64>().ok()? * 1_000_000 + micros,
+    id: u32::from_str_radix(id, 16).ok()?,
+    data,
+  })
+}
+
+fn read_candump_recording<R: BufRead>(reader: R) -> anyhow::Result<Vec<RecordingEntry>> {
+  let mut entries = vec![];
+  for line in reader.lines() {
+    let line = line?;
+    if line.trim().is_empty() {
+      continue;
+    }
+    match parse_candump_line(&line) {
+      Some(frame) => entries.push(RecordingEntry::Frame(frame)),
+      None => log::debug!(line = line.as_str(); "Skipping unrecognised candump line"),
+    }
+  }
+  Ok(entries)
+}
+
+/// Read a recording written by the recorder, in either format. Candump files written by other
+/// tools can be read too, as long as they use the `candump -L` format.
+pub fn read_recording(path: &Path) -> anyhow::Result<Vec<RecordingEntry>> {
+  let mut reader = BufReader::new(File::open(path)?);
+  if reader.fill_buf()?.starts_with(RECORDING_MAGIC) {
+    read_binary_recording(reader)
+  } else {
+    read_candump_recording(reader)
+  }
+}
+
+/// The files making up a recording, starting with `path` and followed by the files the recorder
+/// rotated to after it in the same run, in order. Files that don't follow the recorder's naming
+/// are returned on their own.
+pub fn recording_files(path: &Path) -> Vec<PathBuf> {
+  let single = vec![path.to_path_buf()];
+  let (Some(name), Some(directory)) = (path.file_name().and_then(|n| n.to_str()), path.parent()) else {
+    return single;
+  };
+  // <prefix>-<run>-<index>.<extension>
+  let Some((stem, extension)) = name.rsplit_once('.') else { return single };
+  let Some((run, _index)) = stem.rsplit_once('-') else { return single };
+  if !run.rsplit_once('-').is_some_and(|(_, run)| !run.is_empty() && run.bytes().all(|b| b.is_ascii_digit())) {
+    return single;
+  }
+
+  let directory = if directory.as_os_str().is_empty() { Path::new(".") } else { directory };
+  let Ok(entries) = std::fs::read_dir(directory) else { return single };
+  let start = format!("{}-", run);
+  let end = format!(".{}", extension);
+  let mut names: Vec<String> = entries
+    .filter_map(|e| e.ok())
+    .filter_map(|e| e.file_name().into_string().ok())
+    .filter(|n| n.starts_with(&start) && n.ends_with(&end) && n.as_str() >= name)
+    .collect();
+  names.sort();
+  names.into_iter().map(|n| path.with_file_name(n)).collect()
+}
+
+/// Record CAN traffic until `stop` is set. Frames written and bytes written are counted in the
+/// stats' frames_out and bytes_out.
+pub fn run_can_recorder_until(config: RecorderConfig, stop: Arc<AtomicBool>, stats: Arc<BridgeStats>) -> anyhow::Result<()> {
+  std::fs::create_dir_all(&config.directory)?;
+
+  let session_config = BridgeConfig { session_depth: config.session_depth, poll_interval: config.poll_interval, ..BridgeConfig::tcp() };
+  let mut session = BridgeSession::open(&session_config, "recorder".to_owned(), config.filters.clone(), stats.clone())?;
+  let mut recorder = Recorder::new(&config);
+  let mut last_flush = Instant::now();
+  let mut overflows = 0;
+
+  log::info!(directory:? = config.directory, format:? = config.format; "Recording CAN traffic");
+
+  while !stop.load(Ordering::Relaxed) {
+    let fpga_time_us = clock_sync(None).fpga_time_us;
+    let mut frames = 0;
+    let mut bytes = 0;
+
+    // Mark the lost frames before the frames read after them
+    let frames_read: Vec<HAL_CANStreamMessage> = session.read().copied().collect();
+    if session.overflows() != overflows {
+      overflows = session.overflows();
+      bytes += recorder.write_overflow(overflows, fpga_time_us)?;
+    }
+    for msg in &frames_read {
+      bytes += recorder.write_frame(msg, fpga_time_us)?;
+      frames += 1;
+    }
+    stats.frames_out.fetch_add(frames, Ordering::Relaxed);
+    stats.bytes_out.fetch_add(bytes, Ordering::Relaxed);
+
+    if last_flush.elapsed() >= config.flush_interval {
+      recorder.flush()?;
+      last_flush = Instant::now();
+    }
+
+    std::thread::sleep(session.next_poll_delay());
+  }
+
+  recorder.flush()?;
+  Ok(())
+}
+
+/// Start recording CAN traffic in the background, returning a handle that can be used to stop it.
+pub fn start_can_recorder(config: RecorderConfig) -> BridgeHandle {
+  let stats = BridgeStats::register("recorder", 0);
+  BridgeHandle::spawn(stats, move |stop, stats| run_can_recorder_until(config, stop, stats))
+}
+
+/// C representation of a RecorderConfig. Any field left as zero (or null) takes on its default,
+/// except for the directory, which is required.
+#[repr(C)]
+pub struct CRecorderConfig {
+  pub directory: *const c_char,
+  pub prefix: *const c_char,
+  pub format: RecordingFormat,
+  pub candump_interface: *const c_char,
+  pub filters: *const CanFilter,
+  pub filters_len: usize,
+  pub max_file_size: u64,
+  pub max_files: u32,
+  pub session_depth: u32,
+  pub poll_interval_ms: u32,
+  pub flush_interval_ms: u32,
+}
+
+impl CRecorderConfig {
+  pub(crate) fn to_config(&self) -> anyhow::Result<RecorderConfig> {
+    if self.directory.is_null() {
+      anyhow::bail!("No directory given");
+    }
+    let mut config = RecorderConfig::new(unsafe { CStr::from_ptr(self.directory) }.to_str()?);
+    if !self.prefix.is_null() {
+      config.prefix = unsafe { CStr::from_ptr(self.prefix) }.to_str()?.to_owned();
+    }
+    config.format = self.format;
+    if !self.candump_interface.is_null() {
+      config.candump_interface = unsafe { CStr::from_ptr(self.candump_interface) }.to_str()?.to_owned();
+    }
+    if !self.filters.is_null() {
+      config.filters = unsafe { std::slice::from_raw_parts(self.filters, self.filters_len) }.to_vec();
+    }
+    if self.max_file_size != 0 { config.max_file_size = self.max_file_size; }
+    if self.max_files != 0 { config.max_files = self.max_files as usize; }
+    if self.session_depth != 0 { config.session_depth = self.session_depth; }
+    if self.poll_interval_ms != 0 { config.poll_interval = Duration::from_millis(self.poll_interval_ms as u64); }
+    if self.flush_interval_ms != 0 { config.flush_interval = Duration::from_millis(self.flush_interval_ms as u64); }
+    Ok(config)
+  }
+}
+
+/// Start recording CAN traffic in the background. Returns null if the configuration is invalid.
+#[no_mangle]
+pub extern "C" fn can_recorder_start(config: CRecorderConfig) -> *mut BridgeHandle {
+  crate::logging::init();
+  match config.to_config() {
+    Ok(config) => Box::into_raw(Box::new(start_can_recorder(config))),
+    Err(e) => {
+      log::error!(error:% = e; "Invalid CAN recorder configuration");
+      std::ptr::null_mut()
+    }
+  }
+}
+
+#[cfg(feature = "jni")]
+mod jni {
+  use std::time::Duration;
+
+  use jni::{objects::{JClass, JObject}, sys::jlong, JNIEnv};
+
+  use crate::bridge_config::jni::{get_filters, get_int, get_string};
+
+  use super::{start_can_recorder, RecorderConfig, RecordingFormat};
+
+  fn read_config<'local>(env: &mut JNIEnv<'local>, obj: &JObject<'local>) -> anyhow::Result<RecorderConfig> {
+    let Some(directory) = get_string(env, obj, "directory")? else {
+      anyhow::bail!("No directory given");
+    };
+    let mut config = RecorderConfig::new(directory);
+    if let Some(prefix) = get_string(env, obj, "prefix")? {
+      config.prefix = prefix;
+    }
+    config.format = match get_int(env, obj, "format")? {
+      0 => RecordingFormat::Binary,
+      1 => RecordingFormat::Candump,
+      2 => RecordingFormat::Both,
+      format => anyhow::bail!("Unknown format {}", format),
+    };
+    if let Some(interface) = get_string(env, obj, "candumpInterface")? {
+      config.candump_interface = interface;
+    }
+    config.filters = get_filters(env, obj, "filters")?;
+    match env.get_field(obj, "maxFileSize", "J")?.j()? {
+      0 => (),
+      max_file_size => config.max_file_size = max_file_size as u64,
+    }
+    match get_int(env, obj, "maxFiles")? {
+      0 => (),
+      max_files => config.max_files = max_files as usize,
+    }
+    match get_int(env, obj, "sessionDepth")? {
+      0 => (),
+      session_depth => config.session_depth = session_depth as u32,
+    }
+    match get_int(env, obj, "pollIntervalMs")? {
+      0 => (),
+      poll_interval_ms => config.poll_interval = Duration::from_millis(poll_interval_ms as u64),
+    }
+    match get_int(env, obj, "flushIntervalMs")? {
+      0 => (),
+      flush_interval_ms => config.flush_interval = Duration::from_millis(flush_interval_ms as u64),
+    }
+    Ok(config)
+  }
+
+  #[no_mangle]
+  pub extern "system" fn Java_au_grapplerobotics_CanRecorder_startInternal<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    config: JObject<'local>
+  ) -> jlong {
+    match read_config(&mut env, &config) {
+      Ok(config) => Box::into_raw(Box::new(start_can_recorder(config))) as jlong,
+      Err(e) => {
+        env.throw_new("java/lang/IllegalArgumentException", format!("Invalid CAN Recorder Configuration: {}", e)).ok();
+        0
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::{Path, PathBuf};
+
+  use super::{read_recording, recording_files, Recorder, RecorderConfig, RecordingEntry, RecordingFormat};
+
+  // Frames are 8 bytes, so each binary record is 21 bytes, after the 25 byte header.
+  const HEADER_LEN: u64 = 25;
+  const RECORD_LEN: u64 = 21;
+
+  fn test_directory(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("grapplefrc-recorder-{}-{}", name, std::process::id()));
+    std::fs::remove_dir_all(&directory).ok();
+    std::fs::create_dir_all(&directory).unwrap();
+    directory
+  }
+
+  // The files in the directory, oldest first.
+  fn files(directory: &Path, extension: &str) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(directory).unwrap()
+      .map(|e| e.unwrap().path())
+      .filter(|p| p.extension().is_some_and(|e| e == extension))
+      .collect();
+    files.sort();
+    files
+  }
+
+  fn frames(entries: &[RecordingEntry]) -> Vec<(u32, Vec<u8>)> {
+    entries.iter().filter_map(|e| match e {
+      RecordingEntry::Frame(frame) => Some((frame.id, frame.data.clone())),
+      RecordingEntry::Overflow { .. } => None,
+    }).collect()
+  }
+
+  fn write_frames(recorder: &mut Recorder, ids: impl IntoIterator<Item = u32>) {
+    for id in ids {
+      recorder.write_binary(1_000 * id as u64, id, 8, &[id as u8; 8]).unwrap();
+      recorder.write_candump(1_000 * id as u64, id, &[id as u8; 8]).unwrap();
+    }
+    recorder.flush().unwrap();
+  }
+
+  #[test]
+  fn round_trip() {
+    let directory = test_directory("round-trip");
+    let mut recorder = Recorder::new(&RecorderConfig { format: RecordingFormat::Both, ..RecorderConfig::new(&directory) });
+    let sent = vec![(0x0A060187, vec![0xDE, 0xAD, 0xBE, 0xEF]), (0x1FFFFFFF, vec![]), (0x60000, vec![1, 2, 3, 4, 5, 6, 7, 8])];
+    for (i, (id, data)) in sent.iter().enumerate() {
+      recorder.write_binary(i as u64 * 1_000, *id, data.len() as u8, data).unwrap();
+      recorder.write_candump(i as u64 * 1_000, *id, data).unwrap();
+    }
+    recorder.flush().unwrap();
+
+    let binary = read_recording(&files(&directory, "grplcan")[0]).unwrap();
+    let candump = read_recording(&files(&directory, "log")[0]).unwrap();
+    assert_eq!(frames(&binary), sent);
+    assert_eq!(frames(&candump), sent);
+
+    // Both are in Unix time, and 1ms apart
+    for (b, c) in binary.iter().zip(&candump) {
+      assert!(b.timestamp_us().abs_diff(c.timestamp_us()) < 1_000_000);
+    }
+    assert_eq!(binary[1].timestamp_us() - binary[0].timestamp_us(), 1_000);
+    assert_eq!(candump[2].timestamp_us() - candump[1].timestamp_us(), 1_000);
+
+    std::fs::remove_dir_all(&directory).ok();
+  }
+
+  #[test]
+  fn overflow_marker() {
+    let directory = test_directory("overflow");
+    let mut recorder = Recorder::new(&RecorderConfig::new(&directory));
+    write_frames(&mut recorder, [1]);
+    recorder.write_overflow(3, 1_500).unwrap();
+    write_frames(&mut recorder, [2]);
+
+    let entries = read_recording(&files(&directory, "grplcan")[0]).unwrap();
+    assert_eq!(entries.len(), 3);
+    assert!(matches!(entries[1], RecordingEntry::Overflow { overflows: 3, .. }));
+    assert_eq!(frames(&entries), vec![(1, vec![1; 8]), (2, vec![2; 8])]);
+
+    std::fs::remove_dir_all(&directory).ok();
+  }
+
+  #[test]
+  fn truncated_final_record() {
+    let directory = test_directory("truncated");
+    let mut recorder = Recorder::new(&RecorderConfig::new(&directory));
+    write_frames(&mut recorder, 1..=3);
+    let path = files(&directory, "grplcan")[0].clone();
+    let len = std::fs::metadata(&path).unwrap().len();
+
+    // Cut off partway through the last frame's data, then partway through its ID
+    for cut in [3, 12] {
+      std::fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(len - cut).unwrap();
+      assert_eq!(frames(&read_recording(&path).unwrap()), vec![(1, vec![1; 8]), (2, vec![2; 8])]);
+    }
+
+    std::fs::remove_dir_all(&directory).ok();
+  }
+
+  #[test]
+  fn rotates_at_max_file_size() {
+    let directory = test_directory("rotate");
+    let config = RecorderConfig { max_file_size: HEADER_LEN + 2 * RECORD_LEN, max_files: 0, ..RecorderConfig::new(&directory) };
+    let mut recorder = Recorder::new(&config);
+    write_frames(&mut recorder, 1..=5);
+
+    let files = files(&directory, "grplcan");
+    assert_eq!(files.len(), 3);
+    for file in &files {
+      assert!(std::fs::metadata(file).unwrap().len() <= config.max_file_size);
+    }
+
+    // Each file stands on its own, and together they hold every frame in order
+    let all: Vec<_> = files.iter().flat_map(|f| frames(&read_recording(f).unwrap())).collect();
+    assert_eq!(all, (1..=5).map(|id| (id, vec![id as u8; 8])).collect::<Vec<_>>());
+    assert_eq!(recording_files(&files[0]), files);
+
+    std::fs::remove_dir_all(&directory).ok();
+  }
+
+  #[test]
+  fn prunes_to_max_files() {
+    let directory = test_directory("prune");
+    let config = RecorderConfig { max_file_size: HEADER_LEN + RECORD_LEN, max_files: 2, ..RecorderConfig::new(&directory) };
+    let other_prefix = directory.join("other-0000000001-0000.grplcan");
+    std::fs::write(&other_prefix, b"").unwrap();
+
+    let mut recorder = Recorder::new(&config);
+    write_frames(&mut recorder, 1..=5);
+
+    // Only the newest files are kept, and files with other prefixes are left alone
+    let files: Vec<_> = files(&directory, "grplcan").into_iter().filter(|f| *f != other_prefix).collect();
+    assert_eq!(files.len(), 2);
+    let kept: Vec<_> = files.iter().flat_map(|f| frames(&read_recording(f).unwrap())).collect();
+    assert_eq!(kept, vec![(4, vec![4; 8]), (5, vec![5; 8])]);
+    assert!(other_prefix.exists());
+
+    std::fs::remove_dir_all(&directory).ok();
+  }
+
+  #[test]
+  fn recording_files_order() {
+    let directory = test_directory("order");
+    for name in [
+      "grapplecan-0000000100-0010.grplcan",
+      "grapplecan-0000000100-0000.grplcan",
+      "grapplecan-0000000100-0002.grplcan",
+      "grapplecan-0000000100-0001.grplcan",
+      "grapplecan-0000000100-0001.log",
+      "grapplecan-0000000200-0000.grplcan",
+      "notes.grplcan",
+    ] {
+      std::fs::write(directory.join(name), b"").unwrap();
+    }
+
+    let names = |path: &str| -> Vec<String> {
+      recording_files(&directory.join(path)).iter().map(|p| p.file_name().unwrap().to_string_lossy().into_owned()).collect()
+    };
+    // Starting partway through a run only returns that file and the ones after it
+    assert_eq!(names("grapplecan-0000000100-0001.grplcan"), vec![
+      "grapplecan-0000000100-0001.grplcan",
+      "grapplecan-0000000100-0002.grplcan",
+      "grapplecan-0000000100-0010.grplcan",
+    ]);
+    assert_eq!(names("grapplecan-0000000200-0000.grplcan"), vec!["grapplecan-0000000200-0000.grplcan"]);
+    assert_eq!(names("notes.grplcan"), vec!["notes.grplcan"]);
+
+    std::fs::remove_dir_all(&directory).ok();
+  }
+}
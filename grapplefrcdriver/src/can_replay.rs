@@ -0,0 +1,310 @@
+use std::{path::{Path, PathBuf}, sync::{Arc, OnceLock}, time::{Duration, Instant}};
+
+use crate::{bridge_protocol::CanFilter, can::{set_default_transport, CanFrame, CanTransport}, can_recorder::{read_recording, recording_files, RecordedFrame, RecordingEntry}};
+
+struct ReplayState {
+  frames: Vec<RecordedFrame>,
+  // Set when the first transport is created
+  started: OnceLock<Instant>,
+  speed: f64,
+}
+
+impl ReplayState {
+  fn first_us(&self) -> u64 {
+    self.frames.first().map(|f| f.timestamp_us).unwrap_or(0)
+  }
+
+  // The recording's clock, in Unix microseconds. It stays at the first frame until the replay starts.
+  fn now_us(&self) -> u64 {
+    let elapsed = self.started.get().map(|started| started.elapsed()).unwrap_or_default();
+    self.first_us() + (elapsed.as_secs_f64() * self.speed * 1_000_000.0) as u64
+  }
+}
+
+/// Plays back a recording made by can_recorder, as if its frames were arriving on the bus. The
+/// replay starts when the first transport is created, so frames aren't skipped while the robot
+/// program is still starting up. It then runs on its own clock, so every device replaying it sees
+/// the same point in the recording.
+#[derive(Clone)]
+pub struct CanReplay {
+  state: Arc<ReplayState>,
+}
+
+impl CanReplay {
+  /// Open the given recording files to be replayed one after the other. `speed` is how much faster
+  /// than real time to replay, e.g. 1.0 for real time or 10.0 for ten times as fast.
+  pub fn open(paths: &[PathBuf], speed: f64) -> anyhow::Result<Self> {
+    if !(speed > 0.0 && speed.is_finite()) {
+      anyhow::bail!("Invalid replay speed {}", speed);
+    }
+
+    let mut frames = vec![];
+    for path in paths {
+      let entries = read_recording(path).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+      frames.extend(entries.into_iter().filter_map(|entry| match entry {
+        RecordingEntry::Frame(frame) => Some(frame),
+        RecordingEntry::Overflow { .. } => None,
+      }));
+    }
+    frames.sort_by_key(|f| f.timestamp_us);
+
+    log::info!(files = paths.len(), frames = frames.len(), speed; "Replaying CAN recording");
+    Ok(Self { state: Arc::new(ReplayState { frames, started: OnceLock::new(), speed }) })
+  }
+
+  /// Open a recording starting at `path`, along with the files the recorder rotated to after it.
+  pub fn open_recording(path: &Path, speed: f64) -> anyhow::Result<Self> {
+    Self::open(&recording_files(path), speed)
+  }
+
+  /// A transport that receives the recording's frames as they come up, starting the replay if this
+  /// is the first. Each transport sees every frame, so give each device its own.
+  pub fn transport(&self) -> ReplayTransport {
+    self.state.started.get_or_init(Instant::now);
+    ReplayTransport { state: self.state.clone(), next: 0 }
+  }
+
+  /// How far into the recording the replay is. This is zero until the replay starts.
+  pub fn position(&self) -> Duration {
+    Duration::from_micros(self.state.now_us() - self.state.first_us())
+  }
+
+  /// The length of the recording.
+  pub fn duration(&self) -> Duration {
+    match (self.state.frames.first(), self.state.frames.last()) {
+      (Some(first), Some(last)) => Duration::from_micros(last.timestamp_us - first.timestamp_us),
+      _ => Duration::ZERO,
+    }
+  }
+
+  pub fn is_finished(&self) -> bool {
+    self.state.frames.last().is_none_or(|last| self.state.now_us() >= last.timestamp_us)
+  }
+
+  /// Replay the recording to every device created from now on without a transport of its own,
+  /// instead of the roboRIO's CAN bus. Start the replay before creating any devices.
+  pub fn replay_to_all_devices(&self) {
+    let replay = self.clone();
+    set_default_transport(Some(Box::new(move || Box::new(replay.transport()))));
+  }
+}
+
+/// Go back to creating devices on the roboRIO's CAN bus, after CanReplay::replay_to_all_devices.
+pub fn stop_replay() {
+  set_default_transport(None);
+}
+
+/// Receives frames from a CanReplay once the replay reaches them, timestamped in milliseconds since
+/// the start of the recording. Frames sent to it are dropped, so requests to devices will time out,
+/// as the recording can't answer them.
+pub struct ReplayTransport {
+  state: Arc<ReplayState>,
+  next: usize,
+}
+
+impl CanTransport for ReplayTransport {
+  fn send(&mut self, id: u32, _data: &[u8]) -> anyhow::Result<()> {
+    log::trace!(id = format!("0x{:08X}", id).as_str(); "Dropping frame sent during replay");
+    Ok(())
+  }
+
+  fn receive(&mut self, filter: CanFilter) -> Option<CanFrame> {
+    let now_us = self.state.now_us();
+    while let Some(frame) = self.state.frames.get(self.next).filter(|f| f.timestamp_us <= now_us) {
+      self.next += 1;
+      if filter.matches(frame.id) {
+        // Unix milliseconds don't fit in the frame's timestamp, and would wrap
+        let timestamp = ((frame.timestamp_us - self.state.first_us()) / 1000) as u32;
+        return Some(CanFrame { id: frame.id, timestamp, data: frame.data.clone() });
+      }
+    }
+    None
+  }
+}
+
+/// Replay the recording starting at `path` to every device created from now on, at `speed` times
+/// real time. See CanReplay::open_recording and CanReplay::replay_to_all_devices.
+pub fn start_replay(path: &Path, speed: f64) -> anyhow::Result<()> {
+  CanReplay::open_recording(path, speed)?.replay_to_all_devices();
+  Ok(())
+}
+
+#[cfg(feature = "c")]
+mod c {
+  use std::{ffi::{c_char, CStr}, path::Path};
+
+  use super::{start_replay, stop_replay};
+
+  /// Replay the recording starting at `path` to every device created from now on, at `speed` times
+  /// real time. Returns false if the recording couldn't be read.
+  #[no_mangle]
+  pub extern "C" fn can_replay_start(path: *const c_char, speed: f64) -> bool {
+    crate::logging::init();
+    if path.is_null() {
+      return false;
+    }
+    let result = unsafe { CStr::from_ptr(path) }.to_str().map_err(anyhow::Error::from)
+      .and_then(|path| start_replay(Path::new(path), speed));
+    match result {
+      Ok(()) => true,
+      Err(e) => {
+        log::error!(error:% = e; "Could not start CAN replay");
+        false
+      }
+    }
+  }
+
+  /// Go back to creating devices on the roboRIO's CAN bus.
+  #[no_mangle]
+  pub extern "C" fn can_replay_stop() {
+    stop_replay();
+  }
+}
+
+#[cfg(feature = "jni")]
+mod jni {
+  use std::path::Path;
+
+  use jni::{objects::{JClass, JString}, sys::jdouble, JNIEnv};
+
+  #[no_mangle]
+  pub extern "system" fn Java_au_grapplerobotics_CanReplay_startInternal<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    path: JString<'local>,
+    speed: jdouble,
+  ) {
+    let path: String = match env.get_string(&path) {
+      Ok(path) => path.into(),
+      Err(_) => {
+        env.throw_new("java/lang/IllegalArgumentException", "No recording given").ok();
+        return;
+      }
+    };
+    if let Err(e) = super::start_replay(Path::new(&path), speed) {
+      env.throw_new("java/lang/IllegalArgumentException", format!("Could not start CAN replay: {}", e)).ok();
+    }
+  }
+
+  #[no_mangle]
+  pub extern "system" fn Java_au_grapplerobotics_CanReplay_stopInternal<'local>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+  ) {
+    super::stop_replay();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{path::PathBuf, time::{Duration, Instant}};
+
+  use crate::{bridge_protocol::CanFilter, can::{CanFrame, CanTransport}, can_recorder::{Recorder, RecorderConfig}};
+
+  use super::{CanReplay, ReplayTransport};
+
+  const TIMEOUT: Duration = Duration::from_secs(5);
+  const ANY: CanFilter = CanFilter { id: 0, mask: 0 };
+
+  // Record a frame with each ID at the given time, in microseconds, returning the files written.
+  fn record(name: &str, frames: &[(u64, u32)], max_file_size: u64) -> Vec<PathBuf> {
+    let directory = std::env::temp_dir().join(format!("grapplefrc-replay-{}-{}", name, std::process::id()));
+    std::fs::remove_dir_all(&directory).ok();
+    std::fs::create_dir_all(&directory).unwrap();
+
+    let mut recorder = Recorder::new(&RecorderConfig { max_file_size, max_files: 0, ..RecorderConfig::new(&directory) });
+    for (timestamp_us, id) in frames {
+      recorder.write_binary(*timestamp_us, *id, 1, &[*id as u8]).unwrap();
+    }
+    recorder.flush().unwrap();
+
+    let mut files: Vec<PathBuf> = std::fs::read_dir(&directory).unwrap().map(|e| e.unwrap().path()).collect();
+    files.sort();
+    files
+  }
+
+  fn receive_all(transport: &mut ReplayTransport, filter: CanFilter) -> Vec<CanFrame> {
+    std::iter::from_fn(|| transport.receive(filter)).collect()
+  }
+
+  fn ids(frames: &[CanFrame]) -> Vec<u32> {
+    frames.iter().map(|f| f.id).collect()
+  }
+
+  fn wait_until_finished(replay: &CanReplay) {
+    let started = Instant::now();
+    while !replay.is_finished() {
+      assert!(started.elapsed() < TIMEOUT, "Timed out");
+      std::thread::sleep(Duration::from_millis(1));
+    }
+  }
+
+  #[test]
+  fn frames_wait_for_the_replay_clock() {
+    let files = record("clock", &[(5_000_000, 1), (65_000_000, 2)], 1024);
+    // A minute of recording in 600ms
+    let replay = CanReplay::open(&files, 100.0).unwrap();
+    assert_eq!(replay.duration(), Duration::from_secs(60));
+    assert_eq!(replay.position(), Duration::ZERO);
+
+    let mut transport = replay.transport();
+    let started = Instant::now();
+    let first = transport.receive(ANY).unwrap();
+    assert_eq!((first.id, first.timestamp, first.data), (1, 0, vec![1]));
+    assert!(transport.receive(ANY).is_none());
+
+    wait_until_finished(&replay);
+    assert!(started.elapsed() >= Duration::from_millis(500));
+    let second = transport.receive(ANY).unwrap();
+    // Timestamps count from the start of the recording
+    assert_eq!((second.id, second.timestamp), (2, 60_000));
+  }
+
+  #[test]
+  fn each_transport_sees_every_frame() {
+    let files = record("transports", &[(0, 1), (1_000, 2), (2_000, 3)], 1024);
+    let replay = CanReplay::open(&files, 1_000.0).unwrap();
+    let mut a = replay.transport();
+    let mut b = replay.transport();
+    wait_until_finished(&replay);
+
+    assert_eq!(ids(&receive_all(&mut a, ANY)), vec![1, 2, 3]);
+    assert_eq!(ids(&receive_all(&mut b, ANY)), vec![1, 2, 3]);
+  }
+
+  #[test]
+  fn filters_are_applied() {
+    let files = record("filters", &[(0, 0x101), (1_000, 0x202), (2_000, 0x103), (3_000, 0x204)], 1024);
+    let replay = CanReplay::open(&files, 1_000.0).unwrap();
+    let mut transport = replay.transport();
+    wait_until_finished(&replay);
+
+    assert_eq!(ids(&receive_all(&mut transport, CanFilter { id: 0x200, mask: 0xF00 })), vec![0x202, 0x204]);
+  }
+
+  #[test]
+  fn replays_rotated_files() {
+    // Two frames per file
+    let frames: Vec<(u64, u32)> = (1..=5).map(|id| (id as u64 * 1_000, id)).collect();
+    let files = record("rotated", &frames, 25 + 2 * 14);
+    assert_eq!(files.len(), 3);
+
+    let replay = CanReplay::open_recording(&files[0], 1_000.0).unwrap();
+    let mut transport = replay.transport();
+    wait_until_finished(&replay);
+    assert_eq!(ids(&receive_all(&mut transport, ANY)), vec![1, 2, 3, 4, 5]);
+
+    // Starting partway through only replays from there on
+    let replay = CanReplay::open_recording(&files[1], 1_000.0).unwrap();
+    let mut transport = replay.transport();
+    wait_until_finished(&replay);
+    assert_eq!(ids(&receive_all(&mut transport, ANY)), vec![3, 4, 5]);
+  }
+
+  #[test]
+  fn rejects_invalid_speed() {
+    let files = record("speed", &[(0, 1)], 1024);
+    assert!(CanReplay::open(&files, 0.0).is_err());
+    assert!(CanReplay::open(&files, f64::NAN).is_err());
+  }
+}
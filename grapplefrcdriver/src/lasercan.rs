@@ -3,7 +3,7 @@ use std::time::{Duration, Instant};
 use bounded_static::ToBoundedStatic as _;
 pub use grapple_frc_msgs::{grapple::{Request, errors::{GrappleResult, GrappleError}, lasercan::{LaserCanMessage, LaserCanRoi, LaserCanMeasurement, LaserCanTimingBudget, LaserCanRangingMode}, GrappleDeviceMessage, DEVICE_TYPE_DISTANCE_SENSOR}, request_factory};
 
-use crate::can::GrappleCanDriver;
+use crate::can::{CanTransport, GrappleCanDriver, default_transport};
 
 #[cfg(feature = "pyo3")]
 use pyo3::prelude::*;
@@ -18,13 +18,19 @@ pub struct LaserCAN {
 
 impl LaserCAN {
   pub fn new(can_id: u8) -> Self {
+    Self::with_transport(can_id, default_transport())
+  }
+
+  /// Talk to the device over the given transport, e.g. a bridge_client::BridgeClient connected to
+  /// a robot's CAN bridge, instead of the roboRIO's CAN bus.
+  pub fn with_transport(can_id: u8, transport: Box<dyn CanTransport>) -> Self {
     Self {
-      driver: GrappleCanDriver::new(can_id, DEVICE_TYPE_DISTANCE_SENSOR),
+      driver: GrappleCanDriver::with_transport(can_id, DEVICE_TYPE_DISTANCE_SENSOR, transport),
       last_status_frame: None
     }
   }
 
-  fn get_measurement(&mut self) -> Option<LaserCanMeasurement> {
+  pub(crate) fn get_measurement(&mut self) -> Option<LaserCanMeasurement> {
     self.driver.spin(&mut |_id, msg| {
       match msg {
         GrappleDeviceMessage::DistanceSensor(LaserCanMessage::Measurement(measurement)) => {
@@ -48,21 +54,21 @@ impl LaserCAN {
     }
   }
 
-  fn set_timing_budget(&mut self, budget: LaserCanTimingBudget) -> GrappleResult<'static, ()> {
+  pub(crate) fn set_timing_budget(&mut self, budget: LaserCanTimingBudget) -> GrappleResult<'static, ()> {
     let (encode, decode) = request_factory!(data, GrappleDeviceMessage::DistanceSensor(LaserCanMessage::SetTimingBudget(data)));
     decode(self.driver.request(encode(budget), 200, 3)?)
       .map_err(|e| e.to_static())?.map_err(|e| e.to_static())?;
     Ok(())
   }
 
-  fn set_roi(&mut self, roi: LaserCanRoi) -> GrappleResult<'static, ()> {
+  pub(crate) fn set_roi(&mut self, roi: LaserCanRoi) -> GrappleResult<'static, ()> {
     let (encode, decode) = request_factory!(data, GrappleDeviceMessage::DistanceSensor(LaserCanMessage::SetRoi(data)));
     decode(self.driver.request(encode(roi), 200, 3)?)
       .map_err(|e| e.to_static())?.map_err(|e| e.to_static())?;
     Ok(())
   }
 
-  fn set_range(&mut self, mode: LaserCanRangingMode) -> GrappleResult<'static, ()> {
+  pub(crate) fn set_range(&mut self, mode: LaserCanRangingMode) -> GrappleResult<'static, ()> {
     let (encode, decode) = request_factory!(data, GrappleDeviceMessage::DistanceSensor(LaserCanMessage::SetRange(data)));
     decode(self.driver.request(encode(mode), 200, 3)?)
       .map_err(|e| e.to_static())?.map_err(|e| e.to_static())?;
@@ -110,6 +116,7 @@ mod c {
   // C
   #[no_mangle]
   pub extern "C" fn lasercan_new(can_id: u8) -> *mut LaserCAN {
+    crate::logging::init();
     Box::into_raw(Box::new(LaserCAN::new(can_id)))
   }
 
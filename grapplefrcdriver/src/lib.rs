@@ -13,11 +13,24 @@ use jni::{JNIEnv, objects::{JThrowable, JValue}};
 
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 
+pub mod bridge_api;
+pub mod bridge_client;
+pub mod bridge_config;
+pub mod bridge_handle;
+pub mod bridge_json;
+pub mod bridge_protocol;
+pub mod bridge_session;
+pub mod bridge_stats;
 pub mod calling;
 pub mod can;
 pub mod can_bridge;
+pub mod can_recorder;
+pub mod can_replay;
 pub mod lasercan;
+pub mod logging;
 pub mod mitocandria;
+#[cfg(target_os = "linux")]
+pub mod socketcan;
 pub mod ws_can_bridge;
 
 #[repr(C)]
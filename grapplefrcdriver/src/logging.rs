@@ -0,0 +1,230 @@
+use std::sync::{Arc, Once, RwLock};
+
+use log::{kv::{Key, Value, VisitSource}, Level, LevelFilter, Log, Metadata, Record};
+
+#[cfg(feature = "pyo3")]
+use pyo3::prelude::*;
+
+/// How much the driver logs, from nothing at all to everything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "pyo3", pyclass(eq, eq_int))]
+#[repr(C)]
+pub enum LogLevel {
+  Off = 0,
+  Error = 1,
+  Warn = 2,
+  Info = 3,
+  Debug = 4,
+  Trace = 5,
+}
+
+impl From<LogLevel> for LevelFilter {
+  fn from(level: LogLevel) -> Self {
+    match level {
+      LogLevel::Off => LevelFilter::Off,
+      LogLevel::Error => LevelFilter::Error,
+      LogLevel::Warn => LevelFilter::Warn,
+      LogLevel::Info => LevelFilter::Info,
+      LogLevel::Debug => LevelFilter::Debug,
+      LogLevel::Trace => LevelFilter::Trace,
+    }
+  }
+}
+
+impl From<Level> for LogLevel {
+  fn from(level: Level) -> Self {
+    match level {
+      Level::Error => LogLevel::Error,
+      Level::Warn => LogLevel::Warn,
+      Level::Info => LogLevel::Info,
+      Level::Debug => LogLevel::Debug,
+      Level::Trace => LogLevel::Trace,
+    }
+  }
+}
+
+/// Receives each log message, along with its level and target (the module it came from). The
+/// message includes any structured fields, e.g. `Client Connected client=10.0.0.5:52311`.
+pub type LogSink = Box<dyn Fn(LogLevel, &str, &str) + Send + Sync>;
+
+static SINK: RwLock<Option<Arc<LogSink>>> = RwLock::new(None);
+static INIT: Once = Once::new();
+
+// Appends each key-value pair of a record to the message as ` key=value`
+struct KeyValues<'a>(&'a mut String);
+
+impl<'kvs> VisitSource<'kvs> for KeyValues<'_> {
+  fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+    self.0.push_str(&format!(" {}={}", key, value));
+    Ok(())
+  }
+}
+
+struct GrappleLogger;
+
+impl Log for GrappleLogger {
+  fn enabled(&self, metadata: &Metadata) -> bool {
+    metadata.level() <= log::max_level()
+  }
+
+  fn log(&self, record: &Record) {
+    if !self.enabled(record.metadata()) {
+      return;
+    }
+
+    let mut message = record.args().to_string();
+    record.key_values().visit(&mut KeyValues(&mut message)).ok();
+
+    // Don't hold the lock while calling the sink, in case it logs or changes the sink itself.
+    let sink = SINK.read().unwrap_or_else(|e| e.into_inner()).clone();
+    match sink {
+      Some(sink) => sink(record.level().into(), record.target(), &message),
+      None => println!("[{}] {}: {}", record.level(), record.target(), message),
+    }
+  }
+
+  fn flush(&self) {}
+}
+
+/// Install the driver's logger, which prints to stdout at LogLevel::Info unless told otherwise.
+/// The C, Java and Python bindings call this when they're first used, as does setting the level or
+/// sink. Rust applications are free to install their own `log` logger instead, in which case the
+/// driver's messages go to it.
+pub fn init() {
+  INIT.call_once(|| {
+    if log::set_logger(&GrappleLogger).is_ok() {
+      log::set_max_level(LevelFilter::Info);
+    }
+  });
+}
+
+/// Change how much the driver logs.
+pub fn set_level(level: LogLevel) {
+  init();
+  log::set_max_level(level.into());
+}
+
+/// Send log messages to `sink` instead of stdout, e.g. to record them in WPILib's DataLog. Pass
+/// None to go back to printing them.
+pub fn set_sink(sink: Option<LogSink>) {
+  init();
+  *SINK.write().unwrap_or_else(|e| e.into_inner()) = sink.map(Arc::new);
+}
+
+#[cfg(feature = "c")]
+mod c {
+  use std::ffi::{c_char, c_void, CString};
+
+  use super::{set_level, set_sink, LogLevel};
+
+  /// Called with the level, target and message of each log message. The strings are only valid
+  /// for the duration of the call. May be null.
+  pub type LogCallback = Option<extern "C" fn(level: LogLevel, target: *const c_char, message: *const c_char, context: *mut c_void)>;
+
+  // The context pointer is owned by the caller, who is responsible for it being usable from any thread
+  struct CallbackContext(*mut c_void);
+  unsafe impl Send for CallbackContext {}
+  unsafe impl Sync for CallbackContext {}
+
+  impl CallbackContext {
+    // Closures capture by field, so go through a method to capture the whole (Send) wrapper
+    fn get(&self) -> *mut c_void {
+      self.0
+    }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn grapple_set_log_level(level: LogLevel) {
+    set_level(level);
+  }
+
+  /// Send log messages to `callback` instead of stdout, passing `context` along with each. Pass a
+  /// null callback to go back to printing them.
+  #[no_mangle]
+  pub extern "C" fn grapple_set_log_callback(callback: LogCallback, context: *mut c_void) {
+    let context = CallbackContext(context);
+    set_sink(callback.map(|callback| -> super::LogSink {
+      Box::new(move |level, target, message| {
+        // Interior nul bytes can't be passed through, so drop the message rather than truncate it
+        if let (Ok(target), Ok(message)) = (CString::new(target), CString::new(message)) {
+          callback(level, target.as_ptr(), message.as_ptr(), context.get());
+        }
+      })
+    }));
+  }
+}
+
+#[cfg(feature = "jni")]
+mod jni {
+  use jni::{objects::{JClass, JObject, JValue}, sys::jint, JNIEnv};
+
+  use super::{init, set_level, set_sink, LogLevel};
+
+  // Called by GrappleJNI once the library is loaded, so Java users get log output by default.
+  #[no_mangle]
+  pub extern "system" fn Java_au_grapplerobotics_GrappleJNI_initLogging<'local>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+  ) {
+    init();
+  }
+
+  fn level_from_jni(level: jint) -> LogLevel {
+    match level {
+      i32::MIN..=0 => LogLevel::Off,
+      1 => LogLevel::Error,
+      2 => LogLevel::Warn,
+      3 => LogLevel::Info,
+      4 => LogLevel::Debug,
+      _ => LogLevel::Trace,
+    }
+  }
+
+  #[no_mangle]
+  pub extern "system" fn Java_au_grapplerobotics_GrappleLog_setLevelInternal<'local>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    level: jint,
+  ) {
+    set_level(level_from_jni(level));
+  }
+
+  #[no_mangle]
+  pub extern "system" fn Java_au_grapplerobotics_GrappleLog_setHandlerInternal<'local>(
+    env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handler: JObject<'local>,
+  ) {
+    if handler.is_null() {
+      set_sink(None);
+      return;
+    }
+
+    let (Ok(vm), Ok(handler)) = (env.get_java_vm(), env.new_global_ref(handler)) else {
+      return;
+    };
+
+    set_sink(Some(Box::new(move |level, target, message| {
+      // Log messages come from the driver's own threads, which the JVM doesn't know about yet
+      let Ok(mut env) = vm.attach_current_thread_as_daemon() else {
+        return;
+      };
+      // The thread stays attached, so free the strings once we're done with them
+      let result = env.with_local_frame(4, |env| -> jni::errors::Result<()> {
+        let target = env.new_string(target)?;
+        let message = env.new_string(message)?;
+        env.call_method(
+          &handler,
+          "log",
+          "(ILjava/lang/String;Ljava/lang/String;)V",
+          &[JValue::Int(level as jint), JValue::Object(&target), JValue::Object(&message)]
+        )?;
+        Ok(())
+      });
+      if result.is_err() {
+        // Don't leave the exception pending on a thread that will never check it
+        env.exception_clear().ok();
+      }
+    })));
+  }
+}
@@ -3,41 +3,213 @@ use std::{borrow::Cow, time::{Duration, Instant}};
 use bounded_static::ToBoundedStatic;
 pub use grapple_frc_msgs::{binmarshal::AsymmetricCow, grapple::{errors::{GrappleError, GrappleResult}, mitocandria::{self, MitocandriaAdjustableChannelRequest, MitocandriaChannelStatus, MitocandriaSwitchableChannelRequest}, GrappleDeviceMessage, Request, DEVICE_TYPE_POWER_DISTRIBUTION_MODULE}, request_factory};
 
-use crate::can::GrappleCanDriver;
+use crate::can::{CanTransport, GrappleCanDriver, default_transport};
 
 #[cfg(feature = "pyo3")]
 use pyo3::prelude::*;
 #[cfg(feature = "pyo3")]
 use grapple_frc_msgs::grapple::errors::{convert_grpl_result_to_py, convert_optional_grpl_result_to_py, GrappleResultPy};
 
+const MITOCANDRIA_NUM_CHANNELS: usize = 5;
+const MITOCANDRIA_STATUS_TIMEOUT: Duration = Duration::from_millis(500);
+const MITOCANDRIA_POLICY_RETRY: Duration = Duration::from_millis(1000);
+const MITOCANDRIA_POLICY_VOLTAGE_TOLERANCE: f64 = 0.05;
+
+/// Accumulated power usage of a single MitoCANdria channel since the last reset.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "pyo3", pyclass(get_all, eq))]
+#[repr(C)]
+pub struct MitocandriaChannelEnergy {
+  /// Charge drawn by the channel, in Amp-Hours
+  pub amp_hours: f64,
+  /// Energy drawn by the channel, in Watt-Hours
+  pub watt_hours: f64,
+  /// Highest current observed on the channel, in Amperes
+  pub peak_current: f64,
+  /// Time-averaged current of the channel, in Amperes
+  pub average_current: f64,
+}
+
+// Integrates current and voltage between successive status frames, using the frames' timestamps
+// (in milliseconds on the bus's clock) so the totals don't depend on when robot code polls, or on
+// the speed of a replay. Frames are only seen when the MitoCANdria is polled though, and the bus
+// may only keep the latest one, so the current is assumed to change linearly between polls.
+#[derive(Default)]
+struct EnergyAccumulator {
+  channels: [MitocandriaChannelEnergy; MITOCANDRIA_NUM_CHANNELS],
+  elapsed: Duration,
+  last_sample: Option<(u32, [(f64, f64); MITOCANDRIA_NUM_CHANNELS])>,
+}
+
+impl EnergyAccumulator {
+  fn sample(chan: &MitocandriaChannelStatus) -> (f64, f64) {
+    match chan {
+      MitocandriaChannelStatus::NonSwitchable { current } => (*current as f64 / 1000.0, 5.0),
+      MitocandriaChannelStatus::Switchable { current, .. } => (*current as f64 / 1000.0, 5.0),
+      MitocandriaChannelStatus::Adjustable { current, voltage, .. } => (*current as f64 / 1000.0, *voltage as f64 / 1000.0),
+    }
+  }
+
+  // `came_online` frames start a new integration, so the time the MitoCANdria was offline or
+  // rebooting isn't counted.
+  fn record(&mut self, timestamp: u32, came_online: bool, frame: &mitocandria::MitocandriaStatusFrame) {
+    let samples = frame.channels.map(|c| Self::sample(&c));
+
+    if let (Some((last_timestamp, last_samples)), false) = (self.last_sample, came_online) {
+      let dt = Duration::from_millis(timestamp.wrapping_sub(last_timestamp) as u64);
+      if !dt.is_zero() {
+        self.elapsed += dt;
+        let dt_hours = dt.as_secs_f64() / 3600.0;
+        for (i, chan) in self.channels.iter_mut().enumerate() {
+          let (i0, v0) = last_samples[i];
+          let (i1, v1) = samples[i];
+          chan.amp_hours += (i0 + i1) / 2.0 * dt_hours;
+          chan.watt_hours += (i0 * v0 + i1 * v1) / 2.0 * dt_hours;
+          chan.average_current = chan.amp_hours * 3600.0 / self.elapsed.as_secs_f64();
+        }
+      }
+    }
+
+    for (chan, (current, _)) in self.channels.iter_mut().zip(samples.iter()) {
+      chan.peak_current = chan.peak_current.max(*current);
+    }
+    self.last_sample = Some((timestamp, samples));
+  }
+
+  fn reset(&mut self) {
+    *self = Self::default();
+  }
+}
+
+/// The desired state of a single MitoCANdria channel when the board comes online.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct StartupChannelPolicy {
+  enabled: Option<bool>,
+  voltage: Option<f64>,
+}
+
+impl StartupChannelPolicy {
+  // Channels that can't be switched / adjusted are ignored, since the policy could never be met.
+  fn satisfied_by(&self, chan: &MitocandriaChannelStatus) -> bool {
+    let enabled_ok = match (self.enabled, chan) {
+      (Some(want), MitocandriaChannelStatus::Switchable { enabled, .. } | MitocandriaChannelStatus::Adjustable { enabled, .. }) => *enabled == want,
+      _ => true,
+    };
+    let voltage_ok = match (self.voltage, chan) {
+      (Some(want), MitocandriaChannelStatus::Adjustable { voltage_setpoint, .. }) => ((*voltage_setpoint as f64 / 1000.0) - want).abs() < MITOCANDRIA_POLICY_VOLTAGE_TOLERANCE,
+      _ => true,
+    };
+    enabled_ok && voltage_ok
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StartupPolicyState {
+  /// The policy needs to be applied, and hasn't yet been seen in a status frame.
+  Pending { last_attempt: Option<Instant> },
+  /// The policy has been seen in a status frame. It won't be applied again until the board
+  /// next comes online, so robot code is free to change the channels in the meantime.
+  Verified,
+}
+
+struct StartupPolicy {
+  channels: [StartupChannelPolicy; MITOCANDRIA_NUM_CHANNELS],
+  state: StartupPolicyState,
+}
+
+impl Default for StartupPolicy {
+  fn default() -> Self {
+    Self { channels: Default::default(), state: StartupPolicyState::Pending { last_attempt: None } }
+  }
+}
+
+impl StartupPolicy {
+  fn is_empty(&self) -> bool {
+    self.channels.iter().all(|c| c.enabled.is_none() && c.voltage.is_none())
+  }
+
+  fn satisfied_by(&self, frame: &mitocandria::MitocandriaStatusFrame) -> bool {
+    self.channels.iter().zip(frame.channels.iter()).all(|(policy, chan)| policy.satisfied_by(chan))
+  }
+
+  // The board has just come online, and may have lost the channel states the policy set.
+  fn came_online(&mut self) {
+    self.state = StartupPolicyState::Pending { last_attempt: None };
+  }
+
+  fn check(&mut self, frame: &mitocandria::MitocandriaStatusFrame) {
+    if matches!(self.state, StartupPolicyState::Pending { .. }) && self.satisfied_by(frame) {
+      self.state = StartupPolicyState::Verified;
+    }
+  }
+}
+
 #[cfg_attr(feature = "pyo3", pyclass)]
 pub struct MitoCANdria {
   driver: GrappleCanDriver,
-  last_status_frame: Option<(Instant, mitocandria::MitocandriaStatusFrame)>
+  last_status_frame: Option<(Instant, mitocandria::MitocandriaStatusFrame)>,
+  energy: EnergyAccumulator,
+  startup_policy: StartupPolicy,
 }
 
 impl MitoCANdria {
   pub fn new(can_id: u8) -> Self {
+    Self::with_transport(can_id, default_transport())
+  }
+
+  /// Talk to the device over the given transport, e.g. a bridge_client::BridgeClient connected to
+  /// a robot's CAN bridge, instead of the roboRIO's CAN bus.
+  pub fn with_transport(can_id: u8, transport: Box<dyn CanTransport>) -> Self {
     Self {
-      driver: GrappleCanDriver::new(can_id, DEVICE_TYPE_POWER_DISTRIBUTION_MODULE),
+      driver: GrappleCanDriver::with_transport(can_id, DEVICE_TYPE_POWER_DISTRIBUTION_MODULE, transport),
       last_status_frame: None,
+      energy: EnergyAccumulator::default(),
+      startup_policy: StartupPolicy::default(),
     }
   }
 
-  fn get_status(&mut self) -> Option<mitocandria::MitocandriaStatusFrame> {
-    self.driver.spin(&mut |_id, msg| {
+  pub(crate) fn get_status(&mut self) -> Option<mitocandria::MitocandriaStatusFrame> {
+    self.get_status_at(Instant::now())
+  }
+
+  // The status frame has no uptime or boot counter, so the board is known to have come online (or
+  // rebooted) when it's first seen, or when it goes quiet for MITOCANDRIA_STATUS_TIMEOUT: either as
+  // a gap between the frames read here, or by a call finding no frames for that long. Calling this
+  // slowly doesn't look like a reboot, as long as each call finds a new frame.
+  fn get_status_at(&mut self, now: Instant) -> Option<mitocandria::MitocandriaStatusFrame> {
+    let mut latest = None;
+    let mut came_online = false;
+    let mut last_timestamp: Option<u32> = None;
+    let was_online = self.last_status_frame.is_some();
+    let energy = &mut self.energy;
+    self.driver.spin_timestamped(&mut |timestamp, _id, msg| {
       match msg {
         GrappleDeviceMessage::PowerDistributionModule(mitocandria::MitocandriaMessage::StatusFrame(frame)) => {
-          self.last_status_frame = Some((Instant::now(), frame));
-          false
+          let frame_came_online = match last_timestamp {
+            Some(last) => timestamp.wrapping_sub(last) as u128 > MITOCANDRIA_STATUS_TIMEOUT.as_millis(),
+            None => !was_online,
+          };
+          came_online |= frame_came_online;
+          energy.record(timestamp, frame_came_online, &frame);
+          last_timestamp = Some(timestamp);
+          latest = Some(frame);
+          true
         },
         _ => true
       }
     });
 
-    match self.last_status_frame.clone() {
+    if let Some(frame) = latest {
+      if came_online {
+        self.startup_policy.came_online();
+      }
+      self.startup_policy.check(&frame);
+      self.last_status_frame = Some((now, frame));
+    }
+
+    let status = match self.last_status_frame.clone() {
       Some((time, frame)) => {
-        if (Instant::now() - time) > Duration::from_millis(500) {
+        if now.saturating_duration_since(time) > MITOCANDRIA_STATUS_TIMEOUT {
           self.last_status_frame = None;
           None
         } else {
@@ -45,6 +217,44 @@ impl MitoCANdria {
         }
       },
       None => None
+    };
+
+    if let Some(frame) = &status {
+      self.send_startup_policy(now, frame);
+    }
+    status
+  }
+
+  // Send whatever the channels need to match the startup policy, without waiting for the replies so
+  // the getters never block on it. The policy is verified by check() once the status frames show it,
+  // and sent again every MITOCANDRIA_POLICY_RETRY until they do.
+  fn send_startup_policy(&mut self, now: Instant, frame: &mitocandria::MitocandriaStatusFrame) {
+    let StartupPolicyState::Pending { last_attempt } = self.startup_policy.state else { return };
+    if last_attempt.is_some_and(|last| now.saturating_duration_since(last) < MITOCANDRIA_POLICY_RETRY) {
+      return;
+    }
+    self.startup_policy.state = StartupPolicyState::Pending { last_attempt: Some(now) };
+
+    for (channel, (policy, chan)) in self.startup_policy.channels.into_iter().zip(frame.channels.iter()).enumerate() {
+      if policy.satisfied_by(chan) {
+        continue;
+      }
+      let channel = channel as u8;
+
+      // Setting the voltage disables the channel, so it has to go first.
+      if let (Some(voltage), MitocandriaChannelStatus::Adjustable { .. }) = (policy.voltage, chan) {
+        let req = MitocandriaAdjustableChannelRequest { channel, voltage: (voltage * 1000.0) as u16 };
+        self.driver.send(GrappleDeviceMessage::PowerDistributionModule(mitocandria::MitocandriaMessage::ChannelRequest(
+          mitocandria::MitocandriaChannelRequest::SetAdjustableChannel(Request::Request(req))
+        ))).ok();
+      }
+
+      if let (Some(enabled), MitocandriaChannelStatus::Switchable { .. } | MitocandriaChannelStatus::Adjustable { .. }) = (policy.enabled, chan) {
+        let req = MitocandriaSwitchableChannelRequest { channel, enabled };
+        self.driver.send(GrappleDeviceMessage::PowerDistributionModule(mitocandria::MitocandriaMessage::ChannelRequest(
+          mitocandria::MitocandriaChannelRequest::SetSwitchableChannel(Request::Request(req))
+        ))).ok();
+      }
     }
   }
 
@@ -145,6 +355,48 @@ impl MitoCANdria {
       None => Err(GrappleError::ParameterOutOfBounds(AsymmetricCow(Cow::Borrowed("Invalid channel!")))),
     }
   }
+
+  /// Set whether a channel should be enabled when the MitoCANdria comes online. The policy is sent
+  /// when the board is first seen on the bus, and again whenever it reboots, as the MitoCANdria is
+  /// polled.
+  pub fn set_startup_enabled(&mut self, channel: u8, enabled: bool) -> GrappleResult<'static, ()> {
+    let policy = self.startup_policy.channels.get_mut(channel as usize)
+      .ok_or(GrappleError::ParameterOutOfBounds(AsymmetricCow(Cow::Borrowed("Invalid channel!"))))?;
+    policy.enabled = Some(enabled);
+    self.startup_policy.state = StartupPolicyState::Pending { last_attempt: None };
+    Ok(())
+  }
+
+  /// Set the voltage a channel should be set to when the MitoCANdria comes online. Only the
+  /// adjustable channel can be targetted.
+  pub fn set_startup_voltage(&mut self, channel: u8, voltage: f64) -> GrappleResult<'static, ()> {
+    let policy = self.startup_policy.channels.get_mut(channel as usize)
+      .ok_or(GrappleError::ParameterOutOfBounds(AsymmetricCow(Cow::Borrowed("Invalid channel!"))))?;
+    policy.voltage = Some(voltage);
+    self.startup_policy.state = StartupPolicyState::Pending { last_attempt: None };
+    Ok(())
+  }
+
+  pub fn clear_startup_policy(&mut self) {
+    self.startup_policy = StartupPolicy::default();
+  }
+
+  /// Has the startup policy been applied and confirmed by the MitoCANdria's status frames?
+  pub fn is_startup_policy_verified(&mut self) -> bool {
+    self.get_status();
+    self.startup_policy.is_empty() || self.startup_policy.state == StartupPolicyState::Verified
+  }
+
+  pub fn get_energy(&mut self, channel: u8) -> GrappleResult<'static, MitocandriaChannelEnergy> {
+    // Pull in the latest status frame so the accumulator is up to date
+    self.get_status();
+    self.energy.channels.get(channel as usize).copied()
+      .ok_or(GrappleError::ParameterOutOfBounds(AsymmetricCow(Cow::Borrowed("Invalid channel!"))))
+  }
+
+  pub fn reset_energy(&mut self) {
+    self.energy.reset();
+  }
 }
 
 #[cfg(feature = "pyo3")]
@@ -156,19 +408,19 @@ impl MitoCANdria {
   }
 
   #[pyo3(name = "get_status")]
-  pub fn get_status_py(&mut self) -> Option<mitocandria::MitocandriaStatusFrame> {
-    return self.get_status()
+  pub fn get_status_py(&mut self) -> Option<py::MitocandriaStatusFramePy> {
+    self.get_status().map(Into::into)
   }
 
-  // #[pyo3(name = "set_switchable")]
-  // pub fn set_switchable_py(&mut self, req: MitocandriaSwitchableChannelRequest, py: Python<'_>) -> PyResult<GrappleResultPy> {
-  //   convert_grpl_result_to_py(py, self.set_switchable(req))
-  // }
+  #[pyo3(name = "set_switchable")]
+  pub fn set_switchable_py(&mut self, req: py::MitocandriaSwitchableChannelRequestPy, py: Python<'_>) -> PyResult<GrappleResultPy> {
+    convert_grpl_result_to_py(py, self.set_switchable(req.into()))
+  }
 
-  // #[pyo3(name = "set_adjustable")]
-  // pub fn set_adjustable_py(&mut self, req: MitocandriaAdjustableChannelRequest, py: Python<'_>) -> PyResult<GrappleResultPy> {
-  //   convert_grpl_result_to_py(py, self.set_adjustable(req))
-  // }
+  #[pyo3(name = "set_adjustable")]
+  pub fn set_adjustable_py(&mut self, req: py::MitocandriaAdjustableChannelRequestPy, py: Python<'_>) -> PyResult<GrappleResultPy> {
+    convert_grpl_result_to_py(py, self.set_adjustable(req.into()))
+  }
 
   #[pyo3(name = "get_current")]
   pub fn get_current_py(&mut self, channel: u8, py: Python<'_>) -> PyResult<Option<GrappleResultPy>> {
@@ -199,17 +451,178 @@ impl MitoCANdria {
   pub fn set_voltage_py(&mut self, channel: u8, voltage: f64, py: Python<'_>) -> PyResult<GrappleResultPy> {
     convert_grpl_result_to_py(py, self.set_voltage(channel, voltage))
   }
+
+  #[pyo3(name = "set_startup_enabled")]
+  pub fn set_startup_enabled_py(&mut self, channel: u8, enabled: bool, py: Python<'_>) -> PyResult<GrappleResultPy> {
+    convert_grpl_result_to_py(py, self.set_startup_enabled(channel, enabled))
+  }
+
+  #[pyo3(name = "set_startup_voltage")]
+  pub fn set_startup_voltage_py(&mut self, channel: u8, voltage: f64, py: Python<'_>) -> PyResult<GrappleResultPy> {
+    convert_grpl_result_to_py(py, self.set_startup_voltage(channel, voltage))
+  }
+
+  #[pyo3(name = "clear_startup_policy")]
+  pub fn clear_startup_policy_py(&mut self) {
+    self.clear_startup_policy()
+  }
+
+  #[pyo3(name = "is_startup_policy_verified")]
+  pub fn is_startup_policy_verified_py(&mut self) -> bool {
+    self.is_startup_policy_verified()
+  }
+
+  #[pyo3(name = "get_energy")]
+  pub fn get_energy_py(&mut self, channel: u8, py: Python<'_>) -> PyResult<GrappleResultPy> {
+    convert_grpl_result_to_py(py, self.get_energy(channel))
+  }
+
+  #[pyo3(name = "reset_energy")]
+  pub fn reset_energy_py(&mut self) {
+    self.reset_energy()
+  }
+}
+
+#[cfg(feature = "pyo3")]
+#[cfg_attr(feature = "pyo3", pymethods)]
+impl MitocandriaChannelEnergy {
+  fn __repr__(&self) -> String {
+    format!(
+      "MitocandriaChannelEnergy(amp_hours={:?}, watt_hours={:?}, peak_current={:?}, average_current={:?})",
+      self.amp_hours, self.watt_hours, self.peak_current, self.average_current
+    )
+  }
+}
+
+// The types in grapple_frc_msgs are opaque to Python, so we wrap them here to give
+// them constructors, field access, equality and a useful repr.
+#[cfg(feature = "pyo3")]
+pub mod py {
+  use grapple_frc_msgs::grapple::mitocandria::{MitocandriaAdjustableChannelRequest, MitocandriaChannelStatus, MitocandriaStatusFrame, MitocandriaSwitchableChannelRequest};
+  use pyo3::prelude::*;
+
+  fn py_bool(b: bool) -> &'static str {
+    if b { "True" } else { "False" }
+  }
+
+  /// The status of a single MitoCANdria channel. Currents are in milliamps, and voltages in millivolts.
+  #[pyclass(name = "MitocandriaChannelStatus", eq)]
+  #[derive(Debug, Clone, PartialEq)]
+  pub enum MitocandriaChannelStatusPy {
+    Switchable { enabled: bool, current: u16 },
+    NonSwitchable { current: u16 },
+    Adjustable { enabled: bool, voltage: u16, voltage_setpoint: u16, current: u16 },
+  }
+
+  #[pymethods]
+  impl MitocandriaChannelStatusPy {
+    fn __repr__(&self) -> String {
+      match self {
+        Self::Switchable { enabled, current } =>
+          format!("MitocandriaChannelStatus.Switchable(enabled={}, current={})", py_bool(*enabled), current),
+        Self::NonSwitchable { current } =>
+          format!("MitocandriaChannelStatus.NonSwitchable(current={})", current),
+        Self::Adjustable { enabled, voltage, voltage_setpoint, current } =>
+          format!("MitocandriaChannelStatus.Adjustable(enabled={}, voltage={}, voltage_setpoint={}, current={})", py_bool(*enabled), voltage, voltage_setpoint, current),
+      }
+    }
+  }
+
+  impl From<MitocandriaChannelStatus> for MitocandriaChannelStatusPy {
+    fn from(value: MitocandriaChannelStatus) -> Self {
+      match value {
+        MitocandriaChannelStatus::Switchable { enabled, current } => Self::Switchable { enabled, current },
+        MitocandriaChannelStatus::NonSwitchable { current } => Self::NonSwitchable { current },
+        MitocandriaChannelStatus::Adjustable { enabled, voltage, voltage_setpoint, current } => Self::Adjustable { enabled, voltage, voltage_setpoint, current },
+      }
+    }
+  }
+
+  /// A status frame from the MitoCANdria, containing the status of each channel.
+  #[pyclass(name = "MitocandriaStatusFrame", eq)]
+  #[derive(Debug, Clone, PartialEq)]
+  pub struct MitocandriaStatusFramePy {
+    #[pyo3(get)]
+    pub channels: Vec<MitocandriaChannelStatusPy>,
+  }
+
+  #[pymethods]
+  impl MitocandriaStatusFramePy {
+    fn __repr__(&self) -> String {
+      let channels: Vec<String> = self.channels.iter().map(|c| c.__repr__()).collect();
+      format!("MitocandriaStatusFrame(channels=[{}])", channels.join(", "))
+    }
+  }
+
+  impl From<MitocandriaStatusFrame> for MitocandriaStatusFramePy {
+    fn from(value: MitocandriaStatusFrame) -> Self {
+      Self { channels: value.channels.into_iter().map(Into::into).collect() }
+    }
+  }
+
+  /// A request to enable or disable a switchable channel.
+  #[pyclass(name = "MitocandriaSwitchableChannelRequest", get_all, set_all, eq)]
+  #[derive(Debug, Clone, PartialEq)]
+  pub struct MitocandriaSwitchableChannelRequestPy {
+    pub channel: u8,
+    pub enabled: bool,
+  }
+
+  #[pymethods]
+  impl MitocandriaSwitchableChannelRequestPy {
+    #[new]
+    fn new(channel: u8, enabled: bool) -> Self {
+      Self { channel, enabled }
+    }
+
+    fn __repr__(&self) -> String {
+      format!("MitocandriaSwitchableChannelRequest(channel={}, enabled={})", self.channel, py_bool(self.enabled))
+    }
+  }
+
+  impl From<MitocandriaSwitchableChannelRequestPy> for MitocandriaSwitchableChannelRequest {
+    fn from(value: MitocandriaSwitchableChannelRequestPy) -> Self {
+      Self { channel: value.channel, enabled: value.enabled }
+    }
+  }
+
+  /// A request to set the voltage of an adjustable channel, in millivolts.
+  #[pyclass(name = "MitocandriaAdjustableChannelRequest", get_all, set_all, eq)]
+  #[derive(Debug, Clone, PartialEq)]
+  pub struct MitocandriaAdjustableChannelRequestPy {
+    pub channel: u8,
+    pub voltage: u16,
+  }
+
+  #[pymethods]
+  impl MitocandriaAdjustableChannelRequestPy {
+    #[new]
+    fn new(channel: u8, voltage: u16) -> Self {
+      Self { channel, voltage }
+    }
+
+    fn __repr__(&self) -> String {
+      format!("MitocandriaAdjustableChannelRequest(channel={}, voltage={})", self.channel, self.voltage)
+    }
+  }
+
+  impl From<MitocandriaAdjustableChannelRequestPy> for MitocandriaAdjustableChannelRequest {
+    fn from(value: MitocandriaAdjustableChannelRequestPy) -> Self {
+      Self { channel: value.channel, voltage: value.voltage }
+    }
+  }
 }
 
 #[cfg(feature = "c")]
 mod c {
-  use crate::{MaybeBoolResult, MaybeDoubleResult, UnitCGrappleResult};
+  use crate::{CGrappleResult, MaybeBoolResult, MaybeDoubleResult, UnitCGrappleResult};
 
-  use super::MitoCANdria;
+  use super::{MitoCANdria, MitocandriaChannelEnergy};
 
   // C
   #[no_mangle]
   pub extern "C" fn mitocandria_new(can_id: u8) -> *mut MitoCANdria {
+    crate::logging::init();
     Box::into_raw(Box::new(MitoCANdria::new(can_id)))
   }
 
@@ -248,11 +661,45 @@ mod c {
   pub extern "C" fn mitocandria_set_channel_voltage(inst: *mut MitoCANdria, channel: u8, voltage: f64) -> UnitCGrappleResult {
     unsafe { UnitCGrappleResult((*inst).set_voltage(channel, voltage).map(Into::into).into()) }
   }
+
+  #[no_mangle]
+  pub extern "C" fn mitocandria_set_startup_channel_enabled(inst: *mut MitoCANdria, channel: u8, enabled: bool) -> UnitCGrappleResult {
+    unsafe { UnitCGrappleResult((*inst).set_startup_enabled(channel, enabled).map(Into::into).into()) }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn mitocandria_set_startup_channel_voltage(inst: *mut MitoCANdria, channel: u8, voltage: f64) -> UnitCGrappleResult {
+    unsafe { UnitCGrappleResult((*inst).set_startup_voltage(channel, voltage).map(Into::into).into()) }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn mitocandria_clear_startup_policy(inst: *mut MitoCANdria) {
+    unsafe { (*inst).clear_startup_policy() }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn mitocandria_is_startup_policy_verified(inst: *mut MitoCANdria) -> bool {
+    unsafe { (*inst).is_startup_policy_verified() }
+  }
+
+  // Need to wrap this so MSVC doesn't complain about using C++ generics in extern "C"
+  #[repr(C)]
+  pub struct ChannelEnergyResult(CGrappleResult<MitocandriaChannelEnergy>);
+
+  #[no_mangle]
+  pub extern "C" fn mitocandria_get_channel_energy(inst: *mut MitoCANdria, channel: u8) -> ChannelEnergyResult {
+    unsafe { ChannelEnergyResult((*inst).get_energy(channel).into()) }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn mitocandria_reset_energy(inst: *mut MitoCANdria) {
+    unsafe { (*inst).reset_energy() }
+  }
 }
 
 #[cfg(feature = "jni")]
 mod jni {
-  use jni::{objects::{JClass, JObject, JValueGen}, sys::{jdouble, jint, jlong, jobject}, JNIEnv};
+  use jni::{objects::{JClass, JObject, JValueGen}, sys::{jboolean, jdouble, jint, jlong, jobject}, JNIEnv};
 
   use crate::JNIResultExtension;
 
@@ -404,4 +851,323 @@ use super::MitoCANdria;
     let mc = get_handle(&mut env, inst);
     unsafe { (*mc).set_voltage(channel as u8, voltage) }.with_jni_throw(&mut env, "ConfigurationFailedException", |_| {});
   }
-}
\ No newline at end of file
+
+  #[no_mangle]
+  pub extern "system" fn Java_au_grapplerobotics_MitoCANdria_setStartupChannelEnabled<'local>(
+    mut env: JNIEnv<'local>,
+    inst: JObject<'local>,
+    channel: jint,
+    enabled: bool,
+  ) {
+    let mc = get_handle(&mut env, inst);
+    unsafe { (*mc).set_startup_enabled(channel as u8, enabled) }.with_jni_throw(&mut env, "ConfigurationFailedException", |_| {});
+  }
+
+  #[no_mangle]
+  pub extern "system" fn Java_au_grapplerobotics_MitoCANdria_setStartupChannelVoltage<'local>(
+    mut env: JNIEnv<'local>,
+    inst: JObject<'local>,
+    channel: jint,
+    voltage: jdouble,
+  ) {
+    let mc = get_handle(&mut env, inst);
+    unsafe { (*mc).set_startup_voltage(channel as u8, voltage) }.with_jni_throw(&mut env, "ConfigurationFailedException", |_| {});
+  }
+
+  #[no_mangle]
+  pub extern "system" fn Java_au_grapplerobotics_MitoCANdria_clearStartupPolicy<'local>(
+    mut env: JNIEnv<'local>,
+    inst: JObject<'local>,
+  ) {
+    let mc = get_handle(&mut env, inst);
+    unsafe { (*mc).clear_startup_policy() };
+  }
+
+  #[no_mangle]
+  pub extern "system" fn Java_au_grapplerobotics_MitoCANdria_isStartupPolicyVerified<'local>(
+    mut env: JNIEnv<'local>,
+    inst: JObject<'local>,
+  ) -> jboolean {
+    let mc = get_handle(&mut env, inst);
+    unsafe { (*mc).is_startup_policy_verified() as jboolean }
+  }
+
+  #[no_mangle]
+  pub extern "system" fn Java_au_grapplerobotics_MitoCANdria_getChannelEnergy<'local>(
+    mut env: JNIEnv<'local>,
+    inst: JObject<'local>,
+    channel: jint,
+  ) -> jobject {
+    let mc = get_handle(&mut env, inst);
+    let energy = unsafe { (*mc).get_energy(channel as u8) }.with_jni_throw(&mut env, "CouldNotGetException", |v| v);
+
+    match energy {
+      Some(energy) => {
+        let cls = env.find_class("au/grapplerobotics/interfaces/MitoCANdriaInterface$ChannelEnergy").unwrap();
+        env.new_object(cls, "(DDDD)V", &[
+          JValueGen::Double(energy.amp_hours),
+          JValueGen::Double(energy.watt_hours),
+          JValueGen::Double(energy.peak_current),
+          JValueGen::Double(energy.average_current),
+        ]).unwrap().into_raw()
+      },
+      None => JObject::null().into_raw(),   // Doesn't matter, it'll raise an exception
+    }
+  }
+
+  #[no_mangle]
+  pub extern "system" fn Java_au_grapplerobotics_MitoCANdria_resetEnergy<'local>(
+    mut env: JNIEnv<'local>,
+    inst: JObject<'local>,
+  ) {
+    let mc = get_handle(&mut env, inst);
+    unsafe { (*mc).reset_energy() };
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{collections::VecDeque, sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+  use grapple_frc_msgs::grapple::{mitocandria::{MitocandriaChannelStatus, MitocandriaMessage, MitocandriaStatusFrame}, GrappleDeviceMessage, DEVICE_TYPE_POWER_DISTRIBUTION_MODULE};
+
+  use crate::{bridge_protocol::CanFilter, can::{CanFrame, CanTransport, GrappleCanDriver}};
+
+  use super::{MitoCANdria, MitocandriaChannelEnergy, StartupPolicyState, MITOCANDRIA_STATUS_TIMEOUT};
+
+  const CAN_ID: u8 = 3;
+
+  // Hands the MitoCANdria whatever the test queues, and records what it sends.
+  #[derive(Clone, Default)]
+  struct FakeTransport {
+    incoming: Arc<Mutex<VecDeque<CanFrame>>>,
+    sent: Arc<Mutex<Vec<u32>>>,
+  }
+
+  impl CanTransport for FakeTransport {
+    fn send(&mut self, id: u32, _data: &[u8]) -> anyhow::Result<()> {
+      self.sent.lock().unwrap().push(id);
+      Ok(())
+    }
+
+    fn receive(&mut self, filter: CanFilter) -> Option<CanFrame> {
+      let mut incoming = self.incoming.lock().unwrap();
+      let position = incoming.iter().position(|f| filter.matches(f.id))?;
+      incoming.remove(position)
+    }
+  }
+
+  // Encodes messages as the MitoCANdria would send them.
+  #[derive(Clone, Default)]
+  struct Encoder(Arc<Mutex<Vec<EncodedFrame>>>);
+
+  type EncodedFrame = (u32, Vec<u8>);
+
+  impl CanTransport for Encoder {
+    fn send(&mut self, id: u32, data: &[u8]) -> anyhow::Result<()> {
+      self.0.lock().unwrap().push((id, data.to_vec()));
+      Ok(())
+    }
+
+    fn receive(&mut self, _filter: CanFilter) -> Option<CanFrame> {
+      None
+    }
+  }
+
+  fn status_frame(enabled: bool) -> MitocandriaStatusFrame {
+    MitocandriaStatusFrame {
+      channels: [
+        MitocandriaChannelStatus::NonSwitchable { current: 0 },
+        MitocandriaChannelStatus::Switchable { enabled, current: 100 },
+        MitocandriaChannelStatus::Switchable { enabled: true, current: 100 },
+        MitocandriaChannelStatus::Switchable { enabled: true, current: 100 },
+        MitocandriaChannelStatus::Adjustable { enabled: true, voltage: 5000, voltage_setpoint: 5000, current: 100 },
+      ],
+    }
+  }
+
+  // Every channel draws `current_ma`, with the adjustable channel at 12V.
+  fn energy_frame(current_ma: u16) -> MitocandriaStatusFrame {
+    MitocandriaStatusFrame {
+      channels: [
+        MitocandriaChannelStatus::NonSwitchable { current: current_ma },
+        MitocandriaChannelStatus::Switchable { enabled: true, current: current_ma },
+        MitocandriaChannelStatus::Switchable { enabled: true, current: current_ma },
+        MitocandriaChannelStatus::Switchable { enabled: true, current: current_ma },
+        MitocandriaChannelStatus::Adjustable { enabled: true, voltage: 12000, voltage_setpoint: 12000, current: current_ma },
+      ],
+    }
+  }
+
+  fn assert_close(actual: f64, expected: f64) {
+    assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+  }
+
+  struct Fixture {
+    mitocandria: MitoCANdria,
+    transport: FakeTransport,
+    encoder: Encoder,
+    driver: GrappleCanDriver,
+    // The bus's clock, which starts at 0ms
+    start: Instant,
+    now: Instant,
+  }
+
+  impl Fixture {
+    fn new() -> Self {
+      let transport = FakeTransport::default();
+      let encoder = Encoder::default();
+      Self {
+        mitocandria: MitoCANdria::with_transport(CAN_ID, Box::new(transport.clone())),
+        driver: GrappleCanDriver::with_transport(CAN_ID, DEVICE_TYPE_POWER_DISTRIBUTION_MODULE, Box::new(encoder.clone())),
+        transport,
+        encoder,
+        start: Instant::now(),
+        now: Instant::now(),
+      }
+    }
+
+    fn advance_to(&mut self, timestamp_ms: u32) {
+      self.now = self.start + Duration::from_millis(timestamp_ms as u64);
+    }
+
+    fn poll(&mut self) -> Option<MitocandriaStatusFrame> {
+      self.mitocandria.get_status_at(self.now)
+    }
+
+    // Queue a status frame, as received at `timestamp_ms` on the bus's clock
+    fn receive(&mut self, timestamp_ms: u32, enabled: bool) {
+      self.receive_frame(timestamp_ms, status_frame(enabled));
+    }
+
+    fn receive_frame(&mut self, timestamp_ms: u32, frame: MitocandriaStatusFrame) {
+      let msg = GrappleDeviceMessage::PowerDistributionModule(MitocandriaMessage::StatusFrame(frame));
+      self.driver.send(msg).unwrap();
+      for (id, data) in self.encoder.0.lock().unwrap().drain(..) {
+        self.transport.incoming.lock().unwrap().push_back(CanFrame { id, timestamp: timestamp_ms, data });
+      }
+      self.advance_to(timestamp_ms);
+    }
+
+    fn state(&self) -> StartupPolicyState {
+      self.mitocandria.startup_policy.state
+    }
+
+    fn sent(&self) -> usize {
+      self.transport.sent.lock().unwrap().len()
+    }
+  }
+
+  #[test]
+  fn energy_follows_frame_timestamps() {
+    let mut f = Fixture::new();
+
+    // Polled once a second, with the current ramping from 1A to 3A in between
+    f.receive_frame(0, energy_frame(1000));
+    f.mitocandria.get_energy(1).unwrap();
+    f.receive_frame(1000, energy_frame(3000));
+
+    let switchable = f.mitocandria.get_energy(1).unwrap();
+    assert_close(switchable.amp_hours, 2.0 / 3600.0);
+    assert_close(switchable.watt_hours, 10.0 / 3600.0);
+    assert_close(switchable.peak_current, 3.0);
+    assert_close(switchable.average_current, 2.0);
+
+    let adjustable = f.mitocandria.get_energy(4).unwrap();
+    assert_close(adjustable.amp_hours, 2.0 / 3600.0);
+    assert_close(adjustable.watt_hours, 24.0 / 3600.0);
+
+    // The time the board was offline between these two frames isn't counted
+    f.receive_frame(1100, energy_frame(3000));
+    f.receive_frame(5000, energy_frame(3000));
+    let switchable = f.mitocandria.get_energy(1).unwrap();
+    assert_close(switchable.amp_hours, 2.3 / 3600.0);
+    assert_close(switchable.average_current, 2.3 / 1.1);
+
+    f.mitocandria.reset_energy();
+    assert_eq!(f.mitocandria.get_energy(1).unwrap(), MitocandriaChannelEnergy::default());
+  }
+
+  #[test]
+  fn policy_is_sent_once_the_board_comes_online() {
+    let mut f = Fixture::new();
+    f.mitocandria.set_startup_enabled(1, true).unwrap();
+    f.receive(0, false);
+    assert!(f.poll().is_some());
+    assert_eq!(f.sent(), 1);
+
+    // Not sent again until the board has had a chance to act on it
+    f.receive(100, false);
+    f.poll();
+    assert_eq!(f.sent(), 1);
+
+    f.receive(1100, false);
+    f.poll();
+    assert_eq!(f.sent(), 2);
+
+    f.receive(1200, true);
+    f.poll();
+    assert_eq!(f.state(), StartupPolicyState::Verified);
+
+    f.receive(2500, true);
+    f.poll();
+    assert_eq!(f.sent(), 2);
+  }
+
+  #[test]
+  fn empty_policy_is_never_sent() {
+    let mut f = Fixture::new();
+    f.receive(0, false);
+    f.poll();
+    assert_eq!(f.state(), StartupPolicyState::Verified);
+    assert_eq!(f.sent(), 0);
+  }
+
+  #[test]
+  fn slow_polling_is_not_a_reboot() {
+    let mut f = Fixture::new();
+    f.mitocandria.set_startup_enabled(1, true).unwrap();
+    f.receive(0, true);
+    f.poll();
+    assert_eq!(f.state(), StartupPolicyState::Verified);
+
+    // Robot code turns the channel off, and doesn't look again for a while
+    f.receive(600, false);
+    f.receive(700, false);
+    assert!(f.poll().is_some());
+    assert_eq!(f.state(), StartupPolicyState::Verified);
+    assert_eq!(f.sent(), 0);
+  }
+
+  #[test]
+  fn gap_between_frames_is_a_reboot() {
+    let mut f = Fixture::new();
+    f.mitocandria.set_startup_enabled(1, true).unwrap();
+    f.receive(0, true);
+    f.poll();
+    assert_eq!(f.state(), StartupPolicyState::Verified);
+
+    f.receive(100, true);
+    f.receive(1100, false);
+    f.poll();
+    assert!(matches!(f.state(), StartupPolicyState::Pending { .. }));
+    assert_eq!(f.sent(), 1);
+  }
+
+  #[test]
+  fn going_quiet_is_a_reboot() {
+    let mut f = Fixture::new();
+    f.mitocandria.set_startup_enabled(1, true).unwrap();
+    f.receive(0, true);
+    f.poll();
+    assert_eq!(f.state(), StartupPolicyState::Verified);
+
+    f.advance_to(MITOCANDRIA_STATUS_TIMEOUT.as_millis() as u32 + 100);
+    assert!(f.poll().is_none());
+
+    f.receive(2000, false);
+    f.poll();
+    assert!(matches!(f.state(), StartupPolicyState::Pending { .. }));
+    assert_eq!(f.sent(), 1);
+  }
+}
@@ -0,0 +1,259 @@
+use std::{ffi::CString, io, mem, os::fd::{AsRawFd, FromRawFd, OwnedFd}, time::{SystemTime, UNIX_EPOCH}};
+
+use crate::{bridge_protocol::CanFilter, can::{CanBus, CanFrame, CanStream, CanTransport}, HAL_CANStreamMessage};
+
+fn unix_time_us() -> u64 {
+  SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_micros() as u64).unwrap_or(0)
+}
+
+fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+  match result {
+    r if r < 0 => Err(io::Error::last_os_error()),
+    r => Ok(r),
+  }
+}
+
+fn set_option<T>(fd: &OwnedFd, level: libc::c_int, name: libc::c_int, value: &T) -> io::Result<()> {
+  check(unsafe { libc::setsockopt(fd.as_raw_fd(), level, name, value as *const T as *const libc::c_void, mem::size_of::<T>() as libc::socklen_t) })?;
+  Ok(())
+}
+
+// A non-blocking raw CAN socket bound to a single interface. Only extended (29-bit) data frames are
+// received, as those are the only ones FRC devices use.
+struct CanSocket {
+  fd: OwnedFd,
+  drops: u32,
+}
+
+impl CanSocket {
+  fn open(ifindex: u32) -> io::Result<Self> {
+    let fd = check(unsafe { libc::socket(libc::PF_CAN, libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC, libc::CAN_RAW) })?;
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let mut addr: libc::sockaddr_can = unsafe { mem::zeroed() };
+    addr.can_family = libc::AF_CAN as libc::sa_family_t;
+    addr.can_ifindex = ifindex as libc::c_int;
+    check(unsafe { libc::bind(fd.as_raw_fd(), &addr as *const libc::sockaddr_can as *const libc::sockaddr, mem::size_of::<libc::sockaddr_can>() as libc::socklen_t) })?;
+
+    // Have the kernel timestamp frames as they arrive, rather than when we get around to reading
+    // them, and count the frames it drops because we didn't read them in time.
+    set_option(&fd, libc::SOL_SOCKET, libc::SO_TIMESTAMP, &1 as &libc::c_int)?;
+    set_option(&fd, libc::SOL_SOCKET, libc::SO_RXQ_OVFL, &1 as &libc::c_int)?;
+
+    let socket = Self { fd, drops: 0 };
+    socket.set_filter(CanFilter { id: 0, mask: 0 })?;
+    Ok(socket)
+  }
+
+  fn set_filter(&self, filter: CanFilter) -> io::Result<()> {
+    let filter = libc::can_filter {
+      can_id: (filter.id & libc::CAN_EFF_MASK) | libc::CAN_EFF_FLAG,
+      can_mask: (filter.mask & libc::CAN_EFF_MASK) | libc::CAN_EFF_FLAG | libc::CAN_RTR_FLAG,
+    };
+    set_option(&self.fd, libc::SOL_CAN_RAW, libc::CAN_RAW_FILTER, &filter)
+  }
+
+  fn send(&self, id: u32, data: &[u8]) -> io::Result<()> {
+    if data.len() > 8 {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, "CAN frames can't be longer than 8 bytes"));
+    }
+
+    let mut frame: libc::can_frame = unsafe { mem::zeroed() };
+    frame.can_id = (id & libc::CAN_EFF_MASK) | libc::CAN_EFF_FLAG;
+    frame.can_dlc = data.len() as u8;
+    frame.data[0..data.len()].copy_from_slice(data);
+
+    let n = unsafe { libc::write(self.fd.as_raw_fd(), &frame as *const libc::can_frame as *const libc::c_void, mem::size_of::<libc::can_frame>()) };
+    if n < 0 {
+      return Err(io::Error::last_os_error());
+    }
+    Ok(())
+  }
+
+  // Take the next frame, if one has arrived, along with whether the kernel dropped frames before it.
+  fn recv(&mut self) -> io::Result<Option<(HAL_CANStreamMessage, bool)>> {
+    let mut frame: libc::can_frame = unsafe { mem::zeroed() };
+    let mut iov = libc::iovec { iov_base: &mut frame as *mut libc::can_frame as *mut libc::c_void, iov_len: mem::size_of::<libc::can_frame>() };
+    // u64s so the control messages are aligned
+    let mut control = [0u64; 16];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+
+    if unsafe { libc::recvmsg(self.fd.as_raw_fd(), &mut msg, 0) } < 0 {
+      let e = io::Error::last_os_error();
+      return match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted => Ok(None),
+        _ => Err(e),
+      };
+    }
+
+    let mut timestamp_us = None;
+    let mut drops = self.drops;
+    unsafe {
+      let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+      while !cmsg.is_null() {
+        match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+          (libc::SOL_SOCKET, libc::SO_TIMESTAMP) => {
+            let tv = (libc::CMSG_DATA(cmsg) as *const libc::timeval).read_unaligned();
+            timestamp_us = Some(tv.tv_sec as u64 * 1_000_000 + tv.tv_usec as u64);
+          },
+          (libc::SOL_SOCKET, libc::SO_RXQ_OVFL) => drops = (libc::CMSG_DATA(cmsg) as *const u32).read_unaligned(),
+          _ => (),
+        }
+        cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+      }
+    }
+
+    let dropped = drops != self.drops;
+    self.drops = drops;
+
+    let message = HAL_CANStreamMessage {
+      messageID: frame.can_id & libc::CAN_EFF_MASK,
+      timeStamp: (timestamp_us.unwrap_or_else(unix_time_us) / 1000) as u32,
+      data: frame.data,
+      dataSize: frame.can_dlc.min(8),
+    };
+    Ok(Some((message, dropped)))
+  }
+}
+
+/// A SocketCAN interface, e.g. `can0` on a coprocessor with a CAN adapter, or `vcan0` for testing.
+/// Frames are timestamped with the Unix time they arrived at, so that's the bus's clock.
+///
+/// Use it for everything with `can::set_bus(Some(Arc::new(SocketCanBus::open("can0")?)))`.
+#[derive(Debug, Clone)]
+pub struct SocketCanBus {
+  interface: String,
+  ifindex: u32,
+}
+
+impl SocketCanBus {
+  /// Find the interface, and check a socket can be opened on it.
+  pub fn open(interface: &str) -> anyhow::Result<Self> {
+    let name = CString::new(interface)?;
+    let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    if ifindex == 0 {
+      anyhow::bail!("No CAN interface named {}", interface);
+    }
+    CanSocket::open(ifindex).map_err(|e| anyhow::anyhow!("Could not open {}: {}", interface, e))?;
+
+    log::info!(interface; "Using SocketCAN interface");
+    Ok(Self { interface: interface.to_owned(), ifindex })
+  }
+
+  pub fn interface(&self) -> &str {
+    &self.interface
+  }
+}
+
+impl CanBus for SocketCanBus {
+  fn transport(&self) -> Box<dyn CanTransport> {
+    Box::new(SocketCanTransport { interface: self.interface.clone(), ifindex: self.ifindex, socket: None, filter: None })
+  }
+
+  fn open_stream(&self, filter: CanFilter, _depth: u32) -> anyhow::Result<Box<dyn CanStream>> {
+    // The socket's receive buffer holds frames between reads, so there's no depth to set
+    let socket = CanSocket::open(self.ifindex)?;
+    socket.set_filter(filter)?;
+    Ok(Box::new(SocketCanStream { interface: self.interface.clone(), socket }))
+  }
+
+  fn time_us(&self) -> u64 {
+    unix_time_us()
+  }
+}
+
+/// A stream session on a SocketCAN interface, see CanBus::open_stream.
+pub struct SocketCanStream {
+  interface: String,
+  socket: CanSocket,
+}
+
+impl CanStream for SocketCanStream {
+  fn read(&mut self, buffer: &mut [HAL_CANStreamMessage]) -> (usize, bool) {
+    let mut n_read = 0;
+    let mut overflowed = false;
+
+    while n_read < buffer.len() {
+      match self.socket.recv() {
+        Ok(Some((message, dropped))) => {
+          buffer[n_read] = message;
+          n_read += 1;
+          overflowed |= dropped;
+        },
+        Ok(None) => break,
+        Err(e) => {
+          log::debug!(interface = self.interface.as_str(), error:% = e; "Could not read from SocketCAN");
+          break;
+        }
+      }
+    }
+    (n_read, overflowed)
+  }
+
+  fn send(&mut self, id: u32, data: &[u8]) -> anyhow::Result<()> {
+    Ok(self.socket.send(id, data)?)
+  }
+}
+
+/// A transport for a single device on a SocketCAN interface. Its socket is opened on first use, and
+/// only receives frames matching the filter it was last asked for.
+pub struct SocketCanTransport {
+  interface: String,
+  ifindex: u32,
+  socket: Option<CanSocket>,
+  filter: Option<CanFilter>,
+}
+
+impl SocketCanTransport {
+  fn socket(&mut self) -> io::Result<&mut CanSocket> {
+    if self.socket.is_none() {
+      self.socket = Some(CanSocket::open(self.ifindex)?);
+      self.filter = None;
+    }
+    Ok(self.socket.as_mut().unwrap())
+  }
+}
+
+impl CanTransport for SocketCanTransport {
+  fn send(&mut self, id: u32, data: &[u8]) -> anyhow::Result<()> {
+    Ok(self.socket()?.send(id, data)?)
+  }
+
+  fn receive(&mut self, filter: CanFilter) -> Option<CanFrame> {
+    let current_filter = self.filter;
+    let socket = match self.socket() {
+      Ok(socket) => socket,
+      Err(e) => {
+        log::debug!(interface = self.interface.as_str(), error:% = e; "Could not open SocketCAN socket");
+        return None;
+      }
+    };
+
+    if current_filter != Some(filter) {
+      // Frames already buffered under the old filter are still read below, so check them again
+      socket.set_filter(filter).ok()?;
+      self.filter = Some(filter);
+    }
+
+    loop {
+      let socket = self.socket.as_mut()?;
+      match socket.recv() {
+        Ok(Some((message, _))) if filter.matches(message.messageID) => return Some(CanFrame {
+          id: message.messageID,
+          timestamp: message.timeStamp,
+          data: message.data[0..message.dataSize as usize].to_vec(),
+        }),
+        Ok(Some(_)) => continue,
+        Ok(None) => return None,
+        Err(e) => {
+          log::debug!(interface = self.interface.as_str(), error:% = e; "Could not read from SocketCAN");
+          return None;
+        }
+      }
+    }
+  }
+}
@@ -1,130 +1,277 @@
-use std::{borrow::Cow, time::Duration};
+use std::{collections::HashMap, net::SocketAddr, sync::{atomic::{AtomicBool, Ordering}, Arc}, time::{Duration, Instant}};
 
 use futures::{SinkExt, StreamExt};
-use grapple_frc_msgs::{binmarshal::{BitView, BitWriter, Demarshal, LengthTaggedPayload, Marshal, VecBitWriter}, bridge::BridgedCANMessage, MessageId};
-use warp::{filters::ws::{Message, WebSocket}, Filter};
+use warp::{filters::ws::{Message, WebSocket}, http::StatusCode, Filter, Reply};
 
-use crate::{hal_safe_call, HAL_CANStreamMessage, HAL_CAN_CloseStreamSession, HAL_CAN_OpenStreamSession, HAL_CAN_ReadStreamSession, HAL_CAN_SendMessage, HAL_CAN_SEND_PERIOD_NO_REPEAT};
+use crate::{bridge_api, bridge_config::{BridgeConfig, CBridgeConfig}, bridge_handle::BridgeHandle, bridge_json::{JsonCodec, JsonCommand, JSON_SUBPROTOCOL}, bridge_protocol::{pack_batches, BridgeControl, CanFilter}, bridge_session::BridgeSession, bridge_stats::BridgeStats, can_bridge::ClientSlot};
 
-pub struct CanDropGuard {
-  session_handle: u32
-}
+// Stream sessions used to be opened from here, so keep the old path working
+pub use crate::can::CanDropGuard;
 
-impl Drop for CanDropGuard {
-  fn drop(&mut self) {
-    unsafe { HAL_CAN_CloseStreamSession(self.session_handle) };
-  }
-}
+/// A small diagnostics page, served from the bridge's own port so it can be used from a browser
+/// without installing GrappleHook. It talks to the bridge over the WebSocket in JSON mode.
+const DASHBOARD_HTML: &str = include_str!("dashboard/index.html");
 
-async fn client_connected(ws: WebSocket) -> anyhow::Result<()> {
+#[allow(clippy::too_many_arguments)]
+async fn client_connected(ws: WebSocket, config: BridgeConfig, client: String, filters: Vec<CanFilter>, token: Option<String>, json: bool, stop: Arc<AtomicBool>, stats: Arc<BridgeStats>) -> anyhow::Result<()> {
   let (mut tx, mut rx) = ws.split();
-  println!("CAN Bridge - WebSocket Client Connected!");
+  log::info!(client = client.as_str(), json; "Client connected");
+  let mut codec = json.then(JsonCodec::new);
+
+  let mut session = BridgeSession::open(&config, client, filters, stats)?;
+  if let Some(token) = token {
+    let result = session.authenticate(&token);
+    tx.send(Message::text(BridgeControl::AuthenticationResult(result).to_json())).await?;
+  }
 
-  let mut recv_interval = tokio::time::interval(Duration::from_millis(1));
+  let poll = tokio::time::sleep(session.next_poll_delay());
+  tokio::pin!(poll);
 
-  let mut session_handle = 0u32;
-  hal_safe_call!(HAL_CAN_OpenStreamSession(&mut session_handle as *mut u32, 0u32, 0u32, 1024))?;
-  let guard = CanDropGuard { session_handle };
+  // A zero interval would make tokio panic
+  let keepalive_interval = config.keepalive_interval.max(Duration::from_millis(1));
+  let mut keepalive = tokio::time::interval_at(tokio::time::Instant::now() + keepalive_interval, keepalive_interval);
+  let mut last_heard = Instant::now();
 
   loop {
     tokio::select! {
-      _ = recv_interval.tick() => {
+      _ = keepalive.tick() => {
+        if last_heard.elapsed() > config.idle_timeout {
+          log::warn!(client = session.client(), idle_timeout:? = config.idle_timeout; "Client timed out");
+          tx.send(Message::close()).await.ok();
+          break;
+        }
+        tx.send(Message::ping(vec![])).await?;
+      },
+      _ = &mut poll => {
+        if stop.load(Ordering::Relaxed) {
+          tx.send(Message::close()).await.ok();
+          break;
+        }
+
+        if let Err(e) = session.check_access() {
+          tx.send(Message::close()).await.ok();
+          return Err(e);
+        }
+
         // See if there's anything to write
-        let mut stream_messages: [HAL_CANStreamMessage; 1024] = [HAL_CANStreamMessage { ..Default::default() }; 1024];
-        let mut n_read = 0u32;
-        let result = hal_safe_call!(HAL_CAN_ReadStreamSession(guard.session_handle, &mut stream_messages as *mut HAL_CANStreamMessage, 1024, &mut n_read as *mut u32));
-
-        match result {
-          Ok(_) => {
-            for msg in &stream_messages[0..n_read as usize] {
-              let message_id: MessageId = msg.messageID.into();
-              let bridged_msg = BridgedCANMessage { id: message_id, timestamp: msg.timeStamp, data: Cow::Borrowed(Into::<&LengthTaggedPayload<_>>::into(&msg.data[0..msg.dataSize as usize])).into() };
-      
-              let mut write_buf = VecBitWriter::new();
-              bridged_msg.write(&mut write_buf, ()).ok();
-              let slice = write_buf.slice();
-
-              tx.send(Message::binary(slice)).await?;
-            }
-          },
-          Err(_) => ()
+        let messages: Vec<Message> = match codec.as_mut() {
+          Some(codec) => session.read_json(codec).into_iter().map(Message::text).collect(),
+          None if session.batching() => pack_batches(session.read_encoded()).into_iter().map(Message::binary).collect(),
+          None => session.read_encoded().into_iter().map(Message::binary).collect(),
+        };
+        for notification in [session.take_overflow_notification(), session.take_clock_sync()].into_iter().flatten() {
+          tx.feed(Message::text(notification.to_json())).await?;
         }
+        for message in messages {
+          tx.feed(message).await?;
+        }
+        tx.flush().await?;
+
+        poll.as_mut().reset(tokio::time::Instant::now() + session.next_poll_delay());
       },
       msg = rx.next() => match msg {
         Some(Ok(msg)) => {
+          last_heard = Instant::now();
           let bytes = msg.as_bytes();
           if msg.is_ping() {
             tx.send(Message::pong(bytes)).await?;
           } else if msg.is_binary() {
-            let bridged_msg = BridgedCANMessage::read(&mut BitView::new(bytes), ()).map_err(|e| anyhow::anyhow!("Invalid Message! {:?}", e))?;
-            let r = bridged_msg.data.as_ref();
-            let msg_data = r.as_ref();
-            hal_safe_call!(HAL_CAN_SendMessage(
-              bridged_msg.id.into(),
-              msg_data.as_ptr(),
-              msg_data.len() as u8,
-              HAL_CAN_SEND_PERIOD_NO_REPEAT as i32
-            ))?;
+            if let Err(e) = session.send_encoded(bytes) {
+              log::warn!(client = session.client(), error:% = e; "Dropped frame from client");
+            }
+          } else if msg.is_text() {
+            match codec.as_mut() {
+              Some(codec) if JsonCommand::is_command(bytes) => {
+                if let Err(e) = session.send_json(codec, bytes) {
+                  tx.send(Message::text(JsonCodec::command_error(&e))).await?;
+                }
+              },
+              _ => match session.handle_control_json(bytes) {
+                Ok(Some(reply)) => tx.send(Message::text(reply.to_json())).await?,
+                Ok(None) => (),
+                Err(e) => log::warn!(client = session.client(), error:% = e; "Invalid control message")
+              }
+            }
+            session.check_access()?;
           } else if msg.is_close() {
             break;
-          } else {
-            println!("Unknown WebSocket Message: {:?}", msg);
+          } else if !msg.is_pong() {
+            log::debug!(client = session.client(), message:? = msg; "Ignoring unknown WebSocket message");
           }
         },
-        None => (),
+        // The connection has gone away without a close message
+        None => break,
         Some(Err(e)) => {
-          println!("Websocket Error : {}", e);
+          log::warn!(client = session.client(), error:% = e; "WebSocket error");
           break;
         }
       }
     }
   }
 
+  // The session is dropped on return, closing its HAL stream session
   Ok(())
 }
 
+/// Run the WebSocket CAN bridge until `stop` is set, serving up to `config.max_clients` clients
+/// at once. Clients beyond the limit are turned away with a 503 before the upgrade.
+///
+/// Clients can choose which frames they receive at connection time with a `filter` query
+/// parameter (see CanFilter::parse_list), e.g. `ws://roborio:7171/?filter=0x60000:0xFF0000`, or
+/// at any time by sending a SetFilters control message. If the bridge requires a token, it can
+/// be given with a `token` query parameter or an Authenticate control message.
+///
+/// Clients can ask for JSON mode (see bridge_json) with a `format=json` query parameter or the
+/// `grapple-json` sub-protocol, and will then receive decoded device messages instead of binary
+/// BridgedCANMessages.
+///
+/// Clients are pinged every `config.keepalive_interval`, and disconnected if nothing (not even a
+/// pong) is heard from them for `config.idle_timeout`.
+///
+/// The server also reports the stats of every running bridge as JSON at `/stats`. If the bridge
+/// requires a token, it must be given as an `Authorization: Bearer <token>` header.
 #[tokio::main(flavor = "current_thread")]
-async fn run_ws_can_bridge(mut port: i32) -> anyhow::Result<()> {
-  let routes = warp::path::end()
+pub async fn run_ws_can_bridge_until(config: BridgeConfig, stop: Arc<AtomicBool>, stats: Arc<BridgeStats>) -> anyhow::Result<()> {
+  let client_stop = stop.clone();
+  let client_config = config.clone();
+
+  let bridge = warp::path::end()
     .and(warp::ws())
-    .map(|ws: warp::ws::Ws| {
-      ws.on_upgrade(move |websocket| async {
-        match client_connected(websocket).await {
-          Ok(()) => (),
-          Err(e) => println!("Error in WebSocket handler: {}", e)
+    .and(warp::query::<HashMap<String, String>>())
+    .and(warp::header::optional::<String>("sec-websocket-protocol"))
+    .and(warp::addr::remote())
+    .map(move |ws: warp::ws::Ws, query: HashMap<String, String>, protocols: Option<String>, remote: Option<SocketAddr>| {
+      let client = remote.map(|addr| addr.to_string()).unwrap_or_default();
+      let filters = match query.get("filter").map(|f| CanFilter::parse_list(f)).transpose() {
+        Ok(filters) => filters.unwrap_or_default(),
+        Err(e) => return warp::reply::with_status(e.to_string(), StatusCode::BAD_REQUEST).into_response(),
+      };
+      let token = query.get("token").cloned();
+
+      let json_subprotocol = protocols.is_some_and(|p| p.split(',').any(|p| p.trim() == JSON_SUBPROTOCOL));
+      let json = match query.get("format").map(String::as_str) {
+        None | Some("binary") => json_subprotocol,
+        Some("json") => true,
+        Some(format) => return warp::reply::with_status(format!("Unknown format '{}'", format), StatusCode::BAD_REQUEST).into_response(),
+      };
+
+      let Some(slot) = ClientSlot::acquire(&stats, client_config.max_clients) else {
+        log::warn!(client = client.as_str(), max_clients = client_config.max_clients; "Rejecting client, too many clients connected");
+        return warp::reply::with_status("Too many clients", StatusCode::SERVICE_UNAVAILABLE).into_response();
+      };
+
+      let stop = client_stop.clone();
+      let config = client_config.clone();
+      let stats = stats.clone();
+      let reply = ws.on_upgrade(move |websocket| async move {
+        let _slot = slot;
+        match client_connected(websocket, config, client.clone(), filters, token, json, stop, stats).await {
+          Ok(()) => log::info!(client = client.as_str(); "Client disconnected"),
+          Err(e) => log::warn!(client = client.as_str(), error:% = e; "Client disconnected with error"),
         }
-      })
+      });
+
+      // Browsers drop the connection if they asked for a sub-protocol and it isn't echoed back
+      match json_subprotocol {
+        true => warp::reply::with_header(reply, "sec-websocket-protocol", JSON_SUBPROTOCOL).into_response(),
+        false => reply.into_response(),
+      }
     });
-  
-  if port <= 0 {
-    port = 7171;
-  }
 
-  warp::serve(routes).run(([0, 0, 0, 0], port as u16)).await;
+  let stats_token = config.auth_token.clone();
+  let stats_route = warp::path("stats")
+    .and(warp::path::end())
+    .and(warp::get())
+    .and(warp::header::optional::<String>("authorization"))
+    .map(move |authorization: Option<String>| match bridge_api::authorize_bearer(stats_token.as_deref(), authorization.as_deref()) {
+      Ok(()) => warp::reply::json(&BridgeStats::all()).into_response(),
+      Err(e) => e.into_response(),
+    });
+
+  // Plain GET requests to / (without a WebSocket upgrade) get the dashboard
+  let dashboard_route = warp::path::end()
+    .and(warp::get())
+    .map(|| warp::reply::html(DASHBOARD_HTML));
+
+  let routes = bridge.or(stats_route).or(bridge_api::routes(&config)).or(dashboard_route);
+
+  let (_, server) = warp::serve(routes).try_bind_with_graceful_shutdown(config.socket_addr(), async move {
+    while !stop.load(Ordering::Relaxed) {
+      tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+  })?;
+  server.await;
   Ok(())
 }
 
+// A port of 0 or less uses the default port (7171).
+fn ws_config(port: i32) -> BridgeConfig {
+  match port {
+    port if port <= 0 => BridgeConfig::websocket(),
+    port => BridgeConfig { port: port as u16, ..BridgeConfig::websocket() }
+  }
+}
+
+fn run_ws_can_bridge(port: i32) -> anyhow::Result<()> {
+  let config = ws_config(port);
+  let stats = BridgeStats::register("websocket", config.port);
+  run_ws_can_bridge_until(config, Arc::new(AtomicBool::new(false)), stats)
+}
+
+/// Start the WebSocket CAN bridge in the background, returning a handle that can be used to stop it.
+pub fn start_ws_can_bridge_with_config(config: BridgeConfig) -> BridgeHandle {
+  let stats = BridgeStats::register("websocket", config.port);
+  BridgeHandle::spawn(stats, move |stop, stats| run_ws_can_bridge_until(config, stop, stats))
+}
+
+pub fn start_ws_can_bridge_background(port: i32) -> BridgeHandle {
+  start_ws_can_bridge_with_config(ws_config(port))
+}
+
 pub fn run_ws_can_bridge_in_background(port: i32) {
-  std::thread::spawn(move || {
-    run_ws_can_bridge(port).unwrap()
-  });
+  start_ws_can_bridge_background(port).detach();
 }
 
 #[no_mangle]
 pub extern "C" fn run_ws_can_bridge_c(port: i32) {
-  run_ws_can_bridge(port).unwrap()
+  crate::logging::init();
+  if let Err(e) = run_ws_can_bridge(port) {
+    log::error!(error:% = e; "CAN bridge stopped");
+  }
 }
 
 #[no_mangle]
 pub extern "C" fn run_ws_can_bridge_in_background_c(port: i32) {
+  crate::logging::init();
   run_ws_can_bridge_in_background(port)
 }
 
+#[no_mangle]
+pub extern "C" fn ws_can_bridge_start(port: i32) -> *mut BridgeHandle {
+  crate::logging::init();
+  Box::into_raw(Box::new(start_ws_can_bridge_background(port)))
+}
+
+/// Start the WebSocket CAN bridge with the given configuration. Returns null if the configuration is invalid.
+#[no_mangle]
+pub extern "C" fn ws_can_bridge_start_with_config(config: CBridgeConfig) -> *mut BridgeHandle {
+  crate::logging::init();
+  match config.to_config(BridgeConfig::websocket()) {
+    Ok(config) => Box::into_raw(Box::new(start_ws_can_bridge_with_config(config))),
+    Err(e) => {
+      log::error!(error:% = e; "Invalid CAN bridge configuration");
+      std::ptr::null_mut()
+    }
+  }
+}
+
 #[cfg(feature = "jni")]
 mod jni {
-  use jni::{objects::JClass, sys::jint, JNIEnv};
+  use jni::{objects::{JClass, JObject}, sys::{jint, jlong}, JNIEnv};
+
+  use crate::bridge_config::{jni::config_from_jni, BridgeConfig};
 
-  use super::{run_ws_can_bridge, run_ws_can_bridge_in_background};
+  use super::{run_ws_can_bridge, run_ws_can_bridge_in_background, start_ws_can_bridge_background, start_ws_can_bridge_with_config};
 
   #[no_mangle]
   pub extern "system" fn Java_au_grapplerobotics_CanBridge_runWebsocket<'local>(
@@ -132,7 +279,9 @@ mod jni {
     _class: JClass<'local>,
     port: jint
   ) {
-    run_ws_can_bridge(port).unwrap()
+    if let Err(e) = run_ws_can_bridge(port) {
+      log::error!(error:% = e; "CAN bridge stopped");
+    }
   }
 
   #[no_mangle]
@@ -143,4 +292,25 @@ mod jni {
   ) {
     run_ws_can_bridge_in_background(port)
   }
-}
\ No newline at end of file
+
+  #[no_mangle]
+  pub extern "system" fn Java_au_grapplerobotics_CanBridge_startWebsocketInternal<'local>(
+    mut _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    port: jint
+  ) -> jlong {
+    Box::into_raw(Box::new(start_ws_can_bridge_background(port))) as jlong
+  }
+
+  #[no_mangle]
+  pub extern "system" fn Java_au_grapplerobotics_CanBridge_startWebsocketWithConfigInternal<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    config: JObject<'local>
+  ) -> jlong {
+    match config_from_jni(&mut env, config, BridgeConfig::websocket()) {
+      Some(config) => Box::into_raw(Box::new(start_ws_can_bridge_with_config(config))) as jlong,
+      None => 0
+    }
+  }
+}
@@ -1,4 +1,13 @@
+use std::time::Duration;
+
+use grapplefrcdriver::bridge_config::BridgeConfig;
+use grapplefrcdriver::bridge_handle::BridgeHandle;
+use grapplefrcdriver::bridge_protocol::CanFilter;
+use grapplefrcdriver::bridge_stats::BridgeStatsSnapshot;
+use grapplefrcdriver::can_recorder::{RecorderConfig, RecordingFormat};
 use grapplefrcdriver::lasercan::{LaserCanMeasurement, LaserCanRangingMode, LaserCanRoi, LaserCanTimingBudget};
+use grapplefrcdriver::logging::{self, LogLevel};
+use pyo3::exceptions::{PyIOError, PyRuntimeError, PyValueError};
 
 #[allow(dead_code)]
 pub use pyo3::prelude::*;
@@ -7,16 +16,223 @@ pub use pyo3::prelude::*;
 pub use grapplefrcdriver::lasercan::LaserCAN;
 
 #[allow(dead_code)]
-pub use grapplefrcdriver::mitocandria::MitoCANdria;
+pub use grapplefrcdriver::mitocandria::{MitoCANdria, MitocandriaChannelEnergy, py::{MitocandriaAdjustableChannelRequestPy, MitocandriaChannelStatusPy, MitocandriaStatusFramePy, MitocandriaSwitchableChannelRequestPy}};
 
 #[pyfunction]
 pub fn can_bridge_tcp() {
   grapplefrcdriver::can_bridge::start_can_bridge_c_background();
 }
 
+/// Change how much the driver logs. By default, messages at LogLevel.Info and above are printed.
+#[pyfunction]
+pub fn set_log_level(level: LogLevel) {
+  logging::set_level(level);
+}
+
+/// Send log messages to `handler(level, target, message)` instead of stdout, e.g. to hand them to
+/// Python's `logging` module. Pass None to go back to printing them.
+#[pyfunction]
+#[pyo3(signature = (handler))]
+pub fn set_log_handler(handler: Option<PyObject>) {
+  logging::set_sink(handler.map(|handler| -> logging::LogSink {
+    Box::new(move |level, target, message| {
+      Python::with_gil(|py| {
+        if let Err(e) = handler.call1(py, (level, target, message)) {
+          e.print(py);
+        }
+      });
+    })
+  }));
+}
+
+/// Replay a recording made by CanRecorder to every device created from now on, instead of the CAN
+/// bus, at `speed` times real time. `path` is the first file of the recording; the files the
+/// recorder rotated to after it are replayed too. Anything sent to the devices is dropped.
+#[pyfunction]
+#[pyo3(signature = (path, speed = 1.0))]
+pub fn start_replay(path: &str, speed: f64) -> PyResult<()> {
+  grapplefrcdriver::can_replay::start_replay(std::path::Path::new(path), speed).map_err(|e| PyIOError::new_err(e.to_string()))
+}
+
+/// Go back to creating devices on the CAN bus. Devices created during the replay keep replaying it.
+#[pyfunction]
+pub fn stop_replay() {
+  grapplefrcdriver::can_replay::stop_replay();
+}
+
+/// Bridges the roboRIO's CAN bus to GrappleHook and other tools, over either TCP or WebSockets.
+/// Only one bridge may be running per instance.
+#[pyclass]
+#[derive(Default)]
+pub struct CanBridge {
+  handle: Option<BridgeHandle>,
+}
+
+impl CanBridge {
+  fn start<F: FnOnce() -> BridgeHandle>(&mut self, f: F) -> PyResult<()> {
+    if self.is_running() {
+      return Err(PyRuntimeError::new_err("CAN Bridge is already running"));
+    }
+    self.handle = Some(f());
+    Ok(())
+  }
+
+  #[allow(clippy::too_many_arguments)]
+  fn config(port: u16, bind_address: &str, max_clients: usize, session_depth: u32, poll_interval_ms: u64, auth_token: Option<String>, read_only: bool, transmit_allow_list: Option<Vec<CanFilter>>) -> PyResult<BridgeConfig> {
+    Ok(BridgeConfig {
+      bind_address: bind_address.parse().map_err(|e| PyValueError::new_err(format!("Invalid bind address: {}", e)))?,
+      port,
+      max_clients,
+      session_depth,
+      poll_interval: Duration::from_millis(poll_interval_ms),
+      auth_token,
+      read_only,
+      transmit_allow_list: transmit_allow_list.unwrap_or_default(),
+      ..BridgeConfig::tcp()
+    })
+  }
+}
+
+#[pymethods]
+impl CanBridge {
+  #[new]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Start the TCP bridge in the background, as used by GrappleHook.
+  #[pyo3(signature = (port = 8006, bind_address = "0.0.0.0", max_clients = 4, session_depth = 1024, poll_interval_ms = 1, auth_token = None, read_only = false, transmit_allow_list = None))]
+  #[allow(clippy::too_many_arguments)]
+  pub fn start_tcp(&mut self, port: u16, bind_address: &str, max_clients: usize, session_depth: u32, poll_interval_ms: u64, auth_token: Option<String>, read_only: bool, transmit_allow_list: Option<Vec<CanFilter>>) -> PyResult<()> {
+    let config = Self::config(port, bind_address, max_clients, session_depth, poll_interval_ms, auth_token, read_only, transmit_allow_list)?;
+    self.start(|| grapplefrcdriver::can_bridge::start_can_bridge_with_config(config))
+  }
+
+  /// Start the WebSocket bridge in the background.
+  #[pyo3(signature = (port = 7171, bind_address = "0.0.0.0", max_clients = 4, session_depth = 1024, poll_interval_ms = 1, auth_token = None, read_only = false, transmit_allow_list = None, keepalive_interval_ms = 5000, idle_timeout_ms = 15000))]
+  #[allow(clippy::too_many_arguments)]
+  pub fn start_websocket(&mut self, port: u16, bind_address: &str, max_clients: usize, session_depth: u32, poll_interval_ms: u64, auth_token: Option<String>, read_only: bool, transmit_allow_list: Option<Vec<CanFilter>>, keepalive_interval_ms: u64, idle_timeout_ms: u64) -> PyResult<()> {
+    let config = BridgeConfig {
+      keepalive_interval: Duration::from_millis(keepalive_interval_ms),
+      idle_timeout: Duration::from_millis(idle_timeout_ms),
+      ..Self::config(port, bind_address, max_clients, session_depth, poll_interval_ms, auth_token, read_only, transmit_allow_list)?
+    };
+    self.start(|| grapplefrcdriver::ws_can_bridge::start_ws_can_bridge_with_config(config))
+  }
+
+  /// Stop the bridge, disconnecting any clients. Blocks until the bridge has shut down.
+  pub fn stop(&mut self, py: Python<'_>) {
+    if let Some(handle) = self.handle.as_mut() {
+      handle.stop();
+      py.allow_threads(|| handle.join());
+    }
+  }
+
+  /// Wait for the bridge to finish.
+  pub fn join(&mut self, py: Python<'_>) {
+    if let Some(handle) = self.handle.as_mut() {
+      py.allow_threads(|| handle.join());
+    }
+  }
+
+  pub fn is_running(&self) -> bool {
+    self.handle.as_ref().map(|h| h.is_running()).unwrap_or(false)
+  }
+
+  /// The error that caused the bridge to stop, if any.
+  pub fn last_error(&self) -> Option<String> {
+    self.handle.as_ref().and_then(|h| h.last_error())
+  }
+
+  /// The bridge's traffic counters, or None if the bridge has never been started.
+  pub fn stats(&self) -> Option<BridgeStatsSnapshot> {
+    self.handle.as_ref().map(|h| h.stats())
+  }
+}
+
+// The bridge logs as it shuts down, which needs the GIL if there's a log handler, so don't hold it
+// while waiting for the bridge when it's garbage collected without being stopped.
+impl Drop for CanBridge {
+  fn drop(&mut self) {
+    if let Some(handle) = self.handle.as_mut() {
+      handle.stop();
+      Python::with_gil(|py| py.allow_threads(|| handle.join()));
+    }
+  }
+}
+
+/// Records CAN traffic to disk in the background, so device dropouts can be looked into after a match.
+#[pyclass]
+pub struct CanRecorder {
+  handle: BridgeHandle,
+}
+
+#[pymethods]
+impl CanRecorder {
+  /// Start recording to `directory`. Files are named `<prefix>-<unix time>-<index>.grplcan` (or
+  /// `.log` for candump), and the oldest are deleted once there are more than `max_files`.
+  #[new]
+  #[pyo3(signature = (directory, prefix = "grapplecan", format = RecordingFormat::Binary, candump_interface = "can0", filters = None, max_file_size = 16 * 1024 * 1024, max_files = 16, session_depth = 1024, poll_interval_ms = 1, flush_interval_ms = 1000))]
+  #[allow(clippy::too_many_arguments)]
+  pub fn new(directory: &str, prefix: &str, format: RecordingFormat, candump_interface: &str, filters: Option<Vec<CanFilter>>, max_file_size: u64, max_files: usize, session_depth: u32, poll_interval_ms: u64, flush_interval_ms: u64) -> Self {
+    let config = RecorderConfig {
+      prefix: prefix.to_owned(),
+      format,
+      candump_interface: candump_interface.to_owned(),
+      filters: filters.unwrap_or_default(),
+      max_file_size,
+      max_files,
+      session_depth,
+      poll_interval: Duration::from_millis(poll_interval_ms),
+      flush_interval: Duration::from_millis(flush_interval_ms),
+      ..RecorderConfig::new(directory)
+    };
+    Self { handle: grapplefrcdriver::can_recorder::start_can_recorder(config) }
+  }
+
+  /// Stop recording, writing out any buffered frames. Blocks until the recorder has shut down.
+  pub fn stop(&mut self, py: Python<'_>) {
+    self.handle.stop();
+    py.allow_threads(|| self.handle.join());
+  }
+
+  pub fn is_running(&self) -> bool {
+    self.handle.is_running()
+  }
+
+  /// The error that caused the recorder to stop, if any.
+  pub fn last_error(&self) -> Option<String> {
+    self.handle.last_error()
+  }
+
+  /// The recorder's counters. frames_out and bytes_out count the frames and bytes written.
+  pub fn stats(&self) -> BridgeStatsSnapshot {
+    self.handle.stats()
+  }
+}
+
+// Same as CanBridge, the recorder logs as it shuts down.
+impl Drop for CanRecorder {
+  fn drop(&mut self) {
+    self.handle.stop();
+    Python::with_gil(|py| py.allow_threads(|| self.handle.join()));
+  }
+}
+
 #[pymodule]
 pub fn libgrapplefrc(m: &Bound<'_, PyModule>) -> PyResult<()> {
+  logging::init();
   m.add_function(wrap_pyfunction!(can_bridge_tcp, m)?)?;
+  m.add_function(wrap_pyfunction!(set_log_level, m)?)?;
+  m.add_function(wrap_pyfunction!(set_log_handler, m)?)?;
+  m.add_function(wrap_pyfunction!(start_replay, m)?)?;
+  m.add_function(wrap_pyfunction!(stop_replay, m)?)?;
+  m.add_class::<LogLevel>()?;
+  m.add_class::<CanBridge>()?;
+  m.add_class::<CanRecorder>()?;
+  m.add_class::<RecordingFormat>()?;
+  m.add_class::<BridgeStatsSnapshot>()?;
+  m.add_class::<CanFilter>()?;
   m.add_class::<LaserCAN>()?;
   m.add_class::<LaserCanMeasurement>()?;
   m.add_class::<LaserCanRoi>()?;
@@ -24,6 +240,11 @@ pub fn libgrapplefrc(m: &Bound<'_, PyModule>) -> PyResult<()> {
   m.add_class::<LaserCanRangingMode>()?;
 
   m.add_class::<MitoCANdria>()?;
+  m.add_class::<MitocandriaChannelEnergy>()?;
+  m.add_class::<MitocandriaStatusFramePy>()?;
+  m.add_class::<MitocandriaChannelStatusPy>()?;
+  m.add_class::<MitocandriaSwitchableChannelRequestPy>()?;
+  m.add_class::<MitocandriaAdjustableChannelRequestPy>()?;
 
   Ok(())
 }
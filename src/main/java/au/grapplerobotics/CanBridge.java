@@ -1,6 +1,154 @@
 package au.grapplerobotics;
 
-public class CanBridge {
+import java.lang.AutoCloseable;
+import java.lang.ref.Cleaner;
+
+/**
+ * Bridges the CAN bus to GrappleHook and other tools, over either TCP or WebSockets.
+ * 
+ * The static runTCP / runWebsocket methods start a bridge that lives for the rest of the program.
+ * To be able to stop a bridge, use startTCP / startWebsocket, which return a handle to the bridge.
+*/
+public class CanBridge implements AutoCloseable {
+  static native long startTCPInternal(int port);
+  static native long startWebsocketInternal(int port);
+  static native long startTCPWithConfigInternal(Config config);
+  static native long startWebsocketWithConfigInternal(Config config);
+  static native void free(long handle);
+
+  /**
+   * Configuration for a CAN bridge. Any field left as 0 (or null, for the bind address) uses the
+   * default for the bridge being started.
+   */
+  public static class Config {
+    /** The address to listen on, e.g. "10.0.0.2" to only listen on the robot radio. Defaults to all interfaces. */
+    public String bindAddress = null;
+    /** The port to listen on. Defaults to 8006 for TCP and 7171 for WebSockets. */
+    public int port = 0;
+    /** The maximum number of clients that may be connected at once. Defaults to 4. */
+    public int maxClients = 0;
+    /** The number of CAN frames buffered for each client. Defaults to 1024. */
+    public int sessionDepth = 0;
+    /** How often to check for new CAN frames while the bus is busy, in milliseconds. Defaults to 1. */
+    public int pollIntervalMs = 0;
+    /** If set, clients must provide this token before they can use the bridge. */
+    public String authToken = null;
+    /** Refuse all frames sent by clients, so they can only listen to the bus. */
+    public boolean readOnly = false;
+    /** If set, clients may only send frames matching at least one of these filters. */
+    public Filter[] transmitAllowList = null;
+    /** How often the WebSocket bridge pings each client, in milliseconds. Defaults to 5000. */
+    public int keepaliveIntervalMs = 0;
+    /** How long the WebSocket bridge waits without hearing from a client before disconnecting it, in milliseconds. Defaults to 15000. */
+    public int idleTimeoutMs = 0;
+  }
+
+  /**
+   * Matches a CAN frame if (frameId &amp; mask) == (id &amp; mask).
+   */
+  public static class Filter {
+    public int id;
+    public int mask;
+
+    public Filter(int id, int mask) {
+      this.id = id;
+      this.mask = mask;
+    }
+
+    /**
+     * Match all frames from the given manufacturer.
+     * @param manufacturer The manufacturer ID, e.g. 6 for Grapple
+     */
+    public static Filter manufacturer(int manufacturer) {
+      return new Filter((manufacturer & 0xFF) << 16, 0xFF << 16);
+    }
+
+    /**
+     * Match all frames for the given device type.
+     * @param deviceType The FRC CAN device type, e.g. 6 for distance sensors
+     */
+    public static Filter deviceType(int deviceType) {
+      return new Filter((deviceType & 0x1F) << 24, 0x1F << 24);
+    }
+  }
+
+  /**
+   * Traffic counters for a bridge.
+   */
+  public static class Stats {
+    /** CAN frames received from clients and sent on the bus */
+    public final long framesIn;
+    /** CAN frames read from the bus and sent to clients, counted once per client */
+    public final long framesOut;
+    /** Encoded frame bytes received from clients */
+    public final long bytesIn;
+    /** Encoded frame bytes sent to clients */
+    public final long bytesOut;
+    /** Times a client's stream session overflowed, losing frames that were not read in time */
+    public final long sessionOverflows;
+    /** Messages from clients that could not be decoded */
+    public final long decodeErrors;
+    /** Frames from clients that could not be sent on the bus */
+    public final long sendFailures;
+    /** Frames from clients that were refused because the bridge is read-only or the frame wasn't allowed */
+    public final long refusedFrames;
+    /** Clients that gave the wrong token or didn't authenticate in time */
+    public final long authFailures;
+    public final int connectedClients;
+    /** Clients that have connected since the bridge started */
+    public final long totalClients;
+    /** Clients turned away because the bridge was full */
+    public final long rejectedClients;
+    /** Seconds since the bridge started */
+    public final double uptime;
+
+    public Stats(long framesIn, long framesOut, long bytesIn, long bytesOut, long sessionOverflows, long decodeErrors, long sendFailures, long refusedFrames, long authFailures, int connectedClients, long totalClients, long rejectedClients, double uptime) {
+      this.framesIn = framesIn;
+      this.framesOut = framesOut;
+      this.bytesIn = bytesIn;
+      this.bytesOut = bytesOut;
+      this.sessionOverflows = sessionOverflows;
+      this.decodeErrors = decodeErrors;
+      this.sendFailures = sendFailures;
+      this.refusedFrames = refusedFrames;
+      this.authFailures = authFailures;
+      this.connectedClients = connectedClients;
+      this.totalClients = totalClients;
+      this.rejectedClients = rejectedClients;
+      this.uptime = uptime;
+    }
+  }
+
+  static class Handle implements Runnable {
+    long handle;
+
+    Handle(long handle) {
+      this.handle = handle;
+    }
+
+    @Override
+    public void run() {
+      free(this.handle);
+    }
+  }
+
+  private final Handle handle;
+  private final Cleaner.Cleanable cleanable;
+
+  CanBridge(long handle) {
+    this.handle = new Handle(handle);
+    this.cleanable = GrappleJNI.cleaner.register(this, this.handle);
+  }
+
+  private static void load() {
+    try {
+      GrappleJNI.forceLoad();
+    } catch (UnsatisfiedLinkError e) {
+      e.printStackTrace();
+      System.exit(1);
+    }
+  }
+
   public static void runTCP() {
     try {
       GrappleJNI.forceLoad();
@@ -11,7 +159,83 @@ public class CanBridge {
     }
   }
 
+  /**
+   * Start the TCP bridge (as used by GrappleHook) in the background.
+   * @param port The port to listen on. GrappleHook uses 8006.
+   * @return A handle to the bridge, which can be used to stop it.
+   */
+  public static CanBridge startTCP(int port) {
+    load();
+    return new CanBridge(startTCPInternal(port));
+  }
+
+  /**
+   * Start the WebSocket bridge in the background. Browsing to the bridge's port (e.g.
+   * http://roborio-TEAM-frc.local:7171/) shows a diagnostics dashboard for connected devices.
+   * @param port The port to listen on, or 0 for the default (7171).
+   * @return A handle to the bridge, which can be used to stop it.
+   */
+  public static CanBridge startWebsocket(int port) {
+    load();
+    return new CanBridge(startWebsocketInternal(port));
+  }
+
+  /**
+   * Start the TCP bridge in the background with the given configuration.
+   * @param config The bridge configuration
+   * @return A handle to the bridge, which can be used to stop it.
+   * @throws IllegalArgumentException if the configuration is invalid
+   */
+  public static CanBridge startTCP(Config config) {
+    load();
+    return new CanBridge(startTCPWithConfigInternal(config));
+  }
+
+  /**
+   * Start the WebSocket bridge in the background with the given configuration.
+   * @param config The bridge configuration
+   * @return A handle to the bridge, which can be used to stop it.
+   * @throws IllegalArgumentException if the configuration is invalid
+   */
+  public static CanBridge startWebsocket(Config config) {
+    load();
+    return new CanBridge(startWebsocketWithConfigInternal(config));
+  }
+
+  /**
+   * Ask the bridge to stop, disconnecting any clients. Use join() to wait for it to finish.
+   */
+  public native void stop();
+
+  /**
+   * Wait for the bridge to finish.
+   */
+  public native void join();
+
+  /**
+   * @return true if the bridge is still running.
+   */
+  public native boolean isRunning();
+
+  /**
+   * @return The error that caused the bridge to stop, or null if there was none.
+   */
+  public native String getLastError();
+
+  /**
+   * @return The bridge's traffic counters. These remain available after the bridge has stopped.
+   */
+  public native Stats getStats();
+
+  /**
+   * Stop the bridge and release its resources.
+   */
+  @Override
+  public void close() throws Exception {
+    cleanable.clean();
+  }
+
   private static native void runTCPNow();
   public static native void runWebsocket(int port);
   public static native void runWebsocketInBackground(int port);
-}
\ No newline at end of file
+}
@@ -0,0 +1,112 @@
+package au.grapplerobotics;
+
+import java.lang.AutoCloseable;
+
+/**
+ * Records CAN traffic to disk in the background, so device dropouts can be looked into after a match.
+ */
+public class CanRecorder implements AutoCloseable {
+  static native long startInternal(Config config);
+
+  public static final int FORMAT_BINARY = 0;
+  /** Text in the format written by candump -L and read by canplayer */
+  public static final int FORMAT_CANDUMP = 1;
+  public static final int FORMAT_BOTH = 2;
+
+  /**
+   * Configuration for a CAN recorder. Any field left as 0 (or null) uses the default, except for the
+   * directory, which is required.
+   */
+  public static class Config {
+    /** The directory to write recordings to, e.g. "/home/lvuser/canlogs". It's created if it doesn't exist. */
+    public String directory;
+    /** Recordings are named prefix-UNIXTIME-INDEX.grplcan (or .log for candump). Defaults to "grapplecan". */
+    public String prefix = null;
+    /** One of FORMAT_BINARY, FORMAT_CANDUMP or FORMAT_BOTH. */
+    public int format = FORMAT_BINARY;
+    /** The interface name written in candump recordings. Defaults to "can0". */
+    public String candumpInterface = null;
+    /** If set, only frames matching at least one of these filters are recorded. */
+    public CanBridge.Filter[] filters = null;
+    /** Start a new file once the current one reaches this many bytes. Defaults to 16MiB. */
+    public long maxFileSize = 0;
+    /** The most recordings to keep in the directory, including from previous runs. Defaults to 16. */
+    public int maxFiles = 0;
+    /** The number of CAN frames buffered between reads. Defaults to 1024. */
+    public int sessionDepth = 0;
+    /** How often to check for new CAN frames while the bus is busy, in milliseconds. Defaults to 1. */
+    public int pollIntervalMs = 0;
+    /** How often recordings are written to disk, in milliseconds. Defaults to 1000. */
+    public int flushIntervalMs = 0;
+
+    public Config(String directory) {
+      this.directory = directory;
+    }
+  }
+
+  private final CanBridge handle;
+
+  private CanRecorder(long handle) {
+    this.handle = new CanBridge(handle);
+  }
+
+  /**
+   * Start recording CAN traffic in the background.
+   * @param config The recorder configuration
+   * @return A handle to the recorder, which can be used to stop it.
+   * @throws IllegalArgumentException if the configuration is invalid
+   */
+  public static CanRecorder start(Config config) {
+    try {
+      GrappleJNI.forceLoad();
+    } catch (UnsatisfiedLinkError e) {
+      e.printStackTrace();
+      System.exit(1);
+    }
+    return new CanRecorder(startInternal(config));
+  }
+
+  /**
+   * Ask the recorder to stop, writing out any buffered frames. Use join() to wait for it to finish.
+   */
+  public void stop() {
+    handle.stop();
+  }
+
+  /**
+   * Wait for the recorder to finish.
+   */
+  public void join() {
+    handle.join();
+  }
+
+  /**
+   * @return true if the recorder is still running.
+   */
+  public boolean isRunning() {
+    return handle.isRunning();
+  }
+
+  /**
+   * @return The error that caused the recorder to stop (e.g. the disk filling up), or null if there was none.
+   */
+  public String getLastError() {
+    return handle.getLastError();
+  }
+
+  /**
+   * @return The recorder's counters. framesOut and bytesOut count the frames and bytes written, and
+   * sessionOverflows counts the times frames were lost because they weren't read in time.
+   */
+  public CanBridge.Stats getStats() {
+    return handle.getStats();
+  }
+
+  /**
+   * Stop the recorder and release its resources.
+   */
+  @Override
+  public void close() throws Exception {
+    handle.close();
+  }
+}
@@ -0,0 +1,51 @@
+package au.grapplerobotics;
+
+/**
+ * Replays a recording made by {@link CanRecorder} to Grapple devices, so robot code can be run
+ * offline (e.g. in simulation) against exactly what the devices reported during a match.
+ *
+ * Devices created after the replay starts receive the recording instead of the CAN bus, so start
+ * it before creating any devices. Anything sent to the devices is dropped, so configuration calls
+ * will time out.
+ */
+public class CanReplay {
+  static native void startInternal(String path, double speed);
+  static native void stopInternal();
+
+  private static void load() {
+    try {
+      GrappleJNI.forceLoad();
+    } catch (UnsatisfiedLinkError e) {
+      e.printStackTrace();
+      System.exit(1);
+    }
+  }
+
+  /**
+   * Start replaying a recording.
+   * @param path The first file of the recording. The files the recorder rotated to after it are replayed too.
+   * @param speed How much faster than real time to replay, e.g. 1.0 for real time.
+   * @throws IllegalArgumentException if the recording could not be read
+   */
+  public static void start(String path, double speed) {
+    load();
+    startInternal(path, speed);
+  }
+
+  /**
+   * Start replaying a recording in real time.
+   * @param path The first file of the recording.
+   * @throws IllegalArgumentException if the recording could not be read
+   */
+  public static void start(String path) {
+    start(path, 1.0);
+  }
+
+  /**
+   * Go back to creating devices on the CAN bus. Devices created during the replay keep replaying it.
+   */
+  public static void stop() {
+    load();
+    stopInternal();
+  }
+}
@@ -32,9 +32,12 @@ public class GrappleJNI {
         System.exit(1);
       }
       libraryLoaded = true;
+      initLogging();
     }
   }
 
+  private static native void initLogging();
+
   /**
    * Force load the library.
    * @throws java.lang.UnsatisfiedLinkError thrown if the native library cannot be found
@@ -47,5 +50,6 @@ public class GrappleJNI {
     // loader.loadLibrary();
     System.loadLibrary("grapplefrcdriver");
     libraryLoaded = true;
+    initLogging();
   }
 }
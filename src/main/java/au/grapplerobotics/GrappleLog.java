@@ -0,0 +1,91 @@
+package au.grapplerobotics;
+
+import edu.wpi.first.wpilibj.DataLogManager;
+
+/**
+ * Controls the driver's log output. By default, messages at INFO and above are printed to stdout.
+ */
+public class GrappleLog {
+  static native void setLevelInternal(int level);
+  static native void setHandlerInternal(Handler handler);
+
+  public enum Level {
+    OFF(0),
+    ERROR(1),
+    WARN(2),
+    INFO(3),
+    DEBUG(4),
+    TRACE(5);
+
+    public final int value;
+
+    Level(int value) {
+      this.value = value;
+    }
+
+    public static Level fromValue(int value) {
+      for (Level level : values()) {
+        if (level.value == value) {
+          return level;
+        }
+      }
+      return TRACE;
+    }
+  }
+
+  /**
+   * Receives each log message. This is called from the driver's own threads, so should return quickly.
+   */
+  @FunctionalInterface
+  public interface Handler {
+    /**
+     * @param level The level of the message, as in {@link Level#value}
+     * @param target Where the message came from, e.g. "grapplefrcdriver::ws_can_bridge"
+     * @param message The message, followed by any structured fields, e.g. "Client connected client=10.0.0.5:52311"
+     */
+    void log(int level, String target, String message);
+  }
+
+  private static void load() {
+    try {
+      GrappleJNI.forceLoad();
+    } catch (UnsatisfiedLinkError e) {
+      e.printStackTrace();
+      System.exit(1);
+    }
+  }
+
+  /**
+   * Change how much the driver logs. This can be changed at any time.
+   * @param level The most detailed level to log, or OFF to log nothing.
+   */
+  public static void setLevel(Level level) {
+    load();
+    setLevelInternal(level.value);
+  }
+
+  /**
+   * Send log messages to the given handler instead of stdout.
+   * @param handler The handler, or null to go back to printing messages.
+   */
+  public static void setHandler(Handler handler) {
+    load();
+    setHandlerInternal(handler);
+  }
+
+  /**
+   * Record log messages in WPILib's DataLog (and the driver station console) through DataLogManager.
+   */
+  public static void logToDataLog() {
+    setHandler((level, target, message) -> {
+      DataLogManager.log("[" + Level.fromValue(level) + "] " + target + ": " + message);
+    });
+  }
+
+  /**
+   * Go back to printing log messages to stdout.
+   */
+  public static void logToStdout() {
+    setHandler(null);
+  }
+}
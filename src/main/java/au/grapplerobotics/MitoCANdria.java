@@ -67,6 +67,24 @@ public class MitoCANdria implements MitoCANdriaInterface, AutoCloseable {
   @Override
   public native void setChannelVoltage(int channel, double voltage) throws ConfigurationFailedException;
 
+  @Override
+  public native void setStartupChannelEnabled(int channel, boolean enabled) throws ConfigurationFailedException;
+
+  @Override
+  public native void setStartupChannelVoltage(int channel, double voltage) throws ConfigurationFailedException;
+
+  @Override
+  public native void clearStartupPolicy();
+
+  @Override
+  public native boolean isStartupPolicyVerified();
+
+  @Override
+  public native ChannelEnergy getChannelEnergy(int channel) throws CouldNotGetException;
+
+  @Override
+  public native void resetEnergy();
+
   @Override
   public void close() throws Exception {
     cleanable.clean();
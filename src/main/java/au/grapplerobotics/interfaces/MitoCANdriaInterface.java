@@ -13,6 +13,41 @@ public interface MitoCANdriaInterface {
   int MITOCANDRIA_CHANNEL_5VB = 3;
   int MITOCANDRIA_CHANNEL_ADJ = 4;
 
+  /**
+   * The accumulated power usage of a MitoCANdria channel since the last call to resetEnergy.
+   * Energy is integrated between the timestamps of the status frames seen each time the
+   * MitoCANdria is polled, assuming the current changes linearly in between, so poll it regularly
+   * (e.g. every robot loop) for accurate results.
+   */
+  public static class ChannelEnergy {
+    /**
+     * The charge drawn by the channel, in Amp-Hours.
+     */
+    public double ampHours;
+
+    /**
+     * The energy drawn by the channel, in Watt-Hours.
+     */
+    public double wattHours;
+
+    /**
+     * The highest current observed on the channel, in Amperes.
+     */
+    public double peakCurrent;
+
+    /**
+     * The time-averaged current of the channel, in Amperes.
+     */
+    public double averageCurrent;
+
+    public ChannelEnergy(double ampHours, double wattHours, double peakCurrent, double averageCurrent) {
+      this.ampHours = ampHours;
+      this.wattHours = wattHours;
+      this.peakCurrent = peakCurrent;
+      this.averageCurrent = averageCurrent;
+    }
+  }
+
   /**
    * Get the current consumption of a channel at this point in time.
    * @param channel The channel. Must be one of MitoCANdria.MITOCANDRIA_CHANNEL_*
@@ -69,4 +104,47 @@ public interface MitoCANdriaInterface {
    *         the adjustable rail.
    */
   void setChannelVoltage(int channel, double voltage) throws ConfigurationFailedException;
+
+  /**
+   * Get the accumulated power usage of a channel since the last call to resetEnergy.
+   * @param channel The channel. Must be one of MitoCANdria.MITOCANDRIA_CHANNEL_*
+   * @throws CouldNotGetException Throws when the channel is out-of-bounds (not a valid channel)
+   * @return The accumulated power usage of the given channel.
+   */
+  ChannelEnergy getChannelEnergy(int channel) throws CouldNotGetException;
+
+  /**
+   * Reset the accumulated power usage of all channels, e.g. at the start of a match.
+   */
+  void resetEnergy();
+
+  /**
+   * Set whether a channel should be enabled when the MitoCANdria comes online. The startup policy is
+   * sent as the MitoCANdria is polled, once it is first seen on the bus and again whenever it
+   * reboots, until it is confirmed by the MitoCANdria's status frames.
+   * @param channel The channel. Must be one of MitoCANdria.MITOCANDRIA_CHANNEL_*
+   * @param enabled The desired enabled state of the channel, where true is energised.
+   * @throws ConfigurationFailedException Throws when the channel is out-of-bounds (not a valid channel)
+   */
+  void setStartupChannelEnabled(int channel, boolean enabled) throws ConfigurationFailedException;
+
+  /**
+   * Set the voltage a channel should be set to when the MitoCANdria comes online.
+   * Note: only the adjustable channel can be targetted by this method.
+   * @param channel The channel. Must be one of MitoCANdria.MITOCANDRIA_CHANNEL_*
+   * @param voltage The desired voltage of the channel, in Volts.
+   * @throws ConfigurationFailedException Throws when the channel is out-of-bounds (not a valid channel)
+   */
+  void setStartupChannelVoltage(int channel, double voltage) throws ConfigurationFailedException;
+
+  /**
+   * Clear the startup policy, leaving channels in whatever state the MitoCANdria boots with.
+   */
+  void clearStartupPolicy();
+
+  /**
+   * Check whether the startup policy has been applied and confirmed by the MitoCANdria.
+   * @return true if the startup policy is in effect (or there is no startup policy).
+   */
+  boolean isStartupPolicyVerified();
 }
\ No newline at end of file
@@ -16,6 +16,7 @@ public class MockMitoCANdria implements MitoCANdriaInterface {
   private OptionalDouble _channelVoltage[] = new OptionalDouble[5];
   private OptionalDouble _channelVoltageSetpoint[] = new OptionalDouble[5];
   private OptionalInt _channelEnabled[] = new OptionalInt[5];
+  private ChannelEnergy _channelEnergy[] = new ChannelEnergy[5];
 
   /**
    * Create a new (mock) MitoCANdria.
@@ -26,6 +27,7 @@ public class MockMitoCANdria implements MitoCANdriaInterface {
       _channelVoltage[i] = OptionalDouble.empty();
       _channelVoltageSetpoint[i] = OptionalDouble.of(5.0);
       _channelEnabled[i] = OptionalInt.empty();
+      _channelEnergy[i] = new ChannelEnergy(0, 0, 0, 0);
     }
     _channelVoltageSetpoint[MITOCANDRIA_CHANNEL_ADJ] = OptionalDouble.empty();
   }
@@ -107,6 +109,66 @@ public class MockMitoCANdria implements MitoCANdriaInterface {
     _channelVoltageSetpoint[channel] = OptionalDouble.of(voltage);
   }
 
+  /**
+   * Set whether a channel should be enabled when the MitoCANdria comes online. In simulation, this
+   * takes effect immediately.
+   * @param channel The channel. Must be one of MitoCANdria.MITOCANDRIA_CHANNEL_*
+   * @param enabled The desired enabled state of the channel, where true is energised.
+   * @throws ConfigurationFailedException Throws when the channel is out-of-bounds (not a valid channel)
+   */
+  @Override
+  public void setStartupChannelEnabled(int channel, boolean enabled) throws ConfigurationFailedException {
+    setChannelEnabled(channel, enabled);
+  }
+
+  /**
+   * Set the voltage a channel should be set to when the MitoCANdria comes online. In simulation, this
+   * takes effect immediately.
+   * @param channel The channel. Must be one of MitoCANdria.MITOCANDRIA_CHANNEL_*
+   * @param voltage The desired voltage of the channel, in Volts.
+   * @throws ConfigurationFailedException Throws when the channel is out-of-bounds (not a valid channel)
+   */
+  @Override
+  public void setStartupChannelVoltage(int channel, double voltage) throws ConfigurationFailedException {
+    setChannelVoltage(channel, voltage);
+  }
+
+  /**
+   * Clear the startup policy, leaving channels in whatever state the MitoCANdria boots with.
+   */
+  @Override
+  public void clearStartupPolicy() { }
+
+  /**
+   * Check whether the startup policy has been applied and confirmed by the MitoCANdria.
+   * @return Always true in simulation.
+   */
+  @Override
+  public boolean isStartupPolicyVerified() {
+    return true;
+  }
+
+  /**
+   * Get the accumulated power usage of a channel since the last call to resetEnergy.
+   * @param channel The channel. Must be one of MitoCANdria.MITOCANDRIA_CHANNEL_*
+   * @throws CouldNotGetException Throws when the channel is out-of-bounds (not a valid channel)
+   * @return The accumulated power usage of the given channel.
+   */
+  @Override
+  public ChannelEnergy getChannelEnergy(int channel) throws CouldNotGetException {
+    return _channelEnergy[channel];
+  }
+
+  /**
+   * Reset the accumulated power usage of all channels, e.g. at the start of a match.
+   */
+  @Override
+  public void resetEnergy() {
+    for (int i = 0; i < 5; i++) {
+      _channelEnergy[i] = new ChannelEnergy(0, 0, 0, 0);
+    }
+  }
+
   /**
    * Set the channel current in simulation mode
    */
@@ -120,4 +182,11 @@ public class MockMitoCANdria implements MitoCANdriaInterface {
   public void setChannelVoltageSim(int channel, double voltage) {
     _channelVoltage[channel] = OptionalDouble.of(voltage);
   }
+
+  /**
+   * Set the accumulated channel energy in simulation mode.
+  */
+  public void setChannelEnergySim(int channel, ChannelEnergy energy) {
+    _channelEnergy[channel] = energy;
+  }
 }
\ No newline at end of file
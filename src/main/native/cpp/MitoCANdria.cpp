@@ -61,3 +61,27 @@ grpl::expected<grpl::empty, GrappleError> MitoCANdria::set_channel_enabled(uint8
 grpl::expected<grpl::empty, GrappleError> MitoCANdria::set_channel_voltage(uint8_t channel, double voltage) {
   return conv_result(ffi::mitocandria_set_channel_voltage(_handle, channel, voltage)._0);
 }
+
+grpl::expected<grpl::empty, GrappleError> MitoCANdria::set_startup_channel_enabled(uint8_t channel, bool enabled) {
+  return conv_result(ffi::mitocandria_set_startup_channel_enabled(_handle, channel, enabled)._0);
+}
+
+grpl::expected<grpl::empty, GrappleError> MitoCANdria::set_startup_channel_voltage(uint8_t channel, double voltage) {
+  return conv_result(ffi::mitocandria_set_startup_channel_voltage(_handle, channel, voltage)._0);
+}
+
+void MitoCANdria::clear_startup_policy() {
+  ffi::mitocandria_clear_startup_policy(_handle);
+}
+
+bool MitoCANdria::is_startup_policy_verified() const {
+  return ffi::mitocandria_is_startup_policy_verified(_handle);
+}
+
+grpl::expected<MitocandriaChannelEnergy, GrappleError> MitoCANdria::get_channel_energy(uint8_t channel) const {
+  return conv_result(ffi::mitocandria_get_channel_energy(_handle, channel)._0);
+}
+
+void MitoCANdria::reset_energy() {
+  ffi::mitocandria_reset_energy(_handle);
+}
@@ -1,9 +1,151 @@
 #pragma once
 
+#include <memory>
+#include <optional>
+#include <string>
+#include <vector>
+
 #include "libgrapplefrcffi.h"
+#include "grpl/utils.h"
 
 namespace grpl {
+  using BridgeStats = libgrapplefrc::ffi::BridgeStatsSnapshot;
+  /**
+   * Matches a CAN frame if (frame_id & mask) == (id & mask).
+   */
+  using CanFilter = libgrapplefrc::ffi::CanFilter;
+
   inline void start_can_bridge() { libgrapplefrc::ffi::start_can_bridge_c_background(); }
   inline void start_ws_can_bridge(uint32_t port = 0) { libgrapplefrc::ffi::run_ws_can_bridge_c(port); }
   inline void start_ws_can_bridge_in_background(uint32_t port = 0) { libgrapplefrc::ffi::run_ws_can_bridge_in_background_c(port); }
-}
\ No newline at end of file
+
+  /**
+   * Configuration for a CAN bridge. Any field left as 0 (or empty, for the bind address) uses the
+   * default for the bridge being started.
+   */
+  struct CanBridgeConfig {
+    /** The address to listen on, e.g. "10.0.0.2". Defaults to all interfaces. */
+    std::string bind_address;
+    /** Defaults to 8006 for TCP and 7171 for WebSockets. */
+    uint16_t port = 0;
+    uint32_t max_clients = 0;
+    /** The number of CAN frames buffered for each client. */
+    uint32_t session_depth = 0;
+    uint32_t poll_interval_ms = 0;
+    /** If set, clients must provide this token before they can use the bridge. */
+    std::optional<std::string> auth_token;
+    /** Refuse all frames sent by clients, so they can only listen to the bus. */
+    bool read_only = false;
+    /** If not empty, clients may only send frames matching at least one of these filters. */
+    std::vector<CanFilter> transmit_allow_list;
+    /** How often the WebSocket bridge pings each client. Defaults to 5000. */
+    uint32_t keepalive_interval_ms = 0;
+    /** How long the WebSocket bridge waits without hearing from a client before disconnecting it. Defaults to 15000. */
+    uint32_t idle_timeout_ms = 0;
+
+    libgrapplefrc::ffi::CBridgeConfig to_ffi() const {
+      return libgrapplefrc::ffi::CBridgeConfig{
+        .bind_address = bind_address.empty() ? nullptr : bind_address.c_str(),
+        .port = port,
+        .max_clients = max_clients,
+        .session_depth = session_depth,
+        .poll_interval_ms = poll_interval_ms,
+        .auth_token = auth_token.has_value() ? auth_token->c_str() : nullptr,
+        .read_only = read_only,
+        .transmit_allow_list = transmit_allow_list.empty() ? nullptr : transmit_allow_list.data(),
+        .transmit_allow_list_len = transmit_allow_list.size(),
+        .keepalive_interval_ms = keepalive_interval_ms,
+        .idle_timeout_ms = idle_timeout_ms
+      };
+    }
+  };
+
+  /**
+   * A CAN bridge running in the background. Unlike start_can_bridge, the bridge can be stopped,
+   * and will be stopped when this object is destroyed.
+   */
+  class CanBridge {
+   public:
+    /**
+     * Start the TCP bridge (as used by GrappleHook) in the background.
+     */
+    static std::unique_ptr<CanBridge> start_tcp(uint16_t port = 8006) {
+      return std::unique_ptr<CanBridge>(new CanBridge(libgrapplefrc::ffi::can_bridge_start(port)));
+    }
+
+    /**
+     * Start the WebSocket bridge in the background. A port of 0 uses the default (7171).
+     */
+    static std::unique_ptr<CanBridge> start_websocket(uint16_t port = 0) {
+      return std::unique_ptr<CanBridge>(new CanBridge(libgrapplefrc::ffi::ws_can_bridge_start(port)));
+    }
+
+    /**
+     * Start the TCP bridge in the background with the given configuration.
+     * Returns nullptr if the configuration is invalid.
+     */
+    static std::unique_ptr<CanBridge> start_tcp(const CanBridgeConfig &config) {
+      return from_handle(libgrapplefrc::ffi::can_bridge_start_with_config(config.to_ffi()));
+    }
+
+    /**
+     * Start the WebSocket bridge in the background with the given configuration.
+     * Returns nullptr if the configuration is invalid.
+     */
+    static std::unique_ptr<CanBridge> start_websocket(const CanBridgeConfig &config) {
+      return from_handle(libgrapplefrc::ffi::ws_can_bridge_start_with_config(config.to_ffi()));
+    }
+
+    CanBridge(const CanBridge &) = delete;
+    CanBridge &operator=(const CanBridge &) = delete;
+
+    ~CanBridge() {
+      libgrapplefrc::ffi::bridge_handle_free(_handle);
+    }
+
+    /**
+     * Ask the bridge to stop, disconnecting any clients. Use join() to wait for it to finish.
+     */
+    void stop() { libgrapplefrc::ffi::bridge_handle_stop(_handle); }
+
+    /**
+     * Wait for the bridge to finish.
+     */
+    void join() { libgrapplefrc::ffi::bridge_handle_join(_handle); }
+
+    bool is_running() const { return libgrapplefrc::ffi::bridge_handle_is_running(_handle); }
+
+    /**
+     * The error that caused the bridge to stop, if any.
+     */
+    std::optional<GrappleError> last_error() const {
+      auto opt = conv_opt(libgrapplefrc::ffi::bridge_handle_last_error(_handle)._0);
+      if (!opt.has_value()) {
+        return std::nullopt;
+      }
+      GrappleError err{
+        .error_message = std::string(opt.value().message),
+        .error_code = opt.value().code
+      };
+      libgrapplefrc::ffi::free_error(opt.value());
+      return err;
+    }
+
+    /**
+     * The bridge's traffic counters. These remain available after the bridge has stopped.
+     */
+    BridgeStats stats() const { return libgrapplefrc::ffi::bridge_handle_stats(_handle); }
+
+   private:
+    CanBridge(libgrapplefrc::ffi::BridgeHandle *handle) : _handle(handle) {}
+
+    static std::unique_ptr<CanBridge> from_handle(libgrapplefrc::ffi::BridgeHandle *handle) {
+      if (handle == nullptr) {
+        return nullptr;
+      }
+      return std::unique_ptr<CanBridge>(new CanBridge(handle));
+    }
+
+    libgrapplefrc::ffi::BridgeHandle *_handle;
+  };
+}
@@ -0,0 +1,122 @@
+#pragma once
+
+#include <memory>
+#include <optional>
+#include <string>
+#include <vector>
+
+#include "libgrapplefrcffi.h"
+#include "grpl/CanBridge.h"
+#include "grpl/utils.h"
+
+namespace grpl {
+  /**
+   * Which files a CanRecorder writes. Candump recordings are text in the format written by
+   * `candump -L` and read by `canplayer`.
+   */
+  using RecordingFormat = libgrapplefrc::ffi::RecordingFormat;
+
+  /**
+   * Configuration for a CAN recorder. Any field left as 0 (or empty) uses the default, except for
+   * the directory, which is required.
+   */
+  struct CanRecorderConfig {
+    /** The directory to write recordings to, e.g. "/home/lvuser/canlogs". */
+    std::string directory;
+    /** Recordings are named prefix-UNIXTIME-INDEX.grplcan (or .log for candump). Defaults to "grapplecan". */
+    std::string prefix;
+    RecordingFormat format = RecordingFormat::Binary;
+    /** The interface name written in candump recordings. Defaults to "can0". */
+    std::string candump_interface;
+    /** If not empty, only frames matching at least one of these filters are recorded. */
+    std::vector<CanFilter> filters;
+    /** Start a new file once the current one reaches this many bytes. Defaults to 16MiB. */
+    uint64_t max_file_size = 0;
+    /** The most recordings to keep in the directory, including from previous runs. Defaults to 16. */
+    uint32_t max_files = 0;
+    uint32_t session_depth = 0;
+    uint32_t poll_interval_ms = 0;
+    /** How often recordings are written to disk. Defaults to 1000. */
+    uint32_t flush_interval_ms = 0;
+
+    libgrapplefrc::ffi::CRecorderConfig to_ffi() const {
+      return libgrapplefrc::ffi::CRecorderConfig{
+        .directory = directory.c_str(),
+        .prefix = prefix.empty() ? nullptr : prefix.c_str(),
+        .format = format,
+        .candump_interface = candump_interface.empty() ? nullptr : candump_interface.c_str(),
+        .filters = filters.empty() ? nullptr : filters.data(),
+        .filters_len = filters.size(),
+        .max_file_size = max_file_size,
+        .max_files = max_files,
+        .session_depth = session_depth,
+        .poll_interval_ms = poll_interval_ms,
+        .flush_interval_ms = flush_interval_ms
+      };
+    }
+  };
+
+  /**
+   * Records CAN traffic to disk in the background, so device dropouts can be looked into after a
+   * match. The recorder is stopped when this object is destroyed.
+   */
+  class CanRecorder {
+   public:
+    /**
+     * Start recording. Returns nullptr if the configuration is invalid.
+     */
+    static std::unique_ptr<CanRecorder> start(const CanRecorderConfig &config) {
+      auto handle = libgrapplefrc::ffi::can_recorder_start(config.to_ffi());
+      if (handle == nullptr) {
+        return nullptr;
+      }
+      return std::unique_ptr<CanRecorder>(new CanRecorder(handle));
+    }
+
+    CanRecorder(const CanRecorder &) = delete;
+    CanRecorder &operator=(const CanRecorder &) = delete;
+
+    ~CanRecorder() {
+      libgrapplefrc::ffi::bridge_handle_free(_handle);
+    }
+
+    /**
+     * Ask the recorder to stop, writing out any buffered frames. Use join() to wait for it to finish.
+     */
+    void stop() { libgrapplefrc::ffi::bridge_handle_stop(_handle); }
+
+    /**
+     * Wait for the recorder to finish.
+     */
+    void join() { libgrapplefrc::ffi::bridge_handle_join(_handle); }
+
+    bool is_running() const { return libgrapplefrc::ffi::bridge_handle_is_running(_handle); }
+
+    /**
+     * The error that caused the recorder to stop (e.g. the disk filling up), if any.
+     */
+    std::optional<GrappleError> last_error() const {
+      auto opt = conv_opt(libgrapplefrc::ffi::bridge_handle_last_error(_handle)._0);
+      if (!opt.has_value()) {
+        return std::nullopt;
+      }
+      GrappleError err{
+        .error_message = std::string(opt.value().message),
+        .error_code = opt.value().code
+      };
+      libgrapplefrc::ffi::free_error(opt.value());
+      return err;
+    }
+
+    /**
+     * The recorder's counters. frames_out and bytes_out count the frames and bytes written, and
+     * session_overflows counts the times frames were lost because they weren't read in time.
+     */
+    BridgeStats stats() const { return libgrapplefrc::ffi::bridge_handle_stats(_handle); }
+
+   private:
+    CanRecorder(libgrapplefrc::ffi::BridgeHandle *handle) : _handle(handle) {}
+
+    libgrapplefrc::ffi::BridgeHandle *_handle;
+  };
+}
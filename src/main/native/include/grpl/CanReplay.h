@@ -0,0 +1,26 @@
+#pragma once
+
+#include <string>
+
+#include "libgrapplefrcffi.h"
+
+namespace grpl {
+  /**
+   * Replay a recording made by CanRecorder to every Grapple device created from now on, instead
+   * of the CAN bus, so robot code can be run offline against exactly what the devices reported
+   * during a match. Start the replay before creating any devices. Anything sent to the devices is
+   * dropped, so configuration calls will time out.
+   *
+   * path is the first file of the recording; the files the recorder rotated to after it are
+   * replayed too. speed is how much faster than real time to replay, e.g. 1.0 for real time.
+   * Returns false if the recording could not be read.
+   */
+  inline bool start_can_replay(const std::string &path, double speed = 1.0) {
+    return libgrapplefrc::ffi::can_replay_start(path.c_str(), speed);
+  }
+
+  /**
+   * Go back to creating devices on the CAN bus. Devices created during the replay keep replaying it.
+   */
+  inline void stop_can_replay() { libgrapplefrc::ffi::can_replay_stop(); }
+}
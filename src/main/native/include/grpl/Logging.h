@@ -0,0 +1,44 @@
+#pragma once
+
+#include <string>
+
+#include <frc/DataLogManager.h>
+
+#include "libgrapplefrcffi.h"
+
+namespace grpl {
+  /**
+   * How much the driver logs. By default, messages at Info and above are printed to stdout.
+   */
+  using LogLevel = libgrapplefrc::ffi::LogLevel;
+
+  /**
+   * Change how much the driver logs. This can be changed at any time.
+   */
+  inline void set_log_level(LogLevel level) { libgrapplefrc::ffi::grapple_set_log_level(level); }
+
+  inline const char *log_level_name(LogLevel level) {
+    switch (level) {
+      case LogLevel::Error: return "ERROR";
+      case LogLevel::Warn: return "WARN";
+      case LogLevel::Info: return "INFO";
+      case LogLevel::Debug: return "DEBUG";
+      case LogLevel::Trace: return "TRACE";
+      default: return "OFF";
+    }
+  }
+
+  /**
+   * Record log messages in WPILib's DataLog (and the driver station console) through DataLogManager.
+   */
+  inline void log_to_data_log() {
+    libgrapplefrc::ffi::grapple_set_log_callback([](LogLevel level, const char *target, const char *message, void *) {
+      frc::DataLogManager::Log(std::string("[") + log_level_name(level) + "] " + target + ": " + message);
+    }, nullptr);
+  }
+
+  /**
+   * Go back to printing log messages to stdout.
+   */
+  inline void log_to_stdout() { libgrapplefrc::ffi::grapple_set_log_callback(nullptr, nullptr); }
+}
@@ -13,6 +13,14 @@ namespace grpl {
   inline constexpr uint8_t MITOCANDRIA_CHANNEL_5VB = 3;
   inline constexpr uint8_t MITOCANDRIA_CHANNEL_ADJ = 4;
 
+  /**
+   * The accumulated power usage of a MitoCANdria channel since the last call to reset_energy.
+   * Energy is integrated between the timestamps of the status frames seen each time the
+   * MitoCANdria is polled, assuming the current changes linearly in between, so poll it regularly
+   * (e.g. every robot loop) for accurate results.
+   */
+  using MitocandriaChannelEnergy = libgrapplefrc::ffi::MitocandriaChannelEnergy;
+
   /**
    * Base class for the MitoCANdria
    */
@@ -65,6 +73,46 @@ namespace grpl {
      * Will return an error if the channel is out of bounds, or the MitoCANdria could not be configured.
      */
     virtual grpl::expected<grpl::empty, GrappleError> set_channel_voltage(uint8_t channel, double voltage) = 0;
+
+    /**
+     * Set whether a channel should be enabled when the MitoCANdria comes online. The startup policy
+     * is sent as the MitoCANdria is polled, once it is first seen on the bus and again whenever it
+     * reboots, until it is confirmed by the MitoCANdria's status frames.
+     * Channel must be one of grpl::MITOCANDRIA_CHANNEL_*.
+     * Will return an error if the channel is out of bounds.
+     */
+    virtual grpl::expected<grpl::empty, GrappleError> set_startup_channel_enabled(uint8_t channel, bool enabled) = 0;
+
+    /**
+     * Set the voltage a channel should be set to when the MitoCANdria comes online.
+     * Note: only the adjustable channel can be targetted by this method.
+     * Channel must be one of grpl::MITOCANDRIA_CHANNEL_*.
+     * Will return an error if the channel is out of bounds.
+     */
+    virtual grpl::expected<grpl::empty, GrappleError> set_startup_channel_voltage(uint8_t channel, double voltage) = 0;
+
+    /**
+     * Clear the startup policy, leaving channels in whatever state the MitoCANdria boots with.
+     */
+    virtual void clear_startup_policy() = 0;
+
+    /**
+     * Returns true if the startup policy has been applied and confirmed by the MitoCANdria,
+     * or if there is no startup policy.
+     */
+    virtual bool is_startup_policy_verified() const = 0;
+
+    /**
+     * Get the accumulated power usage of a channel since the last call to reset_energy.
+     * Channel must be one of grpl::MITOCANDRIA_CHANNEL_*.
+     * Will return an error if the channel is out of bounds.
+     */
+    virtual grpl::expected<MitocandriaChannelEnergy, GrappleError> get_channel_energy(uint8_t channel) const = 0;
+
+    /**
+     * Reset the accumulated power usage of all channels, e.g. at the start of a match.
+     */
+    virtual void reset_energy() = 0;
   };
 
   /**
@@ -87,6 +135,12 @@ namespace grpl {
     std::optional<grpl::expected<double, GrappleError>> get_channel_voltage_setpoint(uint8_t channel) const;
     grpl::expected<grpl::empty, GrappleError> set_channel_enabled(uint8_t channel, bool enabled);
     grpl::expected<grpl::empty, GrappleError> set_channel_voltage(uint8_t channel, double voltage);
+    grpl::expected<grpl::empty, GrappleError> set_startup_channel_enabled(uint8_t channel, bool enabled);
+    grpl::expected<grpl::empty, GrappleError> set_startup_channel_voltage(uint8_t channel, double voltage);
+    void clear_startup_policy();
+    bool is_startup_policy_verified() const;
+    grpl::expected<MitocandriaChannelEnergy, GrappleError> get_channel_energy(uint8_t channel) const;
+    void reset_energy();
 
   private:
     uint8_t _can_id;
@@ -130,10 +184,39 @@ namespace grpl {
       return grpl::empty { 0 };
     }
 
+    grpl::expected<grpl::empty, GrappleError> set_startup_channel_enabled(uint8_t channel, bool enabled) {
+      return set_channel_enabled(channel, enabled);
+    }
+
+    grpl::expected<grpl::empty, GrappleError> set_startup_channel_voltage(uint8_t channel, double voltage) {
+      return set_channel_voltage(channel, voltage);
+    }
+
+    void clear_startup_policy() {}
+
+    bool is_startup_policy_verified() const {
+      return true;
+    }
+
+    grpl::expected<MitocandriaChannelEnergy, GrappleError> get_channel_energy(uint8_t channel) const {
+      return _channelEnergy[channel];
+    }
+
+    void reset_energy() {
+      for (auto &energy : _channelEnergy) {
+        energy = MitocandriaChannelEnergy { 0, 0, 0, 0 };
+      }
+    }
+
+    void set_channel_energy_sim(uint8_t channel, MitocandriaChannelEnergy energy) {
+      _channelEnergy[channel] = energy;
+    }
+
   private:
     double _channelCurrent[5] {0, 0, 0, 0, 0};
     double _channelVoltage[5] {0, 0, 0, 0, 0};
     double _channelVoltageSetpoint[5] {5, 5, 5, 5, 14.5};
     double _channelEnabled[5] {0, 0, 0, 0, 0};
+    MitocandriaChannelEnergy _channelEnergy[5] {};
   };
 }
\ No newline at end of file